@@ -0,0 +1,133 @@
+use std::process::Command;
+use std::time::Duration;
+
+use chrono::SecondsFormat;
+use serde::Deserialize;
+
+use crate::alerting::notifier::Notifier;
+use crate::alerting::AlertEvent;
+use crate::tools::deadline::{self, CancelToken};
+use crate::tools::errors::Error;
+
+/// Runs a local command for every alert. The event is passed in the environment as
+/// XBFISHER_TIME, XBFISHER_RULE, XBFISHER_STATION, XBFISHER_STATE, XBFISHER_SEVERITY, XBFISHER_VALUE and XBFISHER_MESSAGE.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandNotifier {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Seconds after which the command is killed.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl Notifier for CommandNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify(&self, event: &AlertEvent) -> Result<(), Error> {
+        let mut command = Command::new(&self.command);
+        command
+            .args(&self.args)
+            .env("XBFISHER_TIME", event.time.to_rfc3339_opts(SecondsFormat::Secs, false))
+            .env("XBFISHER_RULE", &event.rule)
+            .env("XBFISHER_STATION", event.station_no.to_string())
+            .env("XBFISHER_STATE", event.state.to_string())
+            .env("XBFISHER_SEVERITY", &event.severity)
+            .env("XBFISHER_VALUE", event.value.to_string())
+            .env("XBFISHER_MESSAGE", &event.message);
+        // A command that can't be started or is killed at the timeout fails like one exiting with an error.
+        let output = deadline::output_with_deadline(&mut command, &self.command, Duration::from_secs(self.timeout), &CancelToken::new())
+            .map_err(|error| match error {
+                Error::Timeout { .. } => self.error(error.to_string()),
+                error => self.error(format!("{}: {error}", self.command)),
+            })?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(self.error(format!("{} exited with {}: {}", self.command, output.status, stderr.trim())));
+        }
+        Ok(())
+    }
+}
+
+impl CommandNotifier {
+    fn error(&self, message: String) -> Error {
+        Error::NotifyError { notifier: self.name.clone(), message }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::alerting::AlertState;
+
+    fn event() -> AlertEvent {
+        AlertEvent {
+            time: chrono::DateTime::parse_from_rfc3339("2024-10-27T02:30:00+01:00").unwrap(),
+            rule: "pi-hot".into(),
+            station_no: 3,
+            state: AlertState::Firing,
+            severity: "warning".into(),
+            value: 80.5,
+            message: "Station 3 cpu_temperature is 80.5 C, > 75 C".into(),
+            notifiers: vec![],
+        }
+    }
+
+    fn script(script: &str, timeout: u64) -> CommandNotifier {
+        CommandNotifier { name: "on-call".into(), command: "sh".into(), args: vec!["-c".into(), script.into()], timeout }
+    }
+
+    #[test]
+    fn passes_the_event_in_the_environment() {
+        let path = std::env::temp_dir().join(format!("xbfisher-command-environment-{}.txt", std::process::id()));
+        let notifier = CommandNotifier { args: vec!["-c".into(), "env | grep ^XBFISHER_ | sort > \"$0\"".into(), path.display().to_string()], ..script("", 10) };
+        notifier.notify(&event()).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(text.lines().collect::<Vec<&str>>(), [
+            "XBFISHER_MESSAGE=Station 3 cpu_temperature is 80.5 C, > 75 C",
+            "XBFISHER_RULE=pi-hot",
+            "XBFISHER_SEVERITY=warning",
+            "XBFISHER_STATE=firing",
+            "XBFISHER_STATION=3",
+            "XBFISHER_TIME=2024-10-27T02:30:00+01:00",
+            "XBFISHER_VALUE=80.5",
+        ]);
+    }
+
+    #[test]
+    fn a_failing_command_reports_its_stderr() {
+        let result = script("echo 'no route to pager' >&2; exit 2", 10).notify(&event());
+        let Err(Error::NotifyError { notifier, message }) = result else {
+            panic!("expected a notify error, got {result:?}");
+        };
+        assert_eq!(notifier, "on-call");
+        assert!(message.contains("no route to pager") && message.contains('2'), "{message}");
+    }
+
+    #[test]
+    fn a_command_that_cannot_start_is_a_notify_error() {
+        let notifier = CommandNotifier { command: "/nonexistent/xbfisher-notify".into(), ..script("", 10) };
+        let result = notifier.notify(&event());
+        assert!(matches!(&result, Err(Error::NotifyError { notifier, message }) if notifier == "on-call" && message.contains("/nonexistent/xbfisher-notify")), "{result:?}");
+    }
+
+    #[test]
+    fn kills_the_command_at_the_timeout() {
+        let time_start = Instant::now();
+        let result = script("sleep 30", 1).notify(&event());
+        assert!(time_start.elapsed() < Duration::from_secs(10), "{:?}", time_start.elapsed());
+        assert!(matches!(&result, Err(Error::NotifyError { notifier, message }) if notifier == "on-call" && message == "sh timed out after 1000 ms"), "{result:?}");
+    }
+}
@@ -0,0 +1,124 @@
+pub mod command;
+pub mod notifier;
+pub mod rules;
+pub mod smtp;
+pub mod webhook;
+
+use core::fmt;
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::stations::station::DataRow;
+use crate::tools::time;
+
+use self::rules::{AlertRule, Evaluation, RuleState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+impl fmt::Display for AlertState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AlertState::Firing => "firing",
+            AlertState::Resolved => "resolved",
+        })
+    }
+}
+
+/// A firing or resolved alert of one rule for one station.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AlertEvent {
+    #[serde(rename = "Time", serialize_with = "time::serialize_rfc3339")]
+    pub time: DateTime<FixedOffset>,
+    #[serde(rename = "Rule")]
+    pub rule: String,
+    #[serde(rename = "Station No")]
+    pub station_no: u8,
+    #[serde(rename = "State")]
+    pub state: AlertState,
+    #[serde(rename = "Severity")]
+    pub severity: String,
+    /// The value (or window mean) that triggered the event.
+    #[serde(rename = "Value")]
+    pub value: f64,
+    #[serde(rename = "Message")]
+    pub message: String,
+    /// Notifiers the event is routed to, all if empty.
+    #[serde(skip)]
+    pub notifiers: Vec<String>,
+}
+
+impl fmt::Display for AlertEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: [{}] {} {}: {}", time::rfc3339(&self.time), self.severity, self.rule, self.state, self.message)
+    }
+}
+
+/// Evaluates the alert rules against every gathered DataRow.
+/// Each rule and station pair fires once and stays silent until it resolves (or `renotify` is due), so alerts are deduplicated.
+#[derive(Debug, Default)]
+pub struct AlertEngine {
+    rules: Vec<AlertRule>,
+    states: HashMap<(String, u8), RuleState>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Self { rules, states: HashMap::new() }
+    }
+
+    /// Replaces the rules, keeping the state of rules whose definition did not change.
+    pub fn set_rules(&mut self, rules: Vec<AlertRule>) {
+        self.states.retain(|(name, _), _| rules.iter().any(|rule| &rule.name == name && self.rules.contains(rule)));
+        self.rules = rules;
+    }
+
+    /// Names and stations of the currently firing alerts.
+    pub fn firing(&self) -> Vec<(&str, u8)> {
+        self.states.iter().filter(|(_, state)| state.firing).map(|((name, no), _)| (name.as_str(), *no)).collect()
+    }
+
+    pub fn evaluate_all(&mut self, datavec: &[DataRow], now: DateTime<FixedOffset>) -> Vec<AlertEvent> {
+        datavec.iter().flat_map(|row| self.evaluate(row, now)).collect()
+    }
+
+    pub fn evaluate(&mut self, row: &DataRow, now: DateTime<FixedOffset>) -> Vec<AlertEvent> {
+        let station_no = row.station_no();
+        let mut events = vec![];
+        for rule in self.rules.iter().filter(|rule| rule.applies_to(station_no)) {
+            let state = self.states.entry((rule.name.clone(), station_no)).or_default();
+            let (alert_state, value) = match state.evaluate(rule, rule.metric.value(row), now) {
+                Evaluation::Fire(value) | Evaluation::Renotify(value) => (AlertState::Firing, value),
+                Evaluation::Resolve(value) => (AlertState::Resolved, value),
+                Evaluation::Unchanged => continue,
+            };
+            let mut over = String::new();
+            if let Some(window) = rule.window {
+                over.push_str(&format!(" (mean over {window} s)"));
+            }
+            if rule.for_samples > 1 {
+                over.push_str(&format!(" for {} samples", rule.for_samples));
+            }
+            let message = match alert_state {
+                AlertState::Firing => format!("Station {station_no} {} is {value} {}{over}, {} {} {}", rule.metric, rule.metric.unit(), rule.condition, rule.threshold, rule.metric.unit()),
+                AlertState::Resolved => format!("Station {station_no} {} is back to {value} {}{over}", rule.metric, rule.metric.unit()),
+            };
+            events.push(AlertEvent {
+                time: now,
+                rule: rule.name.clone(),
+                station_no,
+                state: alert_state,
+                severity: rule.severity.clone(),
+                value,
+                message,
+                notifiers: rule.notifiers.clone(),
+            });
+        }
+        events
+    }
+}
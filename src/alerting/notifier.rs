@@ -0,0 +1,335 @@
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::alerting::command::CommandNotifier;
+use crate::alerting::smtp::SmtpNotifier;
+use crate::alerting::webhook::WebhookNotifier;
+use crate::alerting::AlertEvent;
+use crate::tools::errors::Error;
+
+/// A backend alerts are delivered to.
+pub trait Notifier: Send {
+    /// Name used to route alerts to this notifier through `AlertRule::notifiers`.
+    fn name(&self) -> &str;
+
+    fn notify(&self, event: &AlertEvent) -> Result<(), Error>;
+}
+
+/// Prints alerts to stderr (the journal when running under systemd), apart from the results on stdout.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    fn notify(&self, event: &AlertEvent) -> Result<(), Error> {
+        eprintln!("{event}");
+        Ok(())
+    }
+}
+
+/// A notifier from a [[notifier]] table of the config file, selected by its `kind`.
+/// Example:
+/// ```toml
+/// [[notifier]]
+/// kind = "webhook"
+/// name = "ops-chat"
+/// url = "https://chat.example.org/hooks/xyz"
+///
+/// [[notifier]]
+/// kind = "smtp"
+/// name = "ops-mail"
+/// server = "mail.example.org"
+/// from = "xbfisher@example.org"
+/// to = ["ops@example.org"]
+///
+/// [[notifier]]
+/// kind = "command"
+/// name = "pager"
+/// command = "/usr/local/bin/page"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum NotifierConfig {
+    Webhook(WebhookNotifier),
+    Smtp(SmtpNotifier),
+    Command(CommandNotifier),
+}
+
+impl NotifierConfig {
+    pub fn name(&self) -> &str {
+        match self {
+            NotifierConfig::Webhook(notifier) => &notifier.name,
+            NotifierConfig::Smtp(notifier) => &notifier.name,
+            NotifierConfig::Command(notifier) => &notifier.name,
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn Notifier> {
+        match self {
+            NotifierConfig::Webhook(notifier) => Box::new(notifier.clone()),
+            NotifierConfig::Smtp(notifier) => Box::new(notifier.clone()),
+            NotifierConfig::Command(notifier) => Box::new(notifier.clone()),
+        }
+    }
+}
+
+/// Builds the configured notifiers, preceded by the built-in "log" notifier.
+pub fn build_all(configs: &[NotifierConfig]) -> Vec<Box<dyn Notifier>> {
+    let mut notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(LogNotifier)];
+    notifiers.extend(configs.iter().map(NotifierConfig::build));
+    notifiers
+}
+
+/// How often a failed delivery is retried, configured in the [notify] section.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// Seconds before the first retry, doubled for every further one.
+    pub backoff: u64,
+    /// Upper bound of the wait between two attempts in seconds.
+    pub max_backoff: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { retries: 3, backoff: 2, max_backoff: 60 }
+    }
+}
+
+impl RetryPolicy {
+    fn delay(&self, attempt: u32) -> Duration {
+        let secs = self.backoff.saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX));
+        Duration::from_secs(secs.min(self.max_backoff))
+    }
+}
+
+/// Delivers an event to one notifier, retrying with exponential backoff. Returns the last error if every attempt failed.
+pub fn deliver(notifier: &dyn Notifier, event: &AlertEvent, policy: &RetryPolicy) -> Result<(), Error> {
+    let mut attempt = 0;
+    loop {
+        match notifier.notify(event) {
+            Ok(()) => return Ok(()),
+            Err(error) if attempt >= policy.retries => return Err(error),
+            Err(error) => {
+                let delay = policy.delay(attempt);
+                eprintln!("Problem delivering alert {} to notifier {}, retrying in {} s. Error: {error}", event.rule, notifier.name(), delay.as_secs());
+                thread::sleep(delay);
+                attempt += 1;
+            },
+        }
+    }
+}
+
+/// Delivers each event to the notifiers its rule routes to. A failing notifier is reported and does not stop the others.
+pub fn dispatch(notifiers: &[Box<dyn Notifier>], events: &[AlertEvent], policy: &RetryPolicy) {
+    for event in events {
+        for notifier in notifiers.iter().filter(|notifier| event.notifiers.is_empty() || event.notifiers.iter().any(|name| name == notifier.name())) {
+            if let Err(error) = deliver(notifier.as_ref(), event, policy) {
+                eprintln!("Giving up delivering alert {} to notifier {}. Error: {error}", event.rule, notifier.name());
+            }
+        }
+    }
+}
+
+/// Delivers alerts on a background thread, so retrying an unreachable backend doesn't hold up data collection.
+pub struct Dispatcher {
+    sender: Option<Sender<Vec<AlertEvent>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Dispatcher {
+    pub fn spawn(notifiers: Vec<Box<dyn Notifier>>, policy: RetryPolicy) -> Self {
+        let (sender, receiver) = mpsc::channel::<Vec<AlertEvent>>();
+        let worker = thread::spawn(move || {
+            for events in receiver {
+                dispatch(&notifiers, &events, &policy);
+            }
+        });
+        Self { sender: Some(sender), worker: Some(worker) }
+    }
+
+    pub fn send(&self, events: Vec<AlertEvent>) {
+        if events.is_empty() {
+            return;
+        }
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(events);
+        }
+    }
+
+    /// Waits until the queued alerts are delivered (or given up on).
+    pub fn shutdown(mut self) {
+        self.close();
+    }
+
+    fn close(&mut self) {
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::alerting::smtp::SmtpSecurity;
+    use crate::alerting::AlertState;
+    use crate::tools::stand_in;
+
+    fn event() -> AlertEvent {
+        AlertEvent {
+            time: chrono::DateTime::parse_from_rfc3339("2024-10-27T02:30:00+01:00").unwrap(),
+            rule: "pi-hot".into(),
+            station_no: 3,
+            state: AlertState::Firing,
+            severity: "warning".into(),
+            value: 80.5,
+            message: "Station 3 cpu_temperature is 80.5 C, > 75 C".into(),
+            notifiers: vec![],
+        }
+    }
+
+    fn policy(retries: u32, backoff: u64) -> RetryPolicy {
+        RetryPolicy { retries, backoff, max_backoff: 60 }
+    }
+
+    fn webhook(url: String) -> WebhookNotifier {
+        WebhookNotifier { name: "ops-chat".into(), url, timeout: 5 }
+    }
+
+    fn smtp(port: u16) -> SmtpNotifier {
+        SmtpNotifier {
+            name: "ops-mail".into(),
+            server: "127.0.0.1".into(),
+            port: Some(port),
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "xbfisher@example.org".into(),
+            to: vec!["ops@example.org".into()],
+            timeout: 5,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let policy = RetryPolicy { retries: 5, backoff: 2, max_backoff: 10 };
+        let delays: Vec<u64> = (0..5).map(|attempt| policy.delay(attempt).as_secs()).collect();
+        assert_eq!(delays, [2, 4, 8, 10, 10]);
+    }
+
+    #[test]
+    fn webhook_posts_the_event_as_json() {
+        let server = stand_in::http(vec![200]);
+        deliver(&webhook(server.url("/hooks/xyz")), &event(), &policy(0, 0)).unwrap();
+        let requests = server.received();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].target, "/hooks/xyz");
+        assert!(requests[0].header("Content-Type").unwrap().starts_with("application/json"));
+        let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["rule"], "pi-hot");
+        assert_eq!(body["station_no"], 3);
+        assert_eq!(body["state"], "firing");
+        assert_eq!(body["time"], "2024-10-27T02:30:00+01:00");
+        assert_eq!(body["text"], event().to_string());
+    }
+
+    #[test]
+    fn webhook_retries_server_errors() {
+        let server = stand_in::http(vec![503, 502, 200]);
+        deliver(&webhook(server.url("/")), &event(), &policy(3, 0)).unwrap();
+        assert_eq!(server.received().len(), 3);
+    }
+
+    #[test]
+    fn webhook_gives_up_after_the_retry_limit() {
+        let server = stand_in::http(vec![500, 500, 500, 500]);
+        let result = deliver(&webhook(server.url("/")), &event(), &policy(2, 0));
+        assert!(matches!(result, Err(Error::NotifyError { notifier, .. }) if notifier == "ops-chat"));
+        assert_eq!(server.received().len(), 3);
+    }
+
+    #[test]
+    fn webhook_retries_a_refused_connection_after_the_backoff() {
+        let address = stand_in::refused_address();
+        let server = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            stand_in::http_on(TcpListener::bind(address).unwrap(), vec![200])
+        });
+        let start = Instant::now();
+        deliver(&webhook(format!("http://{address}/")), &event(), &policy(3, 1)).unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.join().unwrap().received().len(), 1);
+    }
+
+    #[test]
+    fn webhook_gives_up_on_a_refused_connection() {
+        let result = deliver(&webhook(format!("http://{}/", stand_in::refused_address())), &event(), &policy(1, 0));
+        assert!(matches!(result, Err(Error::NotifyError { .. })));
+    }
+
+    #[test]
+    fn smtp_sends_the_event_as_mail() {
+        let server = stand_in::smtp(0);
+        deliver(&smtp(server.address.port()), &event(), &policy(0, 0)).unwrap();
+        let mails = server.received();
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].contains("Subject: [xbfisher] [warning] pi-hot firing on station 3"));
+        assert!(mails[0].contains("To: ops@example.org"));
+        // The body is quoted-printable, so long lines are wrapped.
+        assert!(mails[0].contains("[warning] pi-hot firing: Station 3"));
+    }
+
+    #[test]
+    fn smtp_retries_a_temporary_failure() {
+        let server = stand_in::smtp(2);
+        deliver(&smtp(server.address.port()), &event(), &policy(3, 0)).unwrap();
+        assert_eq!(server.received().len(), 1);
+    }
+
+    #[test]
+    fn smtp_gives_up_after_the_retry_limit() {
+        let server = stand_in::smtp(usize::MAX);
+        let result = deliver(&smtp(server.address.port()), &event(), &policy(2, 0));
+        assert!(matches!(result, Err(Error::NotifyError { notifier, .. }) if notifier == "ops-mail"));
+        assert!(server.received().is_empty());
+    }
+
+    #[test]
+    fn smtp_gives_up_on_a_refused_connection() {
+        let result = deliver(&smtp(stand_in::refused_address().port()), &event(), &policy(1, 0));
+        assert!(matches!(result, Err(Error::NotifyError { .. })));
+    }
+
+    #[test]
+    fn dispatcher_routes_events_to_their_notifiers() {
+        let (chat, pager) = (stand_in::http(vec![200]), stand_in::http(vec![200]));
+        let notifiers: Vec<Box<dyn Notifier>> = vec![
+            Box::new(webhook(chat.url("/"))),
+            Box::new(WebhookNotifier { name: "pager".into(), ..webhook(pager.url("/")) }),
+        ];
+        let dispatcher = Dispatcher::spawn(notifiers, policy(0, 0));
+        dispatcher.send(vec![AlertEvent { notifiers: vec!["pager".into()], ..event() }]);
+        dispatcher.shutdown();
+        assert!(chat.received().is_empty());
+        assert_eq!(pager.received().len(), 1);
+    }
+}
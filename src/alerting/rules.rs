@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::stations::station::{DataRow, MetricStatus};
+
+/// Metric of a DataRow an alert rule looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Latency,
+    PacketLoss,
+    CpuTemperature,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 3] = [Metric::Latency, Metric::PacketLoss, Metric::CpuTemperature];
+
+    pub fn value(&self, row: &DataRow) -> Option<f64> {
+        match self {
+            Metric::Latency => row.latency(),
+            Metric::PacketLoss => row.packet_loss(),
+            Metric::CpuTemperature => row.cpu_temperature(),
+        }
+    }
+
+    /// Status of the collection step the value comes from.
+    pub fn status(&self, row: &DataRow) -> MetricStatus {
+        match self {
+            Metric::Latency | Metric::PacketLoss => row.latency_status(),
+            Metric::CpuTemperature => row.temperature_status(),
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Metric::Latency => "ms",
+            Metric::PacketLoss => "%",
+            Metric::CpuTemperature => "C",
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Metric::Latency => "latency",
+            Metric::PacketLoss => "packet_loss",
+            Metric::CpuTemperature => "cpu_temperature",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Condition {
+    #[serde(rename = ">")]
+    Above,
+    #[serde(rename = ">=")]
+    AtLeast,
+    #[serde(rename = "<")]
+    Below,
+    #[serde(rename = "<=")]
+    AtMost,
+}
+
+impl Condition {
+    pub fn holds(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Condition::Above => value > threshold,
+            Condition::AtLeast => value >= threshold,
+            Condition::Below => value < threshold,
+            Condition::AtMost => value <= threshold,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Condition::Above => ">",
+            Condition::AtLeast => ">=",
+            Condition::Below => "<",
+            Condition::AtMost => "<=",
+        })
+    }
+}
+
+/// An alert rule from an [[alert]] table of the config file.
+/// Example:
+/// ```toml
+/// [[alert]]
+/// name = "pi-hot"
+/// metric = "cpu_temperature"
+/// condition = ">"
+/// threshold = 75.0
+/// clear_threshold = 70.0
+/// for_samples = 3
+///
+/// [[alert]]
+/// name = "lossy"
+/// metric = "packet_loss"
+/// condition = ">"
+/// threshold = 20.0
+/// window = 600
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub name: String,
+    pub metric: Metric,
+    pub condition: Condition,
+    pub threshold: f64,
+    /// Threshold the value has to cross back over before the alert resolves, defaults to `threshold`.
+    pub clear_threshold: Option<f64>,
+    /// Number of consecutive samples that have to meet the condition before the alert fires, and fail it before it resolves.
+    #[serde(default = "default_for_samples")]
+    pub for_samples: u32,
+    /// If set, the condition is checked against the mean of the samples of the last `window` seconds instead,
+    /// which then has to meet it for `for_samples` consecutive samples.
+    pub window: Option<u64>,
+    /// Stations the rule applies to, all stations if empty.
+    #[serde(default)]
+    pub stations: Vec<u8>,
+    /// Names of the notifiers the alert is delivered to, all notifiers if empty.
+    #[serde(default)]
+    pub notifiers: Vec<String>,
+    #[serde(default = "default_severity")]
+    pub severity: String,
+    /// Seconds after which a still firing alert is sent again, never if unset.
+    pub renotify: Option<u64>,
+}
+
+fn default_for_samples() -> u32 {
+    1
+}
+
+fn default_severity() -> String {
+    "warning".into()
+}
+
+impl AlertRule {
+    pub fn applies_to(&self, station_no: u8) -> bool {
+        self.stations.is_empty() || self.stations.contains(&station_no)
+    }
+
+    fn clear_threshold(&self) -> f64 {
+        self.clear_threshold.unwrap_or(self.threshold)
+    }
+}
+
+/// What an evaluation step decided for one rule and station.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Evaluation {
+    Fire(f64),
+    Resolve(f64),
+    Renotify(f64),
+    Unchanged,
+}
+
+/// Evaluation state of one rule for one station.
+#[derive(Debug, Clone, Default)]
+pub struct RuleState {
+    pub firing: bool,
+    /// Consecutive samples pointing away from the current state.
+    streak: u32,
+    window: VecDeque<(DateTime<FixedOffset>, f64)>,
+    last_sent: Option<DateTime<FixedOffset>>,
+}
+
+impl RuleState {
+    /// Feeds a sample value into the rule. Missing values neither extend nor break a streak.
+    pub fn evaluate(&mut self, rule: &AlertRule, value: Option<f64>, now: DateTime<FixedOffset>) -> Evaluation {
+        let Some(value) = value else {
+            return Evaluation::Unchanged;
+        };
+        let observed = match rule.window {
+            Some(window) => {
+                self.window.push_back((now, value));
+                let cutoff = now - chrono::Duration::seconds(window as i64);
+                while self.window.front().is_some_and(|(time, _)| *time < cutoff) {
+                    self.window.pop_front();
+                }
+                self.window.iter().map(|(_, value)| value).sum::<f64>() / self.window.len() as f64
+            },
+            None => value,
+        };
+        // Firing uses the threshold, resolving the clear threshold, which gives the hysteresis.
+        let flipping = if self.firing {
+            !rule.condition.holds(observed, rule.clear_threshold())
+        } else {
+            rule.condition.holds(observed, rule.threshold)
+        };
+        self.streak = if flipping { self.streak + 1 } else { 0 };
+        let needed = rule.for_samples.max(1);
+        if self.streak >= needed {
+            self.streak = 0;
+            self.firing = !self.firing;
+            self.last_sent = Some(now);
+            return if self.firing { Evaluation::Fire(observed) } else { Evaluation::Resolve(observed) };
+        }
+        if let (true, Some(renotify), Some(last_sent)) = (self.firing, rule.renotify, self.last_sent) {
+            if now - last_sent >= chrono::Duration::seconds(renotify as i64) {
+                self.last_sent = Some(now);
+                return Evaluation::Renotify(observed);
+            }
+        }
+        Evaluation::Unchanged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(for_samples: u32, window: Option<u64>) -> AlertRule {
+        AlertRule {
+            name: "pi-hot".into(),
+            metric: Metric::CpuTemperature,
+            condition: Condition::Above,
+            threshold: 75.0,
+            clear_threshold: Some(70.0),
+            for_samples,
+            window,
+            stations: vec![],
+            notifiers: vec![],
+            severity: default_severity(),
+            renotify: None,
+        }
+    }
+
+    fn at(secs: i64) -> DateTime<FixedOffset> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap().fixed_offset()
+    }
+
+    /// Feeds the values one a minute and returns what each step decided.
+    fn run(rule: &AlertRule, values: &[Option<f64>]) -> Vec<Evaluation> {
+        let mut state = RuleState::default();
+        values.iter().enumerate().map(|(i, value)| state.evaluate(rule, *value, at(i as i64 * 60))).collect()
+    }
+
+    #[test]
+    fn fires_holds_and_resolves_at_the_thresholds() {
+        let steps = run(&rule(1, None), &[Some(75.0), Some(75.1), Some(72.0), Some(70.1), Some(70.0), Some(75.0)]);
+        assert_eq!(steps, [
+            Evaluation::Unchanged,
+            Evaluation::Fire(75.1),
+            // Below the threshold but above the clear threshold, so still firing.
+            Evaluation::Unchanged,
+            Evaluation::Unchanged,
+            // No longer above the clear threshold.
+            Evaluation::Resolve(70.0),
+            Evaluation::Unchanged,
+        ]);
+    }
+
+    #[test]
+    fn for_samples_needs_consecutive_samples_both_ways() {
+        let steps = run(&rule(3, None), &[Some(80.0), Some(80.0), Some(60.0), Some(80.0), Some(80.0), Some(80.0), Some(60.0), Some(60.0), Some(80.0), Some(60.0), Some(60.0), Some(60.0)]);
+        assert_eq!(steps, [
+            Evaluation::Unchanged,
+            Evaluation::Unchanged,
+            Evaluation::Unchanged,
+            Evaluation::Unchanged,
+            Evaluation::Unchanged,
+            Evaluation::Fire(80.0),
+            Evaluation::Unchanged,
+            Evaluation::Unchanged,
+            Evaluation::Unchanged,
+            Evaluation::Unchanged,
+            Evaluation::Unchanged,
+            Evaluation::Resolve(60.0),
+        ]);
+    }
+
+    #[test]
+    fn missing_values_neither_extend_nor_break_a_streak() {
+        let steps = run(&rule(2, None), &[Some(80.0), None, Some(80.0)]);
+        assert_eq!(steps, [Evaluation::Unchanged, Evaluation::Unchanged, Evaluation::Fire(80.0)]);
+    }
+
+    #[test]
+    fn window_mean_is_held_for_for_samples() {
+        // Means over 3 minutes: 60, 70, 80, 85, 90.
+        let steps = run(&rule(2, Some(120)), &[Some(60.0), Some(80.0), Some(100.0), Some(75.0), Some(95.0)]);
+        assert_eq!(steps[..3], [Evaluation::Unchanged, Evaluation::Unchanged, Evaluation::Unchanged]);
+        assert_eq!(steps[3], Evaluation::Fire(85.0));
+        assert_eq!(steps[4], Evaluation::Unchanged);
+    }
+
+    #[test]
+    fn renotifies_while_firing() {
+        let rule = AlertRule { renotify: Some(120), ..rule(1, None) };
+        let steps = run(&rule, &[Some(80.0), Some(80.0), Some(80.0), Some(80.0)]);
+        assert_eq!(steps, [Evaluation::Fire(80.0), Evaluation::Unchanged, Evaluation::Renotify(80.0), Evaluation::Unchanged]);
+    }
+}
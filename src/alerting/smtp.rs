@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::Tls;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::Deserialize;
+
+use crate::alerting::notifier::Notifier;
+use crate::alerting::AlertEvent;
+use crate::tools::errors::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain connection, e.g. to a relay on localhost.
+    None,
+    /// Upgrade with STARTTLS, usually on port 587.
+    #[default]
+    Starttls,
+    /// Implicit TLS, usually on port 465.
+    Tls,
+}
+
+/// Sends alerts as plain text mails.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpNotifier {
+    pub name: String,
+    pub server: String,
+    /// Defaults to the port belonging to `security`.
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Connection timeout in seconds.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl SmtpNotifier {
+    fn error(&self, message: impl ToString) -> Error {
+        Error::NotifyError { notifier: self.name.clone(), message: message.to_string() }
+    }
+
+    fn transport(&self) -> Result<SmtpTransport, Error> {
+        let mut builder = match self.security {
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&self.server).tls(Tls::None),
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&self.server).map_err(|error| self.error(error))?,
+            SmtpSecurity::Tls => SmtpTransport::relay(&self.server).map_err(|error| self.error(error))?,
+        };
+        if let Some(port) = self.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(builder.timeout(Some(Duration::from_secs(self.timeout))).build())
+    }
+}
+
+impl Notifier for SmtpNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify(&self, event: &AlertEvent) -> Result<(), Error> {
+        let mut message = Message::builder()
+            .from(self.from.parse::<Mailbox>().map_err(|error| self.error(format!("invalid from address \"{}\": {error}", self.from)))?)
+            .subject(format!("[xbfisher] [{}] {} {} on station {}", event.severity, event.rule, event.state, event.station_no));
+        for to in &self.to {
+            message = message.to(to.parse::<Mailbox>().map_err(|error| self.error(format!("invalid to address \"{to}\": {error}")))?);
+        }
+        let message = message.body(format!("{event}\n")).map_err(|error| self.error(error))?;
+        self.transport()?.send(&message).map_err(|error| self.error(error))?;
+        Ok(())
+    }
+}
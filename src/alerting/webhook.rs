@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use chrono::SecondsFormat;
+use serde::Deserialize;
+
+use crate::alerting::notifier::Notifier;
+use crate::alerting::AlertEvent;
+use crate::tools::errors::Error;
+
+/// Posts alerts as JSON to an HTTP(S) endpoint.
+/// The "text" field makes the payload a valid Slack or Mattermost incoming webhook message, the other fields carry the event for generic receivers.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookNotifier {
+    pub name: String,
+    pub url: String,
+    /// Request timeout in seconds.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify(&self, event: &AlertEvent) -> Result<(), Error> {
+        let payload = serde_json::json!({
+            "text": event.to_string(),
+            "time": event.time.to_rfc3339_opts(SecondsFormat::Secs, false),
+            "rule": event.rule,
+            "station_no": event.station_no,
+            "state": event.state,
+            "severity": event.severity,
+            "value": event.value,
+            "message": event.message,
+        });
+        ureq::post(&self.url)
+            .timeout(Duration::from_secs(self.timeout))
+            .send_json(payload)
+            .map_err(|error| Error::NotifyError { notifier: self.name.clone(), message: error.to_string() })?;
+        Ok(())
+    }
+}
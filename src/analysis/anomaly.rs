@@ -0,0 +1,510 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use chrono::{DateTime, FixedOffset, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::alerting::rules::Metric;
+use crate::alerting::{AlertEvent, AlertState};
+use crate::analysis::stats::StationStats;
+use crate::stations::station::DataRow;
+use crate::storage::reader::Sample;
+use crate::tools::math::{self, Ewma, RunningStats};
+use crate::tools::time;
+
+/// An anomaly or trend resolves once its score drops below this share of the limit.
+const HYSTERESIS: f64 = 0.8;
+
+/// Baselines, deviation and trend detection, configured in the [anomaly] section of the config file.
+/// Example:
+/// ```toml
+/// [anomaly]
+/// enabled = true
+/// metrics = ["latency", "cpu_temperature"]
+/// z_score = 3.5
+/// alpha = 0.3
+///
+/// [anomaly.trend]
+/// cpu_temperature = 0.1
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnomalyConfig {
+    /// Whether the daemon sends anomaly and trend events along with the alerts. `stats` shows them regardless.
+    pub enabled: bool,
+    pub metrics: Vec<Metric>,
+    /// Limit of the EWMA control chart, in standard deviations of the baseline.
+    pub z_score: f64,
+    /// Weight of the newest sample in the control chart, 1 flags single outliers.
+    pub alpha: f64,
+    /// Samples a baseline needs before deviations from it are flagged.
+    pub warmup: u64,
+    /// Compare each sample with the samples of the same hour of the day, once that hour has `warmup` samples.
+    pub seasonal: bool,
+    /// Days of logged data the daemon learns the baselines from on start.
+    pub history_days: u32,
+    /// Days the linear trends are fitted over.
+    pub trend_days: u32,
+    pub trend: TrendThresholds,
+    pub severity: String,
+    /// Names of the notifiers the events are delivered to, all notifiers if empty.
+    pub notifiers: Vec<String>,
+}
+
+/// Rise per day from which a metric is flagged as trending, e.g. 0.1 °C a day for a dying fan. Unset metrics are not flagged.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrendThresholds {
+    pub latency: Option<f64>,
+    pub packet_loss: Option<f64>,
+    pub cpu_temperature: Option<f64>,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            metrics: vec![Metric::Latency, Metric::CpuTemperature],
+            z_score: 3.5,
+            alpha: 0.3,
+            warmup: 100,
+            seasonal: true,
+            history_days: 14,
+            trend_days: 7,
+            trend: TrendThresholds::default(),
+            severity: "warning".into(),
+            notifiers: vec![],
+        }
+    }
+}
+
+impl Default for TrendThresholds {
+    fn default() -> Self {
+        Self { latency: None, packet_loss: None, cpu_temperature: Some(0.1) }
+    }
+}
+
+impl TrendThresholds {
+    pub fn of(&self, metric: Metric) -> Option<f64> {
+        match metric {
+            Metric::Latency => self.latency,
+            Metric::PacketLoss => self.packet_loss,
+            Metric::CpuTemperature => self.cpu_temperature,
+        }
+    }
+}
+
+/// A sample deviating from the baseline of its station, as listed by `stats`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Anomaly {
+    #[serde(serialize_with = "time::serialize_rfc3339")]
+    pub time: DateTime<FixedOffset>,
+    pub metric: Metric,
+    pub value: f64,
+    /// Mean of the baseline the value was compared with.
+    pub expected: f64,
+    /// Control chart score in standard deviations of the baseline.
+    pub z: f64,
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {}, expected {} (z = {})", time::rfc3339(&self.time), self.metric, self.value, self.metric.unit(), self.expected, self.z)
+    }
+}
+
+/// Linear fit of a metric over the last `trend_days` of the data.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trend {
+    pub metric: Metric,
+    /// Change per day.
+    pub slope: f64,
+    /// Days covered by the fitted samples.
+    pub days: f64,
+    pub samples: usize,
+    /// Whether the rise reaches the configured threshold.
+    pub flagged: bool,
+}
+
+impl fmt::Display for Trend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:+} {} per day over {} days", self.metric, self.slope, self.metric.unit(), self.days)?;
+        if self.flagged {
+            f.write_str(", rising")?;
+        }
+        Ok(())
+    }
+}
+
+/// Least squares line through the samples of a sliding time window, kept as running sums.
+#[derive(Debug, Clone, Default)]
+struct TrendWindow {
+    /// Samples as (days since `origin`, value).
+    points: VecDeque<(f64, f64)>,
+    origin: Option<DateTime<FixedOffset>>,
+    sum_t: f64,
+    sum_x: f64,
+    sum_tt: f64,
+    sum_tx: f64,
+}
+
+impl TrendWindow {
+    fn push(&mut self, time: DateTime<FixedOffset>, value: f64, days: f64) {
+        let origin = *self.origin.get_or_insert(time);
+        let t = (time - origin).num_milliseconds() as f64 / 86_400_000.0;
+        self.points.push_back((t, value));
+        self.add(t, value, 1.0);
+        while self.points.front().is_some_and(|(first, _)| *first < t - days) {
+            let (first, value) = self.points.pop_front().unwrap_or_default();
+            self.add(first, value, -1.0);
+        }
+    }
+
+    fn add(&mut self, t: f64, x: f64, sign: f64) {
+        self.sum_t += sign * t;
+        self.sum_x += sign * x;
+        self.sum_tt += sign * t * t;
+        self.sum_tx += sign * t * x;
+    }
+
+    /// Days between the first and last sample.
+    fn span(&self) -> f64 {
+        match (self.points.front(), self.points.back()) {
+            (Some(first), Some(last)) => last.0 - first.0,
+            _ => 0.0,
+        }
+    }
+
+    /// Change per day, None with fewer than two distinct times.
+    fn slope(&self) -> Option<f64> {
+        let n = self.points.len() as f64;
+        let denominator = n * self.sum_tt - self.sum_t * self.sum_t;
+        (self.points.len() > 1 && denominator > f64::EPSILON).then(|| (n * self.sum_tx - self.sum_t * self.sum_x) / denominator)
+    }
+}
+
+/// What a sample changed for one station and metric.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    AnomalyFired { expected: f64, z: f64 },
+    AnomalyResolved,
+    TrendFired(f64),
+    TrendResolved(f64),
+}
+
+/// Baselines and detection state of one metric of one station.
+#[derive(Debug, Clone)]
+struct Series {
+    overall: RunningStats,
+    /// Baselines by hour of the day.
+    hourly: Vec<RunningStats>,
+    /// EWMA of the z-scores of the samples.
+    chart: Ewma,
+    trend: TrendWindow,
+    anomaly: bool,
+    rising: bool,
+}
+
+impl Series {
+    fn new(config: &AnomalyConfig) -> Self {
+        Self { overall: RunningStats::new(), hourly: vec![RunningStats::new(); 24], chart: Ewma::new(config.alpha), trend: TrendWindow::default(), anomaly: false, rising: false }
+    }
+
+    /// The baseline a sample of this hour is compared with, None while it is warming up.
+    fn baseline(&self, hour: usize, config: &AnomalyConfig) -> Option<&RunningStats> {
+        match &self.hourly[hour] {
+            hourly if config.seasonal && hourly.count() >= config.warmup => Some(hourly),
+            _ if self.overall.count() >= config.warmup => Some(&self.overall),
+            _ => None,
+        }
+    }
+
+    /// Scores the sample against the baseline, then learns from it.
+    fn observe(&mut self, time: DateTime<FixedOffset>, value: f64, metric: Metric, config: &AnomalyConfig) -> Vec<Change> {
+        let hour = time.hour() as usize;
+        let mut changes = vec![];
+        if let Some(baseline) = self.baseline(hour, config) {
+            let expected = baseline.mean().unwrap_or_default();
+            // A floor keeps a flat baseline from flagging every small change.
+            let deviation = baseline.stddev().unwrap_or_default().max(expected.abs() * 0.01).max(0.001);
+            // The chart starts on target so the first score is damped like the others.
+            if self.chart.mean().is_none() {
+                self.chart.push(0.0);
+            }
+            let smoothed = self.chart.push((value - expected) / deviation).unwrap_or_default();
+            let alpha = self.chart.alpha();
+            let z = smoothed / (alpha / (2.0 - alpha)).sqrt();
+            if !self.anomaly && z.abs() >= config.z_score {
+                self.anomaly = true;
+                changes.push(Change::AnomalyFired { expected, z });
+            } else if self.anomaly && z.abs() < config.z_score * HYSTERESIS {
+                self.anomaly = false;
+                changes.push(Change::AnomalyResolved);
+            }
+        }
+        self.overall.push(value);
+        self.hourly[hour].push(value);
+        self.trend.push(time, value, config.trend_days as f64);
+        if let (Some(threshold), Some(slope)) = (config.trend.of(metric), self.trend.slope()) {
+            let covered = self.trend.span() >= config.trend_days as f64 / 2.0 && self.trend.points.len() as u64 >= config.warmup;
+            if !self.rising && covered && slope >= threshold {
+                self.rising = true;
+                changes.push(Change::TrendFired(slope));
+            } else if self.rising && slope < threshold * HYSTERESIS {
+                self.rising = false;
+                changes.push(Change::TrendResolved(slope));
+            }
+        }
+        changes
+    }
+
+    fn trend(&self, metric: Metric) -> Option<Trend> {
+        let slope = self.trend.slope()?;
+        Some(Trend {
+            metric,
+            slope: math::round(slope, 4),
+            days: math::round(self.trend.span(), 2),
+            samples: self.trend.points.len(),
+            flagged: self.rising,
+        })
+    }
+}
+
+/// Learns per-station baselines from the samples and flags deviations from them with an EWMA control chart,
+/// and rising linear trends. Deviations are scored against the samples of the same hour of the day once there are enough of them.
+#[derive(Debug, Clone)]
+pub struct AnomalyDetector {
+    config: AnomalyConfig,
+    series: HashMap<(u8, Metric), Series>,
+}
+
+impl AnomalyDetector {
+    pub fn new(config: AnomalyConfig) -> Self {
+        Self { config, series: HashMap::new() }
+    }
+
+    pub fn config(&self) -> &AnomalyConfig {
+        &self.config
+    }
+
+    /// Replaces the config. Baselines of the metrics still analyzed are kept.
+    pub fn set_config(&mut self, config: AnomalyConfig) {
+        self.series.retain(|(_, metric), _| config.metrics.contains(metric));
+        self.config = config;
+    }
+
+    /// Forgets stations that are no longer logged.
+    pub fn retain(&mut self, station_nos: &[u8]) {
+        self.series.retain(|(station_no, _), _| station_nos.contains(station_no));
+    }
+
+    /// Learns from logged samples, sorted by time, without raising events.
+    pub fn learn(&mut self, samples: &[Sample]) {
+        for sample in samples {
+            self.observe_sample(sample);
+        }
+    }
+
+    /// Feeds a gathered DataRow and returns the anomaly and trend events it causes, in the form of alert events.
+    pub fn observe(&mut self, row: &DataRow, now: DateTime<FixedOffset>) -> Vec<AlertEvent> {
+        let station_no = row.station_no();
+        let mut events = vec![];
+        for metric in self.config.metrics.clone() {
+            let Some(value) = metric.value(row) else {
+                continue;
+            };
+            for change in series(&mut self.series, &self.config, station_no, metric).observe(row.time(), value, metric, &self.config) {
+                events.push(self.event(station_no, metric, value, change, now));
+            }
+        }
+        events
+    }
+
+    pub fn observe_all(&mut self, datavec: &[DataRow], now: DateTime<FixedOffset>) -> Vec<AlertEvent> {
+        datavec.iter().flat_map(|row| self.observe(row, now)).collect()
+    }
+
+    /// Feeds a logged sample, returning the anomalies it starts.
+    fn observe_sample(&mut self, sample: &Sample) -> Vec<Anomaly> {
+        let mut anomalies = vec![];
+        for metric in self.config.metrics.clone() {
+            let Some(value) = sample_value(sample, metric) else {
+                continue;
+            };
+            for change in series(&mut self.series, &self.config, sample.station_no, metric).observe(sample.time, value, metric, &self.config) {
+                if let Change::AnomalyFired { expected, z } = change {
+                    anomalies.push(Anomaly { time: sample.time, metric, value, expected: math::round(expected, 3), z: math::round(z, 2) });
+                }
+            }
+        }
+        anomalies
+    }
+
+    /// The current trends of the station.
+    pub fn trends(&self, station_no: u8) -> Vec<Trend> {
+        Metric::ALL.iter().filter_map(|metric| self.series.get(&(station_no, *metric))?.trend(*metric)).collect()
+    }
+
+    fn event(&self, station_no: u8, metric: Metric, value: f64, change: Change, now: DateTime<FixedOffset>) -> AlertEvent {
+        let unit = metric.unit();
+        let (rule, state, message) = match change {
+            Change::AnomalyFired { expected, z } => (format!("anomaly-{metric}"), AlertState::Firing,
+                format!("Station {station_no} {metric} is {value} {unit}, deviating from its baseline of {} {unit} (z = {})", math::round(expected, 2), math::round(z, 2))),
+            Change::AnomalyResolved => (format!("anomaly-{metric}"), AlertState::Resolved, format!("Station {station_no} {metric} is back to its baseline at {value} {unit}")),
+            Change::TrendFired(slope) => (format!("trend-{metric}"), AlertState::Firing,
+                format!("Station {station_no} {metric} is rising by {} {unit} per day over the last {} days", math::round(slope, 3), self.config.trend_days)),
+            Change::TrendResolved(slope) => (format!("trend-{metric}"), AlertState::Resolved, format!("Station {station_no} {metric} stopped rising, {} {unit} per day", math::round(slope, 3))),
+        };
+        AlertEvent { time: now, rule, station_no, state, severity: self.config.severity.clone(), value, message, notifiers: self.config.notifiers.clone() }
+    }
+}
+
+fn series<'a>(series: &'a mut HashMap<(u8, Metric), Series>, config: &AnomalyConfig, station_no: u8, metric: Metric) -> &'a mut Series {
+    series.entry((station_no, metric)).or_insert_with(|| Series::new(config))
+}
+
+fn sample_value(sample: &Sample, metric: Metric) -> Option<f64> {
+    match metric {
+        Metric::Latency => sample.latency,
+        Metric::PacketLoss => sample.packet_loss,
+        Metric::CpuTemperature => sample.cpu_temperature,
+    }
+}
+
+/// Replays the samples, sorted by time, through a detector and adds the anomalies and final trends of every station to its stats.
+pub fn annotate(stations: &mut [StationStats], samples: &[Sample], config: &AnomalyConfig) {
+    let mut detector = AnomalyDetector::new(config.clone());
+    for sample in samples {
+        let anomalies = detector.observe_sample(sample);
+        if let Some(stats) = stations.iter_mut().find(|stats| stats.station_no == sample.station_no) {
+            stats.anomalies.extend(anomalies);
+        }
+    }
+    for stats in stations {
+        stats.trends = detector.trends(stats.station_no);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(warmup: u64) -> AnomalyConfig {
+        AnomalyConfig { warmup, seasonal: false, trend: TrendThresholds { latency: None, packet_loss: None, cpu_temperature: None }, ..AnomalyConfig::default() }
+    }
+
+    /// One sample a minute, or one an hour for the trends.
+    fn at(minutes: i64) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339("2024-10-27T00:00:00+00:00").unwrap() + chrono::Duration::minutes(minutes)
+    }
+
+    /// The control chart score of the last sample, as the series compares it with the limit.
+    fn score(series: &Series) -> f64 {
+        let alpha = series.chart.alpha();
+        series.chart.mean().unwrap_or_default() / (alpha / (2.0 - alpha)).sqrt()
+    }
+
+    #[test]
+    fn a_step_fires_and_resolves_with_hysteresis() {
+        let config = AnomalyConfig { alpha: 0.1, ..config(20) };
+        let mut series = Series::new(&config);
+        let values: Vec<f64> = (0..60).map(|i| if i % 2 == 0 { 10.5 } else { 9.5 })
+            .chain([20.0; 5])
+            .chain((0..60).map(|i| if i % 2 == 0 { 10.5 } else { 9.5 }))
+            .collect();
+        let (mut fired, mut resolved, mut held_in_band) = (None, None, false);
+        for (i, value) in values.iter().enumerate() {
+            let changes = series.observe(at(i as i64), *value, Metric::Latency, &config);
+            let z = score(&series).abs();
+            match changes[..] {
+                [Change::AnomalyFired { expected, z: fired_z }] => {
+                    assert!(fired.is_none() && z >= config.z_score, "sample {i}: fired at z = {z}");
+                    assert!((expected - 10.0).abs() < 0.1 && (fired_z.abs() - z).abs() < 1e-9, "expected {expected}, z {fired_z}");
+                    fired = Some(i);
+                },
+                [Change::AnomalyResolved] => {
+                    assert!(fired.is_some() && resolved.is_none() && z < config.z_score * HYSTERESIS, "sample {i}: resolved at z = {z}");
+                    resolved = Some(i);
+                },
+                [] if fired.is_some() && resolved.is_none() => {
+                    assert!(z >= config.z_score * HYSTERESIS, "sample {i}: still firing at z = {z}");
+                    held_in_band |= z < config.z_score;
+                },
+                [] => assert!(fired.is_some() || z < config.z_score, "sample {i}: no anomaly at z = {z}"),
+                _ => panic!("sample {i}: unexpected changes {changes:?}"),
+            }
+        }
+        assert_eq!(fired, Some(60));
+        assert!(resolved.is_some_and(|resolved| resolved > 65), "resolved at {resolved:?}");
+        assert!(held_in_band, "the score never went between the resolve and the fire limit");
+    }
+
+    #[test]
+    fn nothing_fires_during_the_warmup() {
+        let config = config(20);
+        let mut series = Series::new(&config);
+        for i in 0..19 {
+            let value = if i == 10 { 1000.0 } else { 10.0 };
+            assert_eq!(series.observe(at(i), value, Metric::Latency, &config), [], "sample {i}");
+        }
+        assert!(series.chart.mean().is_none());
+        assert_eq!(series.observe(at(19), 10.0, Metric::Latency, &config), []);
+        assert!(matches!(series.observe(at(20), 100_000.0, Metric::Latency, &config)[..], [Change::AnomalyFired { .. }]));
+    }
+
+    #[test]
+    fn seasonal_baselines_wait_for_their_hour() {
+        let config = AnomalyConfig { seasonal: true, ..config(5) };
+        let mut series = Series::new(&config);
+        // Five samples in hour 0 and five in hour 1, far apart.
+        for i in 0..5 {
+            series.observe(at(i), 10.0, Metric::Latency, &config);
+            series.observe(at(60 + i), 50.0, Metric::Latency, &config);
+        }
+        assert!(std::ptr::eq(series.baseline(0, &config).unwrap(), &series.hourly[0]));
+        assert!(std::ptr::eq(series.baseline(1, &config).unwrap(), &series.hourly[1]));
+        assert!(std::ptr::eq(series.baseline(2, &config).unwrap(), &series.overall));
+        assert_eq!(series.observe(at(2 * 24 * 60 + 65), 50.0, Metric::Latency, &config), []);
+    }
+
+    #[test]
+    fn a_linear_rise_trips_the_trend() {
+        let config = AnomalyConfig { trend: TrendThresholds { cpu_temperature: Some(0.1), ..config(20).trend }, ..config(20) };
+        let mut series = Series::new(&config);
+        let mut fired = None;
+        // 0.5 °C a day, one sample an hour, for 10 days.
+        for hour in 0..240 {
+            let value = 40.0 + 0.5 * hour as f64 / 24.0;
+            for change in series.observe(at(hour * 60), value, Metric::CpuTemperature, &config) {
+                match change {
+                    Change::TrendFired(slope) => {
+                        assert!(fired.is_none());
+                        assert!((slope - 0.5).abs() < 1e-9, "slope {slope}");
+                        fired = Some(hour);
+                    },
+                    Change::TrendResolved(slope) => panic!("hour {hour}: resolved at {slope}"),
+                    _ => {},
+                }
+            }
+        }
+        // The window has to cover half of the trend days first.
+        assert_eq!(fired, Some(84));
+        let trend = series.trend(Metric::CpuTemperature).unwrap();
+        assert!(trend.flagged && (trend.slope - 0.5).abs() < 1e-3 && trend.days <= 7.0, "{trend:?}");
+        // Leveling off brings the slope of the window down again.
+        let resolved = (240..480).find(|hour| series.observe(at(hour * 60), 45.0, Metric::CpuTemperature, &config).iter().any(|change| matches!(change, Change::TrendResolved(_))));
+        assert!(resolved.is_some());
+        assert!(!series.trend(Metric::CpuTemperature).unwrap().flagged);
+    }
+
+    #[test]
+    fn a_flat_series_never_trends() {
+        let config = AnomalyConfig { trend: TrendThresholds { cpu_temperature: Some(0.1), ..config(20).trend }, ..config(20) };
+        let mut series = Series::new(&config);
+        for hour in 0..240 {
+            let value = if hour % 2 == 0 { 45.5 } else { 44.5 };
+            let changes = series.observe(at(hour * 60), value, Metric::CpuTemperature, &config);
+            assert!(!changes.iter().any(|change| matches!(change, Change::TrendFired(_))), "hour {hour}");
+        }
+    }
+}
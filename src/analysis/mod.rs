@@ -0,0 +1,3 @@
+pub mod anomaly;
+pub mod report;
+pub mod stats;
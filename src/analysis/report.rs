@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::analysis::stats::{Distribution, StationStats, Summary};
+use crate::stations::hosts::HostEntry;
+use crate::storage::reader::Sample;
+use crate::tools::math;
+use crate::tools::time;
+
+/// Width of the charts in SVG user units, they scale with the page.
+const WIDTH: f64 = 960.0;
+/// Room left of the plot area for the axis labels.
+const LEFT: f64 = 56.0;
+const RIGHT: f64 = 12.0;
+const CHART_HEIGHT: f64 = 180.0;
+/// Number of points a time series is averaged down to.
+const SERIES_BUCKETS: usize = 320;
+/// Number of columns of the packet loss heatmap.
+const HEATMAP_COLUMNS: usize = 48;
+const ROW_HEIGHT: f64 = 22.0;
+
+/// Time range covered by the report, as Unix timestamps.
+#[derive(Clone, Copy)]
+struct Span {
+    start: i64,
+    end: i64,
+    offset: FixedOffset,
+}
+
+impl Span {
+    fn new(summary: &Summary) -> Option<Self> {
+        let start = summary.from.or_else(|| summary.stations.iter().map(|stats| stats.first).min())?;
+        let end = summary.to.or_else(|| summary.stations.iter().map(|stats| stats.last).max())?;
+        Some(Self { start: start.timestamp(), end: end.timestamp().max(start.timestamp() + 1), offset: *start.offset() })
+    }
+
+    fn x(&self, timestamp: i64) -> f64 {
+        LEFT + (timestamp - self.start) as f64 / (self.end - self.start) as f64 * (WIDTH - LEFT - RIGHT)
+    }
+
+    /// Index of the bucket a timestamp falls into when the span is cut into `buckets` equal parts.
+    fn bucket(&self, timestamp: i64, buckets: usize) -> Option<usize> {
+        let bucket = (timestamp - self.start) as f64 / (self.end - self.start) as f64 * buckets as f64;
+        (0.0..=buckets as f64).contains(&bucket).then(|| (bucket as usize).min(buckets - 1))
+    }
+
+    fn bucket_start(&self, bucket: usize, buckets: usize) -> i64 {
+        self.start + (self.end - self.start) * bucket as i64 / buckets as i64
+    }
+
+    fn label(&self, timestamp: i64) -> String {
+        DateTime::from_timestamp(timestamp, 0)
+            .map(|time| time.with_timezone(&self.offset).format("%m-%d %H:%M").to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// The names of the stations of the hosts file in the report: their host names, or their addresses without one.
+/// Stations without a name are shown as "Station N" only.
+pub fn station_names(entries: &[HostEntry]) -> BTreeMap<u8, String> {
+    entries.iter().filter_map(|entry| {
+        let name = entry.hostname.as_deref().unwrap_or(&entry.ip_address);
+        (!name.is_empty()).then(|| (entry.station_no, name.to_string()))
+    }).collect()
+}
+
+/// Renders the report as a single HTML page with inline SVG charts, without scripts or external resources.
+/// `samples` are the samples the summary was made from, `names` the names of the stations, see `station_names`.
+pub fn render(summary: &Summary, samples: &[Sample], names: &BTreeMap<u8, String>, generated: DateTime<FixedOffset>) -> String {
+    let mut html = String::new();
+    let _ = write!(html, "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>XBFisher report</title>\n<style>{STYLE}</style>\n</head>\n<body>\n");
+    let _ = writeln!(html, "<h1>XBFisher report</h1>");
+    let _ = writeln!(html, "<p class=\"meta\">Data from {} to {}, generated {}.</p>",
+        escape(&summary.from.map(|from| time::rfc3339(&from)).unwrap_or_else(|| "the start of the data".into())), escape(&summary.to.map(|to| time::rfc3339(&to)).unwrap_or_else(|| "the end of the data".into())), escape(&time::rfc3339(&generated)));
+    let Some(span) = Span::new(summary) else {
+        let _ = writeln!(html, "<p>No samples found.</p>\n</body>\n</html>");
+        return html;
+    };
+    let mut by_station: BTreeMap<u8, Vec<&Sample>> = BTreeMap::new();
+    for sample in samples.iter().filter(|sample| summary.stations.iter().any(|stats| stats.station_no == sample.station_no)) {
+        by_station.entry(sample.station_no).or_default().push(sample);
+    }
+    let name = |station_no: u8| match names.get(&station_no) {
+        Some(name) => format!("Station {station_no} ({})", escape(name)),
+        None => format!("Station {station_no}"),
+    };
+
+    let _ = writeln!(html, "<h2>Summary</h2>");
+    summary_table(&mut html, summary, names);
+    let _ = writeln!(html, "<h2>Outages</h2>");
+    outage_timeline(&mut html, span, &summary.stations, &name);
+    outage_table(&mut html, &summary.stations, &name);
+    let _ = writeln!(html, "<h2>Packet loss</h2>");
+    loss_heatmap(&mut html, span, &by_station, &name);
+    for stats in &summary.stations {
+        let samples = by_station.get(&stats.station_no).map(Vec::as_slice).unwrap_or_default();
+        let _ = writeln!(html, "<h2>{}</h2>", name(stats.station_no));
+        line_chart(&mut html, span, "Latency (ms)", &series(span, samples, |sample| sample.latency), true);
+        line_chart(&mut html, span, "CPU temperature (C)", &series(span, samples, |sample| sample.cpu_temperature), false);
+    }
+    if summary.skipped > 0 {
+        let _ = writeln!(html, "<p class=\"meta\">{} unreadable records skipped.</p>", summary.skipped);
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 1000px; color: #222; }
+h1 { margin-bottom: 0; }
+h2 { margin-top: 1.6em; border-bottom: 1px solid #ccc; }
+.meta { color: #666; }
+table { border-collapse: collapse; font-size: 0.85em; margin: 0.5em 0; }
+th, td { border: 1px solid #ddd; padding: 0.25em 0.5em; text-align: right; }
+th { background: #f3f3f3; }
+td.text { text-align: left; }
+svg { width: 100%; height: auto; display: block; margin: 0.5em 0; }
+svg text { font-size: 11px; fill: #555; }
+.grid { stroke: #e5e5e5; stroke-width: 1; }
+.line { fill: none; stroke: #1f6fb2; stroke-width: 1.5; stroke-linecap: round; stroke-linejoin: round; }
+.span { fill: #d8efd8; }
+.outage { fill: #d9453b; }
+.empty { fill: #eee; }
+";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn number(value: Option<f64>) -> String {
+    value.map(|value| math::round(value, 2).to_string()).unwrap_or_else(|| "-".into())
+}
+
+fn summary_table(html: &mut String, summary: &Summary, names: &BTreeMap<u8, String>) {
+    html.push_str("<table>\n<tr><th>Station</th><th>Name</th><th>Samples</th><th>Uptime %</th><th>Latency avg</th><th>p50</th><th>p95</th><th>p99</th><th>max</th>\
+        <th>Loss avg %</th><th>Temp min</th><th>avg</th><th>max</th><th>Outages</th><th>Down s</th></tr>\n");
+    for stats in &summary.stations {
+        let field = |distribution: &Option<Distribution>, value: fn(&Distribution) -> f64| number(distribution.as_ref().map(value));
+        let _ = writeln!(html, "<tr><td>{}</td><td class=\"text\">{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            stats.station_no, escape(names.get(&stats.station_no).map(String::as_str).unwrap_or("")), stats.samples, stats.uptime,
+            field(&stats.latency, |d| d.avg), field(&stats.latency, |d| d.p50), field(&stats.latency, |d| d.p95), field(&stats.latency, |d| d.p99), field(&stats.latency, |d| d.max),
+            field(&stats.packet_loss, |d| d.avg),
+            field(&stats.cpu_temperature, |d| d.min), field(&stats.cpu_temperature, |d| d.avg), field(&stats.cpu_temperature, |d| d.max),
+            stats.outages.len(), stats.downtime_s);
+    }
+    html.push_str("</table>\n");
+}
+
+fn outage_table(html: &mut String, stations: &[StationStats], name: &dyn Fn(u8) -> String) {
+    if stations.iter().all(|stats| stats.outages.is_empty()) {
+        html.push_str("<p>No outages.</p>\n");
+        return;
+    }
+    html.push_str("<table>\n<tr><th>Station</th><th>Start</th><th>End</th><th>Duration s</th><th>Samples</th></tr>\n");
+    for stats in stations {
+        for outage in &stats.outages {
+            let end = outage.end.map(|end| time::rfc3339(&end)).unwrap_or_else(|| "ongoing".into());
+            let _ = writeln!(html, "<tr><td class=\"text\">{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                name(stats.station_no), time::rfc3339(&outage.start), end, outage.duration_s, outage.samples);
+        }
+    }
+    html.push_str("</table>\n");
+}
+
+/// Opens an SVG whose plot area is `height` high, with room for the time axis below it.
+fn open_svg(html: &mut String, height: f64) {
+    let _ = writeln!(html, "<svg viewBox=\"0 0 {WIDTH} {}\" xmlns=\"http://www.w3.org/2000/svg\">", height + 20.0);
+}
+
+/// Vertical grid lines with time labels below the plot area.
+fn time_axis(html: &mut String, span: Span, top: f64, bottom: f64) {
+    for tick in 0..=6 {
+        let timestamp = span.start + (span.end - span.start) * tick / 6;
+        let x = span.x(timestamp);
+        let anchor = match tick {
+            0 => "start",
+            6 => "end",
+            _ => "middle",
+        };
+        let _ = writeln!(html, "<line class=\"grid\" x1=\"{x:.1}\" y1=\"{top}\" x2=\"{x:.1}\" y2=\"{bottom}\"/><text x=\"{x:.1}\" y=\"{}\" text-anchor=\"{anchor}\">{}</text>",
+            bottom + 14.0, span.label(timestamp));
+    }
+}
+
+/// Averages a metric over equal parts of the span, None for parts without a value.
+fn series(span: Span, samples: &[&Sample], value: fn(&Sample) -> Option<f64>) -> Vec<Option<f64>> {
+    let mut sums = vec![(0.0, 0usize); SERIES_BUCKETS];
+    for sample in samples {
+        if let (Some(value), Some(bucket)) = (value(sample), span.bucket(sample.time.timestamp(), SERIES_BUCKETS)) {
+            sums[bucket].0 += value;
+            sums[bucket].1 += 1;
+        }
+    }
+    sums.into_iter().map(|(sum, count)| (count > 0).then(|| sum / count as f64)).collect()
+}
+
+/// A line chart of a series from `series`, interrupted where it has no values.
+fn line_chart(html: &mut String, span: Span, title: &str, points: &[Option<f64>], from_zero: bool) {
+    let values: Vec<f64> = points.iter().flatten().copied().collect();
+    if values.is_empty() {
+        let _ = writeln!(html, "<p class=\"meta\">{title}: no values.</p>");
+        return;
+    }
+    let max = values.iter().copied().fold(f64::MIN, f64::max);
+    let min = if from_zero { 0.0 } else { values.iter().copied().fold(f64::MAX, f64::min) };
+    let (min, max) = if max - min < 1e-9 { (min - 1.0, max + 1.0) } else { (min, max + (max - min) * 0.05) };
+    let (top, bottom) = (20.0, CHART_HEIGHT);
+    let y = |value: f64| bottom - (value - min) / (max - min) * (bottom - top);
+    open_svg(html, CHART_HEIGHT);
+    let _ = writeln!(html, "<text x=\"{LEFT}\" y=\"12\">{}</text>", escape(title));
+    for tick in 0..=4 {
+        let value = min + (max - min) * tick as f64 / 4.0;
+        let _ = writeln!(html, "<line class=\"grid\" x1=\"{LEFT}\" y1=\"{0:.1}\" x2=\"{1}\" y2=\"{0:.1}\"/><text x=\"{2}\" y=\"{3:.1}\" text-anchor=\"end\">{4}</text>",
+            y(value), WIDTH - RIGHT, LEFT - 4.0, y(value) + 4.0, math::round(value, 1));
+    }
+    time_axis(html, span, top, bottom);
+    let mut path = String::new();
+    let mut drawing = false;
+    for (bucket, point) in points.iter().enumerate() {
+        match point {
+            Some(value) => {
+                // Each bucket is drawn at its middle; "h0" starts a segment with a dot, so single values stay visible.
+                let x = (span.x(span.bucket_start(bucket, SERIES_BUCKETS)) + span.x(span.bucket_start(bucket + 1, SERIES_BUCKETS))) / 2.0;
+                let _ = write!(path, "{}{x:.1} {:.1}{}", if drawing { "L" } else { "M" }, y(*value), if drawing { "" } else { "h0" });
+                drawing = true;
+            },
+            None => drawing = false,
+        }
+    }
+    let _ = writeln!(html, "<path class=\"line\" d=\"{path}\"/>\n</svg>");
+}
+
+/// One row per station with the span of its data in green and its outages in red.
+fn outage_timeline(html: &mut String, span: Span, stations: &[StationStats], name: &dyn Fn(u8) -> String) {
+    let height = stations.len() as f64 * ROW_HEIGHT;
+    open_svg(html, height);
+    for (row, stats) in stations.iter().enumerate() {
+        let top = row as f64 * ROW_HEIGHT + 3.0;
+        let (first, last) = (span.x(stats.first.timestamp()), span.x(stats.last.timestamp()));
+        let _ = writeln!(html, "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text><rect class=\"span\" x=\"{first:.1}\" y=\"{top:.1}\" width=\"{:.1}\" height=\"{}\"/>",
+            LEFT - 4.0, top + 12.0, stats.station_no, (last - first).max(1.0), ROW_HEIGHT - 6.0);
+        for outage in &stats.outages {
+            let start = span.x(outage.start.timestamp());
+            let end = span.x(outage.end.map(|end| end.timestamp()).unwrap_or(stats.last.timestamp()));
+            let _ = writeln!(html, "<rect class=\"outage\" x=\"{start:.1}\" y=\"{top:.1}\" width=\"{:.1}\" height=\"{}\"><title>{}: {}</title></rect>",
+                (end - start).max(1.5), ROW_HEIGHT - 6.0, name(stats.station_no), escape(&outage.to_string()));
+        }
+    }
+    time_axis(html, span, 0.0, height);
+    html.push_str("</svg>\n");
+}
+
+/// One row per station, one column per part of the span, colored from green (no loss) to red (all packets lost).
+/// Samples without a reply count as full loss.
+fn loss_heatmap(html: &mut String, span: Span, by_station: &BTreeMap<u8, Vec<&Sample>>, name: &dyn Fn(u8) -> String) {
+    let height = by_station.len() as f64 * ROW_HEIGHT;
+    let width = (WIDTH - LEFT - RIGHT) / HEATMAP_COLUMNS as f64;
+    open_svg(html, height);
+    for (row, (station_no, samples)) in by_station.iter().enumerate() {
+        let top = row as f64 * ROW_HEIGHT + 1.0;
+        let loss = |sample: &Sample| sample.packet_loss.or((!sample.reachable()).then_some(100.0));
+        let mut sums = vec![(0.0, 0usize); HEATMAP_COLUMNS];
+        for sample in samples {
+            if let (Some(loss), Some(column)) = (loss(sample), span.bucket(sample.time.timestamp(), HEATMAP_COLUMNS)) {
+                sums[column].0 += loss;
+                sums[column].1 += 1;
+            }
+        }
+        let _ = writeln!(html, "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{station_no}</text>", LEFT - 4.0, top + 14.0);
+        for (column, (sum, count)) in sums.into_iter().enumerate() {
+            let x = LEFT + column as f64 * width;
+            let period = span.label(span.bucket_start(column, HEATMAP_COLUMNS));
+            if count == 0 {
+                let _ = writeln!(html, "<rect class=\"empty\" x=\"{x:.1}\" y=\"{top:.1}\" width=\"{:.1}\" height=\"{}\"><title>{}, {period}: no data</title></rect>",
+                    width - 1.0, ROW_HEIGHT - 2.0, name(*station_no));
+                continue;
+            }
+            let loss = sum / count as f64;
+            let _ = writeln!(html, "<rect fill=\"hsl({:.0},70%,45%)\" x=\"{x:.1}\" y=\"{top:.1}\" width=\"{:.1}\" height=\"{}\"><title>{}, {period}: {}% loss</title></rect>",
+                120.0 * (1.0 - loss / 100.0).clamp(0.0, 1.0), width - 1.0, ROW_HEIGHT - 2.0, name(*station_no), math::round(loss, 1));
+        }
+    }
+    time_axis(html, span, 0.0, height);
+    html.push_str("</svg>\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::stats::{self, Query};
+
+    fn at(minute: i64) -> DateTime<FixedOffset> {
+        DateTime::from_timestamp(1_730_030_400 + minute * 60, 0).unwrap().fixed_offset()
+    }
+
+    fn sample(station_no: u8, minute: i64, latency: Option<f64>) -> Sample {
+        Sample { time: at(minute), station_no, latency, packet_loss: latency.map(|_| 0.0), cpu_temperature: Some(45.0), error: None }
+    }
+
+    fn summary(samples: &[Sample], from: Option<DateTime<FixedOffset>>, to: Option<DateTime<FixedOffset>>) -> Summary {
+        Summary { from, to, skipped: 0, stations: stats::summarize(samples, &Query::default()) }
+    }
+
+    /// Checks that the page is complete and needs nothing from elsewhere.
+    fn assert_self_contained(html: &str) {
+        assert!(html.starts_with("<!DOCTYPE html>\n<html lang=\"en\">"));
+        assert!(html.trim_end().ends_with("</body>\n</html>"));
+        for tag in ["html", "head", "body", "style"] {
+            assert_eq!(html.matches(&format!("<{tag}")).count(), 1, "<{tag}>");
+            assert_eq!(html.matches(&format!("</{tag}>")).count(), 1, "</{tag}>");
+        }
+        for tag in ["svg", "table", "tr", "h2", "text", "title"] {
+            assert_eq!(html.matches(&format!("<{tag}>")).count() + html.matches(&format!("<{tag} ")).count(), html.matches(&format!("</{tag}>")).count(), "<{tag}>");
+        }
+        for external in ["<script", "<link", "<img", "src=", "href=", "url("] {
+            assert!(!html.contains(external), "{external}");
+        }
+    }
+
+    #[test]
+    fn an_empty_range_renders_a_page_without_charts() {
+        let html = render(&summary(&[], Some(at(0)), None), &[], &BTreeMap::new(), at(100));
+        assert_self_contained(&html);
+        assert!(html.contains("<p>No samples found.</p>"));
+        assert!(html.contains(&format!("Data from {} to the end of the data, generated {}.", time::rfc3339(&at(0)), time::rfc3339(&at(100)))));
+        assert!(!html.contains("<svg"));
+        assert!(!html.contains("<h2>"));
+    }
+
+    #[test]
+    fn escapes_the_station_names() {
+        let samples = [sample(1, 0, Some(10.0)), sample(1, 1, Some(11.0))];
+        let html = render(&summary(&samples, None, None), &samples, &BTreeMap::from([(1, "pi<1>&".to_string())]), at(100));
+        assert!(html.contains("<h2>Station 1 (pi&lt;1&gt;&amp;)</h2>"));
+        assert!(!html.contains("pi<1>"));
+    }
+
+    #[test]
+    fn renders_the_sections_of_every_station() {
+        let mut samples = vec![];
+        for minute in 0..60 {
+            samples.push(sample(1, minute, Some(10.0 + minute as f64)));
+            samples.push(sample(2, minute, (!(20..30).contains(&minute)).then_some(25.0)));
+        }
+        let lines = ["1 -pi -10.0.0.1 -pi-north-1 -site=north", "2 -pi -10.0.0.2", "3 -pi -10.0.0.3 -pi-south-3"];
+        let entries: Vec<HostEntry> = lines.iter().map(|line| HostEntry::parse(line).unwrap()).collect();
+        let html = render(&summary(&samples, None, None), &samples, &station_names(&entries), at(100));
+        assert_self_contained(&html);
+        let sections: Vec<&str> = html.match_indices("<h2>").map(|(start, _)| &html[start + 4..start + html[start..].find("</h2>").unwrap()]).collect();
+        assert_eq!(sections, ["Summary", "Outages", "Packet loss", "Station 1 (pi-north-1)", "Station 2 (10.0.0.2)"]);
+        assert!(html.contains("<td>1</td><td class=\"text\">pi-north-1</td>"));
+        assert!(!html.contains("(pi)") && !html.contains(">pi<"));
+        // The outage timeline, the heatmap and a latency and a temperature chart for each station.
+        assert_eq!(html.matches("<svg ").count(), 6);
+        assert_eq!(html.matches("class=\"outage\"").count(), 1);
+        assert!(html.contains(&format!("<td>{}</td><td>{}</td><td>600</td><td>10</td>", time::rfc3339(&at(20)), time::rfc3339(&at(30)))));
+        assert_eq!(html.matches("<rect fill=\"hsl(").count() + html.matches("class=\"empty\"").count(), 2 * HEATMAP_COLUMNS);
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+
+use crate::alerting::rules::Metric;
+use crate::analysis::anomaly::{Anomaly, Trend};
+use crate::storage::reader::Sample;
+use crate::tools::math::{self, QuantileSketch, RunningStats};
+use crate::tools::output::OutputFormat;
+use crate::tools::time;
+
+/// Which samples to summarize.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// First instant included.
+    pub from: Option<DateTime<FixedOffset>>,
+    /// First instant excluded.
+    pub to: Option<DateTime<FixedOffset>>,
+    /// Stations to include, all if empty.
+    pub stations: Vec<u8>,
+}
+
+/// Distribution of a metric over the samples that have a value for it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub min: f64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    pub fn of(values: impl IntoIterator<Item = f64>) -> Option<Self> {
+        let mut accumulator = Accumulator::default();
+        values.into_iter().for_each(|value| accumulator.push(Some(value)));
+        accumulator.distribution()
+    }
+}
+
+/// Streaming summary of a metric, from which a `Distribution` is taken. Percentiles are estimated, exact for small counts.
+#[derive(Debug, Clone, Default)]
+pub struct Accumulator {
+    stats: RunningStats,
+    sketch: QuantileSketch,
+}
+
+impl Accumulator {
+    /// Adds a value, None is skipped.
+    pub fn push(&mut self, value: Option<f64>) {
+        if let Some(value) = value {
+            self.stats.push(value);
+            self.sketch.push(value);
+        }
+    }
+
+    /// Adds the values of `other`, e.g. of another file or station.
+    pub fn merge(&mut self, other: &Self) {
+        self.stats.merge(&other.stats);
+        self.sketch.merge(&other.sketch);
+    }
+
+    /// None without values.
+    pub fn distribution(&self) -> Option<Distribution> {
+        let percentile = |p| self.sketch.quantile(p / 100.0).map(|value| math::round(value, 3));
+        Some(Distribution {
+            count: self.stats.count() as usize,
+            min: self.stats.min()?,
+            avg: math::round(self.stats.mean()?, 3),
+            p50: percentile(50.0)?,
+            p90: percentile(90.0)?,
+            p95: percentile(95.0)?,
+            p99: percentile(99.0)?,
+            max: self.stats.max()?,
+        })
+    }
+}
+
+/// A run of consecutive samples in which the station did not answer.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Outage {
+    #[serde(serialize_with = "time::serialize_rfc3339")]
+    pub start: DateTime<FixedOffset>,
+    /// First sample the station answered again, None if it was still down at the end of the data.
+    #[serde(serialize_with = "time::serialize_rfc3339_opt")]
+    pub end: Option<DateTime<FixedOffset>>,
+    /// Seconds from the first failed sample to `end`, or to the last failed sample if the outage is ongoing.
+    pub duration_s: i64,
+    pub samples: usize,
+}
+
+impl fmt::Display for Outage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "{} to {} ({} s, {} samples)", time::rfc3339(&self.start), time::rfc3339(&end), self.duration_s, self.samples),
+            None => write!(f, "{} until the end of the data ({} s, {} samples)", time::rfc3339(&self.start), self.duration_s, self.samples),
+        }
+    }
+}
+
+/// Summary of the samples of one station.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StationStats {
+    pub station_no: u8,
+    pub samples: usize,
+    #[serde(serialize_with = "time::serialize_rfc3339")]
+    pub first: DateTime<FixedOffset>,
+    #[serde(serialize_with = "time::serialize_rfc3339")]
+    pub last: DateTime<FixedOffset>,
+    /// Share of the samples in which the station answered, in percent.
+    pub uptime: f64,
+    pub latency: Option<Distribution>,
+    pub packet_loss: Option<Distribution>,
+    pub cpu_temperature: Option<Distribution>,
+    /// Sum of the outage durations in seconds.
+    pub downtime_s: i64,
+    pub outages: Vec<Outage>,
+    /// Deviations from the baseline of the station, see `anomaly::annotate`.
+    pub anomalies: Vec<Anomaly>,
+    pub trends: Vec<Trend>,
+}
+
+/// Result of `xbfisher stats`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    #[serde(serialize_with = "time::serialize_rfc3339_opt")]
+    pub from: Option<DateTime<FixedOffset>>,
+    #[serde(serialize_with = "time::serialize_rfc3339_opt")]
+    pub to: Option<DateTime<FixedOffset>>,
+    /// Records of the data files that could not be read.
+    pub skipped: usize,
+    pub stations: Vec<StationStats>,
+}
+
+/// Summarizes the samples, which have to be sorted by time, per station.
+pub fn summarize(samples: &[Sample], query: &Query) -> Vec<StationStats> {
+    let mut by_station: BTreeMap<u8, Vec<&Sample>> = BTreeMap::new();
+    for sample in samples.iter().filter(|sample| query.stations.is_empty() || query.stations.contains(&sample.station_no)) {
+        by_station.entry(sample.station_no).or_default().push(sample);
+    }
+    by_station.into_iter().filter_map(|(station_no, samples)| station_stats(station_no, &samples)).collect()
+}
+
+fn station_stats(station_no: u8, samples: &[&Sample]) -> Option<StationStats> {
+    let (first, last) = (samples.first()?.time, samples.last()?.time);
+    let (mut latency, mut packet_loss, mut cpu_temperature) = (Accumulator::default(), Accumulator::default(), Accumulator::default());
+    for sample in samples {
+        latency.push(sample.latency);
+        packet_loss.push(sample.packet_loss);
+        cpu_temperature.push(sample.cpu_temperature);
+    }
+    let outages = outages(samples);
+    let reachable = samples.iter().filter(|sample| sample.reachable()).count();
+    Some(StationStats {
+        station_no,
+        samples: samples.len(),
+        first,
+        last,
+        uptime: math::round(reachable as f64 * 100.0 / samples.len() as f64, 2),
+        latency: latency.distribution(),
+        packet_loss: packet_loss.distribution(),
+        cpu_temperature: cpu_temperature.distribution(),
+        downtime_s: outages.iter().map(|outage| outage.duration_s).sum(),
+        outages,
+        anomalies: vec![],
+        trends: vec![],
+    })
+}
+
+fn outages(samples: &[&Sample]) -> Vec<Outage> {
+    let mut outages = vec![];
+    let mut current: Option<(DateTime<FixedOffset>, DateTime<FixedOffset>, usize)> = None;
+    for sample in samples {
+        match (&mut current, sample.reachable()) {
+            (None, false) => current = Some((sample.time, sample.time, 1)),
+            (Some((_, last, count)), false) => {
+                *last = sample.time;
+                *count += 1;
+            },
+            (Some((start, _, count)), true) => {
+                outages.push(Outage { start: *start, end: Some(sample.time), duration_s: (sample.time - *start).num_seconds(), samples: *count });
+                current = None;
+            },
+            (None, true) => {},
+        }
+    }
+    if let Some((start, last, count)) = current {
+        outages.push(Outage { start, end: None, duration_s: (last - start).num_seconds(), samples: count });
+    }
+    outages
+}
+
+impl Summary {
+    /// Writes the summary as tables, as csv with one line per station and without the outage and anomaly lists, or as a JSON document.
+    pub fn write(&self, out: &mut impl Write, format: OutputFormat) -> io::Result<()> {
+        match format {
+            OutputFormat::Text => self.write_table(out),
+            OutputFormat::Csv => self.write_csv(out),
+            OutputFormat::Json => {
+                serde_json::to_writer_pretty(&mut *out, self)?;
+                writeln!(out)
+            },
+        }
+    }
+
+    fn write_table(&self, out: &mut impl Write) -> io::Result<()> {
+        let range = |bound: Option<DateTime<FixedOffset>>| bound.map(|bound| time::rfc3339(&bound)).unwrap_or_else(|| "-".into());
+        writeln!(out, "Data from {} to {}.", range(self.from), range(self.to))?;
+        if self.stations.is_empty() {
+            return writeln!(out, "No samples found.");
+        }
+        let header = ["Station", "Samples", "Uptime %", "Lat min", "Lat avg", "Lat p50", "Lat p95", "Lat p99", "Lat max", "Loss avg", "Temp min", "Temp avg", "Temp max", "Outages", "Down s", "Anomalies"];
+        let mut rows = vec![header.iter().map(|cell| cell.to_string()).collect::<Vec<String>>()];
+        for stats in &self.stations {
+            let cell = |value: Option<f64>| value.map(|value| math::round(value, 2).to_string()).unwrap_or_else(|| "-".into());
+            let (latency, loss, temperature) = (stats.latency.as_ref(), stats.packet_loss.as_ref(), stats.cpu_temperature.as_ref());
+            rows.push(vec![
+                stats.station_no.to_string(),
+                stats.samples.to_string(),
+                stats.uptime.to_string(),
+                cell(latency.map(|latency| latency.min)),
+                cell(latency.map(|latency| latency.avg)),
+                cell(latency.map(|latency| latency.p50)),
+                cell(latency.map(|latency| latency.p95)),
+                cell(latency.map(|latency| latency.p99)),
+                cell(latency.map(|latency| latency.max)),
+                cell(loss.map(|loss| loss.avg)),
+                cell(temperature.map(|temperature| temperature.min)),
+                cell(temperature.map(|temperature| temperature.avg)),
+                cell(temperature.map(|temperature| temperature.max)),
+                stats.outages.len().to_string(),
+                stats.downtime_s.to_string(),
+                stats.anomalies.len().to_string(),
+            ]);
+        }
+        let widths: Vec<usize> = (0..header.len()).map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0)).collect();
+        for row in &rows {
+            let line: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{cell:>width$}")).collect();
+            writeln!(out, "{}", line.join("  "))?;
+        }
+        for stats in self.stations.iter().filter(|stats| !stats.outages.is_empty()) {
+            writeln!(out, "\nOutages of station {}:", stats.station_no)?;
+            for outage in &stats.outages {
+                writeln!(out, "  {outage}")?;
+            }
+        }
+        for stats in self.stations.iter().filter(|stats| !stats.anomalies.is_empty()) {
+            writeln!(out, "\nAnomalies of station {}:", stats.station_no)?;
+            for anomaly in &stats.anomalies {
+                writeln!(out, "  {anomaly}")?;
+            }
+        }
+        for stats in self.stations.iter().filter(|stats| !stats.trends.is_empty()) {
+            writeln!(out, "\nTrends of station {}:", stats.station_no)?;
+            for trend in &stats.trends {
+                writeln!(out, "  {trend}")?;
+            }
+        }
+        if self.skipped > 0 {
+            writeln!(out, "\n{} unreadable records skipped.", self.skipped)?;
+        }
+        Ok(())
+    }
+
+    fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut wtr = csv::Writer::from_writer(out);
+        let mut header: Vec<String> = ["station_no", "samples", "first", "last", "uptime_pct", "outages", "downtime_s"].iter().map(|name| name.to_string()).collect();
+        for metric in Metric::ALL {
+            header.extend(["min", "avg", "p50", "p90", "p95", "p99", "max"].iter().map(|column| format!("{metric}_{column}")));
+        }
+        header.push("anomalies".into());
+        header.extend(Metric::ALL.iter().map(|metric| format!("{metric}_trend_per_day")));
+        wtr.write_record(&header)?;
+        for stats in &self.stations {
+            let mut record = vec![
+                stats.station_no.to_string(),
+                stats.samples.to_string(),
+                time::rfc3339(&stats.first),
+                time::rfc3339(&stats.last),
+                stats.uptime.to_string(),
+                stats.outages.len().to_string(),
+                stats.downtime_s.to_string(),
+            ];
+            for distribution in [&stats.latency, &stats.packet_loss, &stats.cpu_temperature] {
+                match distribution {
+                    Some(d) => record.extend([d.min, d.avg, d.p50, d.p90, d.p95, d.p99, d.max].iter().map(|value| value.to_string())),
+                    None => record.extend(std::iter::repeat_n(String::new(), 7)),
+                }
+            }
+            record.push(stats.anomalies.len().to_string());
+            for metric in Metric::ALL {
+                record.push(stats.trends.iter().find(|trend| trend.metric == metric).map(|trend| trend.slope.to_string()).unwrap_or_default());
+            }
+            wtr.write_record(&record)?;
+        }
+        wtr.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minute: i64) -> DateTime<FixedOffset> {
+        DateTime::from_timestamp(1_730_030_400 + minute * 60, 0).unwrap().fixed_offset()
+    }
+
+    fn sample(station_no: u8, minute: i64, latency: Option<f64>) -> Sample {
+        Sample { time: at(minute), station_no, latency, packet_loss: Some(if latency.is_some() { 0.0 } else { 100.0 }), cpu_temperature: Some(40.0 + minute as f64), error: None }
+    }
+
+    /// Station 1 answers with 1 to 10 ms, except at minutes 3 and 4 and at the last sample; station 2 always answers with 50 ms.
+    fn fixture() -> Vec<Sample> {
+        let mut samples = vec![];
+        for minute in 0..10 {
+            let latency = (![3, 4, 9].contains(&minute)).then_some(minute as f64 + 1.0);
+            samples.push(sample(1, minute, latency));
+            samples.push(sample(2, minute, Some(50.0)));
+        }
+        samples
+    }
+
+    #[test]
+    fn computes_uptime_outages_and_percentiles() {
+        let stats = summarize(&fixture(), &Query::default());
+        assert_eq!(stats.len(), 2);
+        let station = &stats[0];
+        assert_eq!((station.station_no, station.samples, station.first, station.last), (1, 10, at(0), at(9)));
+        assert_eq!(station.uptime, 70.0);
+        assert_eq!(station.outages, [
+            Outage { start: at(3), end: Some(at(5)), duration_s: 120, samples: 2 },
+            Outage { start: at(9), end: None, duration_s: 0, samples: 1 },
+        ]);
+        assert_eq!(station.downtime_s, 120);
+        // Latencies 1, 2, 3, 6, 7, 8 and 9 ms.
+        assert_eq!(station.latency, Some(Distribution { count: 7, min: 1.0, avg: 5.143, p50: 6.0, p90: 8.4, p95: 8.7, p99: 8.94, max: 9.0 }));
+        assert_eq!(station.packet_loss.map(|loss| (loss.count, loss.avg)), Some((10, 30.0)));
+        assert_eq!(station.cpu_temperature.map(|temperature| (temperature.min, temperature.max)), Some((40.0, 49.0)));
+
+        let station = &stats[1];
+        assert_eq!(station.uptime, 100.0);
+        assert!(station.outages.is_empty());
+        assert_eq!(station.latency.map(|latency| (latency.p50, latency.p99)), Some((50.0, 50.0)));
+    }
+
+    #[test]
+    fn summarizes_only_the_selected_stations() {
+        let stats = summarize(&fixture(), &Query { stations: vec![2], ..Query::default() });
+        assert_eq!(stats.iter().map(|stats| stats.station_no).collect::<Vec<_>>(), [2]);
+        assert!(summarize(&[], &Query::default()).is_empty());
+    }
+
+    #[test]
+    fn a_station_that_never_answered_has_no_latency() {
+        let samples: Vec<Sample> = (0..3).map(|minute| sample(4, minute, None)).collect();
+        let stats = summarize(&samples, &Query::default());
+        assert_eq!(stats[0].uptime, 0.0);
+        assert_eq!(stats[0].latency, None);
+        assert_eq!(stats[0].outages, [Outage { start: at(0), end: None, duration_s: 120, samples: 3 }]);
+    }
+}
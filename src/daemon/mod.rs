@@ -0,0 +1,296 @@
+pub mod pidfile;
+pub mod systemd;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use crate::alerting::notifier::{self, Dispatcher};
+use crate::alerting::AlertEngine;
+use crate::analysis::anomaly::AnomalyDetector;
+use crate::monitor::{Cycles, Monitor};
+use crate::stations::hosts::{self, HostEntry};
+use crate::stations::station::Station;
+use crate::storage::reader;
+use crate::storage::sink::FanOut;
+use crate::tools::config::Config;
+use crate::tools::deadline::CancelToken;
+use crate::tools::errors::Error;
+use crate::tools::filecontrol::AlertLog;
+use crate::tools::output::{OutputFormat, Printer};
+
+use self::pidfile::PidFile;
+use self::systemd::Watchdog;
+
+/// How often the idle daemon checks for signals and feeds the watchdog.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Flags set from the signal thread and read by the main loop.
+#[derive(Default)]
+struct Control {
+    stop: AtomicBool,
+    reload: AtomicBool,
+    cancel: CancelToken,
+}
+
+/// Runs the logger until SIGTERM or SIGINT.
+/// The first SIGTERM/SIGINT lets the running gather cycle finish and be written before exiting, a second one cancels it.
+/// SIGHUP reloads the config and hosts file and connects or drops stations accordingly.
+/// Stations of a [[group]] with an interval are gathered at that interval, the others every `interval`, by the same `Cycles` a `Monitor` runs;
+/// the daemon adds the sinks, alerts and notifiers and feeds the watchdog before each station.
+/// With [anomaly] enabled, baselines are learned from the logged data and deviations and trends are sent like alerts.
+/// If started by systemd the daemon reports readiness and feeds the watchdog (Type=notify, WatchdogSec= above the probe plus ssh timeouts).
+/// Every cycle is printed in the `output` format like the `log` command prints it, messages go to stderr outside of text output.
+pub fn run(config_path: &Path, pidfile: Option<&Path>, output: OutputFormat) -> Result<(), Error> {
+    let mut printer = Printer::new(output);
+    let mut config = Config::load(config_path)?;
+    let pidfile_path: Option<PathBuf> = pidfile.map(Path::to_path_buf).or_else(|| config.daemon.pidfile.clone());
+    let _pidfile = match &pidfile_path {
+        Some(path) => Some(PidFile::create(path)?),
+        None => None,
+    };
+
+    let control = Arc::new(Control::default());
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    let signal_handle = signals.handle();
+    let signal_control = Arc::clone(&control);
+    let signal_thread = thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGHUP => signal_control.reload.store(true, Ordering::SeqCst),
+                _ => {
+                    if signal_control.stop.swap(true, Ordering::SeqCst) {
+                        signal_control.cancel.cancel();
+                    }
+                },
+            }
+        }
+    });
+
+    let svec = connect(&hosts::load_or_create(&config.hosts)?, &printer)?;
+    let mut sinks = FanOut::spawn(&config.sinks());
+    sinks.stations(&entries(&svec));
+    let mut cycles = Cycles::new(Monitor::with_config(svec, &config));
+    let mut alerts = AlertEngine::new(config.alerts.clone());
+    let mut alert_log = AlertLog::new(&config.csv_output());
+    let mut anomalies = detector(&config, &printer);
+    let mut dispatcher = Dispatcher::spawn(notifier::build_all(&config.notifiers), config.notify.clone());
+    let mut watchdog = Watchdog::from_env();
+    let stations = cycles.monitor().stations().len();
+    notify(&format!("READY=1\nSTATUS=Logging {stations} stations every {} s", config.interval));
+    printer.message(format!("Daemon started with pid {}, logging {stations} stations every {} s.", std::process::id(), config.interval));
+
+    while !control.stop.load(Ordering::SeqCst) {
+        if control.reload.swap(false, Ordering::SeqCst) {
+            notify("RELOADING=1");
+            if let Some(entries) = reload_cycles(config_path, &mut config, &mut cycles, &printer) {
+                sinks.reconfigure(&config.sinks());
+                sinks.stations(&entries);
+                alert_log = AlertLog::new(&config.csv_output());
+            }
+            let station_nos: Vec<u8> = cycles.monitor().stations().iter().map(Station::get_station_no).collect();
+            alerts.set_rules(config.alerts.clone());
+            if anomalies.as_ref().map(AnomalyDetector::config) != config.anomaly.enabled.then_some(&config.anomaly) {
+                anomalies = detector(&config, &printer);
+            }
+            if let Some(anomalies) = anomalies.as_mut() {
+                anomalies.retain(&station_nos);
+            }
+            dispatcher.shutdown();
+            dispatcher = Dispatcher::spawn(notifier::build_all(&config.notifiers), config.notify.clone());
+            notify(&format!("READY=1\nSTATUS=Logging {} stations every {} s", station_nos.len(), config.interval));
+        }
+
+        let cycle = cycles.run_due(&control.cancel, |_| {
+            if let Some(watchdog) = watchdog.as_mut() {
+                watchdog.keepalive();
+            }
+        });
+        match cycle {
+            Ok(batch) => {
+                let now = config.time.zone.now();
+                let mut alert_events = alerts.evaluate_all(&batch.rows, now);
+                if let Some(anomalies) = anomalies.as_mut() {
+                    alert_events.extend(anomalies.observe_all(&batch.rows, now));
+                }
+                if let Err(error) = alert_log.write(&alert_events) {
+                    printer.message(format!("Problem writing the alerts. Error: {error}"));
+                }
+                dispatcher.send(alert_events);
+                if let Err(error) = printer.batch(&batch) {
+                    eprintln!("Problem printing the cycle. Error: {error}");
+                }
+                sinks.write(&batch);
+            },
+            Err(_) => {
+                printer.message("Gather cycle cancelled, discarding its data.");
+                break;
+            },
+        }
+
+        let next_cycle = cycles.next_due();
+        while Instant::now() < next_cycle && !control.stop.load(Ordering::SeqCst) && !control.reload.load(Ordering::SeqCst) {
+            if let Some(watchdog) = watchdog.as_mut() {
+                watchdog.keepalive();
+            }
+            thread::sleep(POLL_INTERVAL.min(next_cycle.saturating_duration_since(Instant::now())));
+        }
+    }
+
+    notify("STOPPING=1");
+    dispatcher.shutdown();
+    sinks.shutdown();
+    signal_handle.close();
+    let _ = signal_thread.join();
+    printer.message("Daemon stopped.");
+    Ok(())
+}
+
+/// An anomaly detector that learned from the last `history_days` of logged data, None unless [anomaly] is enabled.
+fn detector(config: &Config, printer: &Printer) -> Option<AnomalyDetector> {
+    if !config.anomaly.enabled {
+        return None;
+    }
+    let mut detector = AnomalyDetector::new(config.anomaly.clone());
+    let csv = config.csv_output();
+    let from = config.time.zone.now() - chrono::Duration::days(config.anomaly.history_days.into());
+    match reader::read_samples(&csv.directory, &csv.prefix, Some(from), None) {
+        Ok(history) => {
+            detector.learn(&history.samples);
+            printer.message(format!("Learned the baselines from {} logged samples.", history.samples.len()));
+        },
+        Err(error) => printer.message(format!("Problem reading the logged data for the baselines, starting without. Error: {error}")),
+    }
+    Some(detector)
+}
+
+/// Re-reads the config and hosts file and returns the stations to log. Stations whose entry is unchanged keep running, others are connected or dropped.
+/// On any error the previous configuration stays in effect and None is returned.
+fn reload(config_path: &Path, config: &mut Config, svec: &[Station], printer: &Printer) -> Option<Vec<Station>> {
+    let new_config = match Config::load(config_path) {
+        Ok(new_config) => new_config,
+        Err(error) => {
+            printer.message(format!("Reload failed, keeping the previous configuration. Error: {error}"));
+            return None;
+        },
+    };
+    let entries = match hosts::load(&new_config.hosts) {
+        Ok(entries) => entries,
+        Err(error) => {
+            printer.message(format!("Reload failed, keeping the previous configuration. Error: {error}"));
+            return None;
+        },
+    };
+    let mut previous: Vec<Option<Station>> = svec.iter().cloned().map(Some).collect();
+    let mut stations = vec![];
+    for entry in &entries {
+        match previous.iter_mut().find(|slot| slot.as_ref().is_some_and(|station| &station.entry() == entry)) {
+            Some(slot) => stations.extend(slot.take()),
+            None => match connect(std::slice::from_ref(entry), printer) {
+                Ok(connected) => stations.extend(connected),
+                Err(error) => {
+                    printer.message(format!("Reload failed, keeping the previous configuration. Error: {error}"));
+                    return None;
+                },
+            },
+        }
+    }
+    for station in previous.into_iter().flatten() {
+        printer.message(format!("Station {} with ip: {} removed.", station.get_station_no(), station.get_ip_address()));
+    }
+    *config = new_config;
+    printer.message(format!("Configuration reloaded, logging {} stations every {} s.", stations.len(), config.interval));
+    Some(stations)
+}
+
+/// Reloads the config and hosts file into `config` and the cycles, see `reload`, and returns the entries of the stations now logged.
+/// The stations kept keep their health state and are due one interval after their last gather.
+fn reload_cycles(config_path: &Path, config: &mut Config, cycles: &mut Cycles, printer: &Printer) -> Option<Vec<HostEntry>> {
+    let svec = reload(config_path, config, cycles.monitor().stations(), printer)?;
+    let entries = entries(&svec);
+    cycles.reconfigure(Monitor::with_config(svec, config));
+    Some(entries)
+}
+
+fn entries(svec: &[Station]) -> Vec<HostEntry> {
+    svec.iter().map(Station::entry).collect()
+}
+
+/// Connects to the stations, checking whether they answer with text output like the other commands.
+fn connect(entries: &[HostEntry], printer: &Printer) -> Result<Vec<Station>, Error> {
+    match printer.format() {
+        OutputFormat::Text => entries.iter().map(Station::connect_entry).collect(),
+        _ => entries.iter().map(Station::from_entry).collect(),
+    }
+}
+
+fn notify(state: &str) {
+    if let Err(error) = systemd::notify(state) {
+        eprintln!("Problem notifying the service manager. Error: {error}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io;
+
+    use crate::stations::health::HealthState;
+    use crate::stations::station::DataRow;
+
+    /// A directory with a config logging the hosts file next to it every `interval` seconds.
+    fn config_dir(name: &str, interval: u64, hosts: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xbfisher-daemon-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        write_config(&dir, interval, hosts);
+        dir
+    }
+
+    fn write_config(dir: &Path, interval: u64, hosts: &str) {
+        fs::write(dir.join("config.toml"), format!("hosts = \"{}\"\ninterval = {interval}\n", dir.join("hosts.txt").display())).unwrap();
+        fs::write(dir.join("hosts.txt"), hosts).unwrap();
+    }
+
+    fn state(cycles: &Cycles, station_no: u8) -> Option<HealthState> {
+        cycles.health().get(station_no).map(|health| health.state())
+    }
+
+    #[test]
+    fn a_reload_keeps_the_health_and_schedule_of_the_stations_kept() {
+        let dir = config_dir("reload", 60, "1 -pi -10.0.0.1\n2 -pi -10.0.0.2\n");
+        let printer = Printer::with_writers(OutputFormat::Json, io::sink(), io::sink());
+        let config_path = dir.join("config.toml");
+        let mut config = Config::load(&config_path).unwrap();
+        let svec = connect(&hosts::load(&config.hosts).unwrap(), &printer).unwrap();
+        let mut cycles = Cycles::new(Monitor::with_config(svec, &config));
+        let start = Instant::now();
+        let now = config.time.zone.now();
+        assert_eq!(cycles.observe_due(start, &[DataRow::unreachable(1, now), DataRow::unreachable(1, now), DataRow::sample(2, now, 10.0)]), [1, 2]);
+        assert_eq!(state(&cycles, 1), Some(HealthState::Down));
+
+        write_config(&dir, 30, "1 -pi -10.0.0.1\n3 -pi -10.0.0.3\n");
+        let entries = reload_cycles(&config_path, &mut config, &mut cycles, &printer).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.station_no).collect::<Vec<u8>>(), [1, 3]);
+        assert_eq!(config.interval, 30);
+        assert_eq!(state(&cycles, 1), Some(HealthState::Down));
+        assert_eq!(state(&cycles, 2), None);
+        assert_eq!(cycles.observe_due(start + Duration::from_secs(1), &[]), [3]);
+        assert_eq!(cycles.next_due(), start + Duration::from_secs(30));
+
+        // A hosts file that doesn't load leaves everything as it was.
+        write_config(&dir, 10, "1 -pi -10.0.0.1\n1 -pi -10.0.0.11\n");
+        assert_eq!(reload_cycles(&config_path, &mut config, &mut cycles, &printer), None);
+        assert_eq!(config.interval, 30);
+        assert_eq!(cycles.monitor().stations().len(), 2);
+        assert_eq!(state(&cycles, 1), Some(HealthState::Down));
+        assert_eq!(cycles.next_due(), start + Duration::from_secs(30));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::tools::errors::Error;
+
+/// Holds the pidfile for as long as the daemon runs and removes it when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the current process id to `path`.
+    /// A pidfile left behind by a process that is no longer running is replaced, a live one is an error.
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        if let Some(pid) = read_pid(path) {
+            if pid != std::process::id() && Path::new(&format!("/proc/{pid}")).exists() {
+                return Err(Error::AlreadyRunning { pid, path: path.display().to_string() });
+            }
+            eprintln!("Removing stale pidfile {} of pid {pid}.", path.display());
+            fs::remove_file(path)?;
+        }
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::AlreadyRunning { pid: read_pid(path).unwrap_or(0), path: path.display().to_string() });
+            },
+            Err(error) => return Err(error.into()),
+        };
+        writeln!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+        Ok(Self { path: path.to_path_buf() })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn pidfile(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("xbfisher-pidfile-{name}-{}.pid", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn writes_the_pid_and_removes_the_file_when_dropped() {
+        let path = pidfile("drop");
+        let pidfile = PidFile::create(&path).unwrap();
+        assert_eq!(read_pid(&path), Some(std::process::id()));
+        drop(pidfile);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn refuses_the_pidfile_of_a_running_process() {
+        let path = pidfile("running");
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        fs::write(&path, format!("{}\n", child.id())).unwrap();
+        let result = PidFile::create(&path);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(matches!(result, Err(Error::AlreadyRunning { pid, .. }) if pid == child.id()));
+        assert_eq!(read_pid(&path), Some(child.id()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replaces_a_stale_pidfile() {
+        let path = pidfile("stale");
+        // Above the largest pid Linux hands out.
+        fs::write(&path, "4000000000\n").unwrap();
+        let pidfile = PidFile::create(&path).unwrap();
+        assert_eq!(read_pid(&path), Some(std::process::id()));
+        drop(pidfile);
+        assert!(!path.exists());
+    }
+}
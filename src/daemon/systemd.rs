@@ -0,0 +1,100 @@
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::{Duration, Instant};
+
+/// Sends a state string such as "READY=1" to the service manager as described in sd_notify(3).
+/// Returns false without doing anything if we were not started by systemd with NotifyAccess.
+pub fn notify(state: &str) -> io::Result<bool> {
+    notify_socket(env::var_os("NOTIFY_SOCKET").as_deref(), state)
+}
+
+/// Sends the state to the socket a NOTIFY_SOCKET variable names, nothing without one.
+fn notify_socket(path: Option<&OsStr>, state: &str) -> io::Result<bool> {
+    let Some(path) = path else {
+        return Ok(false);
+    };
+    let socket = UnixDatagram::unbound()?;
+    let path = path.as_bytes();
+    // A leading '@' denotes a socket in the abstract namespace.
+    let addr = match path.strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(OsStr::from_bytes(path))?,
+    };
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+/// Sends "WATCHDOG=1" keep-alives at half of the interval requested through WATCHDOG_USEC.
+/// The daemon calls `keepalive` from its main loop, so a hung loop lets the watchdog fire.
+pub struct Watchdog {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl Watchdog {
+    /// Returns None if the service manager did not enable the watchdog for this process.
+    pub fn from_env() -> Option<Self> {
+        Self::from_vars(env::var("WATCHDOG_USEC").ok().as_deref(), env::var("WATCHDOG_PID").ok().as_deref())
+    }
+
+    /// The watchdog the WATCHDOG_USEC and WATCHDOG_PID variables describe, None if they don't enable it for this process.
+    fn from_vars(usec: Option<&str>, pid: Option<&str>) -> Option<Self> {
+        let usec: u64 = usec?.parse().ok()?;
+        if let Some(pid) = pid.and_then(|pid| pid.parse::<u32>().ok()) {
+            if pid != std::process::id() {
+                return None;
+            }
+        }
+        Some(Self { interval: Duration::from_micros(usec) / 2, last: None })
+    }
+
+    pub fn keepalive(&mut self) {
+        if self.last.is_some_and(|last| last.elapsed() < self.interval) {
+            return;
+        }
+        if let Err(error) = notify("WATCHDOG=1") {
+            eprintln!("Problem sending the watchdog keep-alive. Error: {error}");
+        }
+        self.last = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn sends_the_state_to_the_notify_socket() {
+        let dir = std::env::temp_dir().join(format!("xbfisher-systemd-notify-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert!(notify_socket(Some(path.as_os_str()), "READY=1\nSTATUS=Logging 3 stations").unwrap());
+        let mut buffer = [0; 256];
+        let size = socket.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"READY=1\nSTATUS=Logging 3 stations");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn does_nothing_without_a_notify_socket() {
+        assert!(!notify_socket(None, "READY=1").unwrap());
+    }
+
+    #[test]
+    fn keeps_the_watchdog_alive_at_half_its_interval() {
+        let pid = std::process::id().to_string();
+        assert_eq!(Watchdog::from_vars(Some("30000000"), None).unwrap().interval, Duration::from_secs(15));
+        assert_eq!(Watchdog::from_vars(Some("500000"), Some(&pid)).unwrap().interval, Duration::from_millis(250));
+        assert!(Watchdog::from_vars(Some("30000000"), Some("1")).is_none());
+        assert!(Watchdog::from_vars(Some("thirty"), None).is_none());
+        assert!(Watchdog::from_vars(None, Some(&pid)).is_none());
+    }
+}
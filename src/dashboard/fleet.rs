@@ -0,0 +1,179 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+use crate::stations::health::{HealthState, HealthThresholds, HealthTracker, StateEvent};
+use crate::stations::hosts::HostEntry;
+use crate::stations::station::DataRow;
+
+/// Samples kept per station for the sparklines and the detail view.
+pub const HISTORY: usize = 120;
+/// State changes kept per station for the detail view.
+const EVENTS: usize = 20;
+
+/// Column the station list is sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    #[default]
+    Station,
+    /// Worst state first.
+    State,
+    Latency,
+    Loss,
+    Temperature,
+}
+
+impl SortKey {
+    /// The key the sort key cycles to.
+    pub fn next(self) -> Self {
+        match self {
+            SortKey::Station => SortKey::State,
+            SortKey::State => SortKey::Latency,
+            SortKey::Latency => SortKey::Loss,
+            SortKey::Loss => SortKey::Temperature,
+            SortKey::Temperature => SortKey::Station,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortKey::Station => "station",
+            SortKey::State => "state",
+            SortKey::Latency => "latency",
+            SortKey::Loss => "loss",
+            SortKey::Temperature => "temperature",
+        }
+    }
+}
+
+/// What the dashboard knows about one station.
+#[derive(Debug, Clone)]
+pub struct StationView {
+    pub entry: HostEntry,
+    pub state: HealthState,
+    /// Oldest sample first.
+    pub history: VecDeque<DataRow>,
+    /// Oldest state change first.
+    pub events: VecDeque<StateEvent>,
+    /// Whether a sample is being gathered right now.
+    pub polling: bool,
+}
+
+impl StationView {
+    pub fn last(&self) -> Option<&DataRow> {
+        self.history.back()
+    }
+
+    /// Error of the most recent sample that had one.
+    pub fn last_error(&self) -> Option<&str> {
+        self.history.iter().rev().find_map(DataRow::error)
+    }
+
+    fn matches(&self, filter: &str) -> bool {
+        let fields = [self.entry.station_no.to_string(), self.entry.usr_name.to_lowercase(), self.entry.ip_address.clone(), self.state.to_string()];
+        filter.to_lowercase().split_whitespace().all(|term| fields.iter().any(|field| field.contains(term)))
+    }
+}
+
+/// Stations of the dashboard with their recent samples and health.
+pub struct Fleet {
+    stations: Vec<StationView>,
+    health: HealthTracker,
+    pub sort: SortKey,
+    /// Sort in descending order.
+    pub reverse: bool,
+    /// Whitespace separated terms that each have to match the number, user, address or state of a shown station.
+    pub filter: String,
+}
+
+impl Fleet {
+    pub fn new(entries: &[HostEntry], thresholds: HealthThresholds) -> Self {
+        let stations = entries.iter().map(|entry| StationView {
+            entry: entry.clone(),
+            state: HealthState::Unknown,
+            history: VecDeque::new(),
+            events: VecDeque::new(),
+            polling: false,
+        }).collect();
+        Self { stations, health: HealthTracker::new(thresholds), sort: SortKey::default(), reverse: false, filter: String::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.stations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stations.is_empty()
+    }
+
+    pub fn get(&self, station_no: u8) -> Option<&StationView> {
+        self.stations.iter().find(|station| station.entry.station_no == station_no)
+    }
+
+    pub fn set_polling(&mut self, station_no: u8, polling: bool) {
+        if let Some(station) = self.stations.iter_mut().find(|station| station.entry.station_no == station_no) {
+            station.polling = polling;
+        }
+    }
+
+    /// Adds a sample to the history of its station and updates the station's health.
+    pub fn record(&mut self, row: DataRow) {
+        let event = self.health.observe(&row, row.time());
+        let Some(station) = self.stations.iter_mut().find(|station| station.entry.station_no == row.station_no()) else {
+            return;
+        };
+        if let Some(health) = self.health.get(row.station_no()) {
+            station.state = health.state();
+        }
+        station.polling = false;
+        station.history.push_back(row);
+        while station.history.len() > HISTORY {
+            station.history.pop_front();
+        }
+        if let Some(event) = event {
+            station.events.push_back(event);
+            while station.events.len() > EVENTS {
+                station.events.pop_front();
+            }
+        }
+    }
+
+    /// The stations matching the filter, in sort order.
+    pub fn visible(&self) -> Vec<&StationView> {
+        let mut stations: Vec<&StationView> = self.stations.iter().filter(|station| station.matches(&self.filter)).collect();
+        stations.sort_by(|a, b| {
+            let order = match self.sort {
+                SortKey::Station => Ordering::Equal,
+                SortKey::State => severity(a.state).cmp(&severity(b.state)).reverse(),
+                SortKey::Latency => compare(a.last().and_then(DataRow::latency), b.last().and_then(DataRow::latency)),
+                SortKey::Loss => compare(a.last().and_then(DataRow::packet_loss), b.last().and_then(DataRow::packet_loss)),
+                SortKey::Temperature => compare(a.last().and_then(DataRow::cpu_temperature), b.last().and_then(DataRow::cpu_temperature)),
+            };
+            order.then(a.entry.station_no.cmp(&b.entry.station_no))
+        });
+        if self.reverse {
+            stations.reverse();
+        }
+        stations
+    }
+}
+
+/// Orders stations from the healthiest to the most troubled.
+fn severity(state: HealthState) -> u8 {
+    match state {
+        HealthState::Up => 0,
+        HealthState::Unknown => 1,
+        HealthState::Degraded => 2,
+        HealthState::Flapping => 3,
+        HealthState::Down => 4,
+    }
+}
+
+/// Ascending, with missing values last.
+fn compare(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
@@ -0,0 +1,232 @@
+pub mod fleet;
+mod ui;
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::DefaultTerminal;
+
+use crate::stations::commands::{cancel_on_signal, select_entries};
+use crate::stations::groups::Selector;
+use crate::stations::station::{DataRow, Station};
+use crate::tools::config::Config;
+use crate::tools::deadline::CancelToken;
+use crate::tools::errors::Error;
+
+use self::fleet::Fleet;
+
+/// How often the screen is redrawn while no key is pressed.
+const TICK: Duration = Duration::from_millis(250);
+
+/// Messages from the polling threads to the dashboard.
+enum Update {
+    Polling(u8),
+    Sample(DataRow),
+}
+
+/// What the dashboard shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    List,
+    Detail(u8),
+}
+
+/// State of the dashboard between two frames.
+struct App {
+    fleet: Fleet,
+    /// Position of the selected station among the visible ones.
+    selected: usize,
+    view: View,
+    /// Whether keys are typed into the filter.
+    editing_filter: bool,
+    interval: u64,
+    quit: bool,
+}
+
+impl App {
+    fn selected_station(&self) -> Option<u8> {
+        self.fleet.visible().get(self.selected).map(|station| station.entry.station_no)
+    }
+
+    fn move_selection(&mut self, step: isize) {
+        let count = self.fleet.visible().len();
+        self.selected = self.selected.saturating_add_signed(step).min(count.saturating_sub(1));
+    }
+}
+
+/// Shows a live overview of the stations of the hosts file picked by the selector until q, Ctrl-C, SIGINT or SIGTERM.
+/// The stations are polled every `interval` seconds like the logger does, but nothing is written to the sinks.
+pub fn run(config: &Config, selector: &Selector) -> Result<(), Error> {
+    let entries = select_entries(config, selector)?;
+    let stations = entries.iter().map(Station::from_entry).collect::<Result<Vec<Station>, Error>>()?;
+    let (poller, receiver) = Poller::spawn(config, stations);
+    let mut app = App { fleet: Fleet::new(&entries, config.health.clone()), selected: 0, view: View::List, editing_filter: false, interval: config.interval, quit: false };
+    let result = ratatui::try_init().map_err(Error::from).and_then(|mut terminal| {
+        let result = event_loop(&mut terminal, &mut app, &poller, &receiver);
+        ratatui::restore();
+        result
+    });
+    poller.stop();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App, poller: &Poller, receiver: &Receiver<Update>) -> Result<(), Error> {
+    while !app.quit && !poller.cancel.is_cancelled() {
+        while let Ok(update) = receiver.try_recv() {
+            match update {
+                Update::Polling(station_no) => app.fleet.set_polling(station_no, true),
+                Update::Sample(row) => app.fleet.record(row),
+            }
+        }
+        app.move_selection(0);
+        terminal.draw(|frame| ui::draw(frame, app))?;
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match handle_key(app, key) {
+            Some(Action::PollAll) => poller.poll_all(),
+            Some(Action::Poll(station_no)) if !app.fleet.get(station_no).is_some_and(|station| station.polling) => poller.poll(station_no),
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Work a key asks the polling side for.
+enum Action {
+    PollAll,
+    Poll(u8),
+}
+
+fn handle_key(app: &mut App, key: KeyEvent) -> Option<Action> {
+    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+        app.quit = true;
+        return None;
+    }
+    if app.editing_filter {
+        match key.code {
+            KeyCode::Enter => app.editing_filter = false,
+            KeyCode::Esc => {
+                app.editing_filter = false;
+                app.fleet.filter.clear();
+            },
+            KeyCode::Backspace => {
+                app.fleet.filter.pop();
+            },
+            KeyCode::Char(character) => app.fleet.filter.push(character),
+            _ => {},
+        }
+        app.selected = 0;
+        return None;
+    }
+    match (app.view, key.code) {
+        (View::Detail(_), KeyCode::Esc | KeyCode::Backspace | KeyCode::Left | KeyCode::Char('q')) => app.view = View::List,
+        (View::Detail(station_no), KeyCode::Char('p')) => return Some(Action::Poll(station_no)),
+        (View::Detail(_), _) => {},
+        (View::List, KeyCode::Char('q')) => app.quit = true,
+        (View::List, KeyCode::Esc) if !app.fleet.filter.is_empty() => app.fleet.filter.clear(),
+        (View::List, KeyCode::Esc) => app.quit = true,
+        (View::List, KeyCode::Up | KeyCode::Char('k')) => app.move_selection(-1),
+        (View::List, KeyCode::Down | KeyCode::Char('j')) => app.move_selection(1),
+        (View::List, KeyCode::Home) => app.selected = 0,
+        (View::List, KeyCode::End) => app.selected = usize::MAX,
+        (View::List, KeyCode::Char('s')) => app.fleet.sort = app.fleet.sort.next(),
+        (View::List, KeyCode::Char('r')) => app.fleet.reverse = !app.fleet.reverse,
+        (View::List, KeyCode::Char('/')) => app.editing_filter = true,
+        (View::List, KeyCode::Char('a')) => return Some(Action::PollAll),
+        (View::List, KeyCode::Char('p')) => return app.selected_station().map(Action::Poll),
+        (View::List, KeyCode::Enter | KeyCode::Right) => {
+            if let Some(station_no) = app.selected_station() {
+                app.view = View::Detail(station_no);
+            }
+        },
+        _ => {},
+    }
+    None
+}
+
+/// The polling side of the dashboard: a thread gathering all stations on the polling schedule, and one thread per station polled on request.
+struct Poller {
+    config: Config,
+    stations: Vec<Station>,
+    cancel: CancelToken,
+    updates: Sender<Update>,
+    poll_now: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Poller {
+    /// Starts polling, returning the receiver of the gathered samples.
+    fn spawn(config: &Config, stations: Vec<Station>) -> (Self, Receiver<Update>) {
+        let cancel = cancel_on_signal();
+        let (updates, receiver) = mpsc::channel();
+        let (poll_now, poll_requests) = mpsc::channel();
+        let thread = {
+            let (config, stations, cancel, updates) = (config.clone(), stations.clone(), cancel.clone(), updates.clone());
+            thread::spawn(move || poll(&config, &stations, &cancel, &poll_requests, &updates))
+        };
+        (Self { config: config.clone(), stations, cancel, updates, poll_now, thread }, receiver)
+    }
+
+    /// Starts the next cycle right away.
+    fn poll_all(&self) {
+        let _ = self.poll_now.send(());
+    }
+
+    /// Gathers a single station outside the polling schedule.
+    fn poll(&self, station_no: u8) {
+        let Some(station) = self.stations.iter().find(|station| station.get_station_no() == station_no).cloned() else {
+            return;
+        };
+        let (timeouts, zone, cancel, updates) = (self.config.timeouts.collect_timeouts(), self.config.time.zone, self.cancel.clone(), self.updates.clone());
+        thread::spawn(move || {
+            let _ = updates.send(Update::Polling(station.get_station_no()));
+            if let Ok(row) = station.gather_data_set_with(&timeouts, &cancel) {
+                let _ = updates.send(Update::Sample(row.in_zone(zone)));
+            }
+        });
+    }
+
+    /// Cancels the running gather steps and waits for the polling thread.
+    fn stop(self) {
+        self.cancel.cancel();
+        drop(self.poll_now);
+        let _ = self.thread.join();
+    }
+}
+
+/// Gathers all stations every `interval` seconds, or right away when something arrives on `poll_requests`.
+fn poll(config: &Config, stations: &[Station], cancel: &CancelToken, poll_requests: &Receiver<()>, updates: &Sender<Update>) {
+    let timeouts = config.timeouts.collect_timeouts();
+    loop {
+        let cycle_start = Instant::now();
+        for station in stations {
+            if updates.send(Update::Polling(station.get_station_no())).is_err() {
+                return;
+            }
+            match station.gather_data_set_with(&timeouts, cancel) {
+                Ok(row) => {
+                    if updates.send(Update::Sample(row.in_zone(config.time.zone))).is_err() {
+                        return;
+                    }
+                },
+                Err(_) => return,
+            }
+        }
+        let next_cycle = cycle_start + Duration::from_secs(config.interval);
+        match poll_requests.recv_timeout(next_cycle.saturating_duration_since(Instant::now())) {
+            Ok(()) | Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        // Requests that came in during the wait are covered by this cycle.
+        while poll_requests.try_recv().is_ok() {}
+    }
+}
@@ -0,0 +1,172 @@
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::symbols::Marker;
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Axis, Block, Cell, Chart, Dataset, GraphType, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+
+use crate::stations::health::HealthState;
+use crate::stations::station::DataRow;
+use crate::tools::math;
+
+use super::fleet::{StationView, HISTORY};
+use super::{App, View};
+
+/// Number of samples in the latency sparkline of the station list.
+const SPARKLINE: usize = 24;
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+pub(super) fn draw(frame: &mut Frame, app: &App) {
+    let [header, body, footer] = Layout::vertical([Constraint::Length(1), Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    let view = match app.view {
+        View::Detail(station_no) => app.fleet.get(station_no),
+        View::List => None,
+    };
+    match view {
+        Some(station) => {
+            frame.render_widget(Line::from(format!(" xbfisher top - station {} ", station.entry.station_no)).bold().reversed(), header);
+            draw_detail(frame, body, station);
+            frame.render_widget(Line::from(" Esc back   p poll now").dim(), footer);
+        },
+        None => {
+            let direction = if app.fleet.reverse { "descending" } else { "ascending" };
+            let mut title = format!(" xbfisher top - {} of {} stations, polled every {} s, sorted by {} {direction}",
+                app.fleet.visible().len(), app.fleet.len(), app.interval, app.fleet.sort.name());
+            if !app.fleet.filter.is_empty() || app.editing_filter {
+                title.push_str(&format!(", filter: {}{}", app.fleet.filter, if app.editing_filter { "_" } else { "" }));
+            }
+            frame.render_widget(Line::from(title).bold().reversed(), header);
+            draw_list(frame, body, app);
+            let help = if app.editing_filter {
+                " Type to filter by number, user, address or state   Enter done   Esc clear"
+            } else {
+                " q quit   ↑↓ select   Enter details   s sort   r reverse   / filter   p poll selected   a poll all"
+            };
+            frame.render_widget(Line::from(help).dim(), footer);
+        },
+    }
+}
+
+fn state_style(state: HealthState) -> Style {
+    let color = match state {
+        HealthState::Up => Color::Green,
+        HealthState::Degraded => Color::Yellow,
+        HealthState::Down => Color::Red,
+        HealthState::Flapping => Color::Magenta,
+        HealthState::Unknown => Color::Gray,
+    };
+    Style::default().fg(color)
+}
+
+fn value(value: Option<f64>) -> String {
+    value.map(|value| math::round(value, 2).to_string()).unwrap_or_else(|| "-".into())
+}
+
+/// The last latencies as block characters scaled to their maximum, "·" for samples without a reply.
+fn sparkline(history: &[&DataRow]) -> String {
+    let latencies: Vec<Option<f64>> = history.iter().rev().take(SPARKLINE).rev().map(|row| row.latency()).collect();
+    let max = latencies.iter().flatten().copied().fold(0.0, f64::max);
+    latencies.iter().map(|latency| match latency {
+        Some(latency) if max > 0.0 => BARS[((latency / max) * (BARS.len() - 1) as f64).round() as usize],
+        Some(_) => BARS[0],
+        None => '·',
+    }).collect()
+}
+
+fn draw_list(frame: &mut Frame, area: Rect, app: &App) {
+    let header = Row::new(["No", "User", "Address", "State", "Latency ms", "Recent latency", "Loss %", "Temp C", "Last sample", "Last error"]).bold();
+    let rows = app.fleet.visible().into_iter().map(|station| {
+        let last = station.last();
+        let history: Vec<&DataRow> = station.history.iter().collect();
+        let state = format!("{}{}", station.state, if station.polling { " *" } else { "" });
+        Row::new(vec![
+            Cell::from(station.entry.station_no.to_string()),
+            Cell::from(station.entry.usr_name.clone()),
+            Cell::from(station.entry.ip_address.clone()),
+            Cell::from(state).style(state_style(station.state)),
+            Cell::from(value(last.and_then(DataRow::latency))),
+            Cell::from(sparkline(&history)).fg(Color::Cyan),
+            Cell::from(value(last.and_then(DataRow::packet_loss))),
+            Cell::from(value(last.and_then(DataRow::cpu_temperature))),
+            Cell::from(last.map(|row| row.time().format("%H:%M:%S").to_string()).unwrap_or_else(|| "-".into())),
+            Cell::from(station.last_error().unwrap_or_default().to_string()).fg(Color::Red),
+        ])
+    });
+    let widths = [
+        Constraint::Length(3),
+        Constraint::Length(12),
+        Constraint::Length(15),
+        Constraint::Length(10),
+        Constraint::Length(10),
+        Constraint::Length(SPARKLINE as u16),
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Length(11),
+        Constraint::Min(10),
+    ];
+    let table = Table::new(rows, widths)
+        .header(header)
+        .block(Block::bordered())
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut state = TableState::default().with_selected(Some(app.selected));
+    if app.fleet.is_empty() {
+        frame.render_widget(Paragraph::new("No stations in the hosts file.").block(Block::bordered()), area);
+    } else {
+        frame.render_stateful_widget(table, area, &mut state);
+    }
+}
+
+fn draw_detail(frame: &mut Frame, area: Rect, station: &StationView) {
+    let [summary, charts, lists] = Layout::vertical([Constraint::Length(3), Constraint::Percentage(45), Constraint::Min(5)]).areas(area);
+    let since = station.events.back().map(|event| format!(" since {}", event.time.format("%Y-%m-%d %H:%M:%S"))).unwrap_or_default();
+    let text = Line::from(vec![
+        Span::raw(format!("{}@{}   ", station.entry.usr_name, station.entry.ip_address)),
+        Span::styled(format!("{}{since}", station.state), state_style(station.state).bold()),
+        Span::raw(if station.polling { "   polling..." } else { "" }),
+    ]);
+    frame.render_widget(Paragraph::new(text).block(Block::bordered().title(format!(" Station {} ", station.entry.station_no))), summary);
+
+    let [latency, temperature] = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(charts);
+    draw_chart(frame, latency, station, " Latency (ms) ", DataRow::latency, Color::Cyan);
+    draw_chart(frame, temperature, station, " CPU temperature (C) ", DataRow::cpu_temperature, Color::LightRed);
+
+    let [samples, events] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(lists);
+    let rows = station.history.iter().rev().map(|row| Row::new(vec![
+        Cell::from(row.time().format("%H:%M:%S").to_string()),
+        Cell::from(value(row.latency())),
+        Cell::from(value(row.packet_loss())),
+        Cell::from(value(row.cpu_temperature())),
+        Cell::from(row.error().unwrap_or_default().to_string()).fg(Color::Red),
+    ]));
+    let table = Table::new(rows, [Constraint::Length(8), Constraint::Length(10), Constraint::Length(6), Constraint::Length(6), Constraint::Min(10)])
+        .header(Row::new(["Time", "Latency ms", "Loss %", "Temp C", "Error"]).bold())
+        .block(Block::bordered().title(format!(" Last {} samples ", station.history.len())));
+    frame.render_widget(table, samples);
+    let lines: Vec<Line> = station.events.iter().rev().map(|event| Line::from(vec![
+        Span::raw(format!("{} ", event.time.format("%H:%M:%S"))),
+        Span::styled(event.to.to_string(), state_style(event.to)),
+        Span::raw(format!(" after {} s: {}", event.duration.as_secs(), event.reason)),
+    ])).collect();
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" State changes ")), events);
+}
+
+/// A line chart of a metric over the kept history, x being the position in the history.
+fn draw_chart(frame: &mut Frame, area: Rect, station: &StationView, title: &str, metric: fn(&DataRow) -> Option<f64>, color: Color) {
+    let points: Vec<(f64, f64)> = station.history.iter().enumerate().filter_map(|(i, row)| metric(row).map(|value| (i as f64, value))).collect();
+    let block = Block::bordered().title(title.to_string());
+    if points.is_empty() {
+        frame.render_widget(Paragraph::new("No values yet.").block(block), area);
+        return;
+    }
+    let min = points.iter().map(|point| point.1).fold(f64::MAX, f64::min);
+    let max = points.iter().map(|point| point.1).fold(f64::MIN, f64::max);
+    let (low, high) = if max - min < 1e-9 { (min - 1.0, max + 1.0) } else { (min, max) };
+    let first = station.history.front().map(|row| row.time().format("%H:%M:%S").to_string()).unwrap_or_default();
+    let last = station.history.back().map(|row| row.time().format("%H:%M:%S").to_string()).unwrap_or_default();
+    let dataset = Dataset::default().marker(Marker::Braille).graph_type(GraphType::Line).style(Style::default().fg(color)).data(&points);
+    let chart = Chart::new(vec![dataset])
+        .block(block)
+        .x_axis(Axis::default().bounds([0.0, (station.history.len().max(2) - 1).min(HISTORY) as f64]).labels([first, last]))
+        .y_axis(Axis::default().bounds([low, high]).labels([math::round(low, 1).to_string(), math::round(high, 1).to_string()]));
+    frame.render_widget(chart, area);
+}
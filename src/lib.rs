@@ -1,10 +1,48 @@
+//! Monitoring of the latency, packet loss and CPU temperature of remote stations.
+//!
+//! The library API follows semver. Its entry points are re-exported at the crate root:
+//! [`Station`] (built with [`Station::builder`]) gathers a [`DataRow`], [`ping()`] sends a single echo request
+//! configured by [`PingOptions`], and [`Monitor`] runs gather cycles, handing them to a callback or a channel.
+//! Every fallible function returns [`Error`].
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use xbfisher::{Monitor, Station};
+//!
+//! let station = Station::builder(1, "10.10.1.2").user("pi").build()?;
+//! let monitor = Monitor::new(vec![station]).interval(Duration::from_secs(30)).spawn();
+//! for batch in monitor.cycles().iter().take(3) {
+//!     for row in &batch.rows {
+//!         println!("{row}");
+//!     }
+//! }
+//! monitor.stop()?;
+//! # Ok::<(), xbfisher::Error>(())
+//! ```
+
+pub mod alerting;
+pub mod analysis;
+pub mod daemon;
+pub mod dashboard;
+pub mod monitor;
+pub mod storage;
 mod pinging;
 mod stations;
 mod tools;
 
-pub use crate::pinging::ping;
+pub use crate::monitor::{Cycles, Monitor, MonitorHandle};
+pub use crate::pinging::ping::{self, ping, PingOptions, PingReply};
+pub use crate::stations::station::{CollectTimeouts, DataRow, Station, StationBuilder};
 pub use crate::tools::errors::Error;
 pub use crate::tools::errors;
 pub use crate::stations::station;
 pub use crate::stations::commands;
-pub use crate::tools::math;
\ No newline at end of file
+pub use crate::stations::discovery;
+pub use crate::stations::groups;
+pub use crate::stations::health;
+pub use crate::stations::hosts;
+pub use crate::tools::math;
+pub use crate::tools::config;
+pub use crate::tools::deadline;
+pub use crate::tools::output;
+pub use crate::tools::time;
\ No newline at end of file
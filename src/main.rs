@@ -1,21 +1,232 @@
-use std::env;
-
-use xbfisher::commands::{start_data_from_ip, start_data_from_list};
-
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 || &args[1] == &"-h".to_owned(){
-        println!("XBFisher 1.0\nUsage: xbfisher [job] [options] <destination/parameters>
-log:\n Can log the data from specified stations in the log file or in the parameters.
-    -s: starts data logging from a specified ip address. Usage:\n    xbfisher log -s <user name> <ip_address> <interval>
-    -l: starts logging from the hosts file into a csv document. Usage:\n    xbfisher log -l <interval>");
-    } else if &args[1] == &"log".to_owned(){
-        match args[2].as_str() {
-            "-s" => {if args.len() == 5{start_data_from_ip(&args[3], &args[4], &args[5])}else{println!("log -s option requires an ip address and an interval.\nSee the output of 'xbfisher -h' for a summary of options.")}},
-            "-l" => {if args.len() == 4{start_data_from_list(&args[3])}else{println!("log -l option requires an interval.\nSee the output of 'xbfisher -h' for a summary of options.")}},
-            _ => println!("log option requires an argument.\nSee the output of 'xbfisher -h' for a summary of options.")
-        }
-    } else {
-        println!("Unknown Command. See the output of 'xbfisher -h' for a summary of options.");
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+
+use xbfisher::analysis::stats::Query;
+use xbfisher::commands;
+use xbfisher::config::{Config, DEFAULT_CONFIG_PATH};
+use xbfisher::daemon;
+use xbfisher::discovery::{Cidr, DiscoverOptions};
+use xbfisher::groups::Selector;
+use xbfisher::dashboard;
+use xbfisher::output::{OutputFormat, Printer};
+use xbfisher::time::parse_bound;
+use xbfisher::Error;
+
+/// Exit code of a command that ran but failed, e.g. an unreachable station or a failed delivery.
+const EXIT_FAILURE: u8 = 1;
+/// Exit code of an invalid or unreadable config file. Invalid arguments exit with 2.
+const EXIT_CONFIG: u8 = 3;
+
+/// Logs latency, packet loss and CPU temperature of the stations and analyzes the logged data.
+#[derive(Parser)]
+#[command(name = "xbfisher", version, after_help = "Exit codes: 0 success, 1 the command failed, 2 invalid arguments, 3 invalid config file.")]
+struct Cli {
+    /// Config file, the defaults are used if it doesn't exist.
+    #[arg(short, long, global = true, default_value = DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+    /// text, json or csv. Streaming commands like log and daemon print one JSON object per line; messages go to stderr unless text.
+    #[arg(short, long, global = true, default_value = "text")]
+    output: OutputFormat,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Logs the stations of the hosts file, or a single one, to the configured sinks until SIGINT/SIGTERM.
+    Log {
+        /// Station number in the hosts file, all stations if neither it, --ip nor --select is given.
+        #[arg(short, long, conflicts_with = "ip")]
+        station: Option<u8>,
+        /// Only the stations of the hosts file matching the selector, e.g. site=north,role!=relay or group=field.
+        #[arg(long, conflicts_with_all = ["station", "ip"])]
+        select: Option<Selector>,
+        /// Address of a station that is not in the hosts file, logged as station 99.
+        #[arg(long)]
+        ip: Option<IpAddr>,
+        /// ssh user of the station given with --ip.
+        #[arg(short, long, default_value = "pi", requires = "ip")]
+        user: String,
+        /// Seconds between two gathers of every station, by default the interval of its [[group]] or `interval` of the config file.
+        #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: Option<u64>,
+    },
+    /// Pings a station and prints every reply.
+    Ping {
+        #[command(flatten)]
+        target: Target,
+        /// Number of echo requests.
+        #[arg(short = 'n', long, default_value_t = 5, value_parser = clap::value_parser!(u16).range(1..))]
+        count: u16,
+    },
+    /// Gathers and prints the current data of a station.
+    Get {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Lists the stations of the hosts file.
+    Stations {
+        /// Only the stations matching the selector.
+        #[arg(long)]
+        select: Option<Selector>,
+    },
+    /// Logs the stations of the hosts file until SIGTERM/SIGINT, reloading the configuration on SIGHUP.
+    Daemon {
+        /// Pidfile written on start and removed on exit, overrides [daemon] pidfile.
+        #[arg(short, long)]
+        pidfile: Option<PathBuf>,
+    },
+    /// Shows a live overview of the stations of the hosts file, polled every `interval` seconds of the config file.
+    Top {
+        /// Only the stations matching the selector.
+        #[arg(long)]
+        select: Option<Selector>,
+    },
+    /// Sweeps an IPv4 range for stations and compares the hosts that answer with the hosts file.
+    Discover {
+        /// Range in CIDR notation, e.g. 10.10.0.0/16, at most a /16.
+        range: Cidr,
+        /// Echo requests waiting for their reply at once.
+        #[arg(short = 'j', long, default_value_t = 64, value_parser = clap::value_parser!(u16).range(1..))]
+        concurrency: u16,
+        /// Echo requests sent per second at most.
+        #[arg(short, long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+        rate: u32,
+        /// Milliseconds to wait for each echo reply.
+        #[arg(short, long, default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+        timeout: u64,
+        /// Check the ssh port of the hosts that answer and read their host name.
+        #[arg(long)]
+        ssh: bool,
+        /// ssh user reading the host names, and of the written entries.
+        #[arg(short, long, default_value = "pi")]
+        user: String,
+        /// Add the new hosts to the hosts file and update the address of the changed stations.
+        #[arg(short, long)]
+        write: bool,
+    },
+    /// Sends a test alert through the configured notifiers.
+    NotifyTest {
+        /// Only test the notifier with this name.
+        #[arg(short, long)]
+        notifier: Option<String>,
+    },
+    /// Prints latency, packet loss and temperature statistics, uptime and outages per station from the logged .csv files.
+    Stats {
+        #[command(flatten)]
+        data: DataArgs,
+    },
+    /// Writes a self-contained HTML report with latency and temperature charts, a packet loss heatmap, an outage timeline and summary tables.
+    Report {
+        #[command(flatten)]
+        data: DataArgs,
+        /// File the report is written to.
+        #[arg(default_value = "./report.html")]
+        file: PathBuf,
+    },
+}
+
+/// A station, by its number in the hosts file or by address, or the stations picked by a selector.
+#[derive(Args)]
+struct Target {
+    /// Station number in the hosts file.
+    #[arg(required_unless_present_any = ["ip", "select"])]
+    station: Option<u8>,
+    /// The stations of the hosts file matching the selector, e.g. site=north,role!=relay.
+    #[arg(long, conflicts_with_all = ["station", "ip"])]
+    select: Option<Selector>,
+    /// Address of a station that is not in the hosts file.
+    #[arg(long, conflicts_with = "station")]
+    ip: Option<IpAddr>,
+    /// ssh user of the station given with --ip.
+    #[arg(short, long, default_value = "pi", requires = "ip")]
+    user: String,
+}
+
+/// Which logged data stats and report read.
+#[derive(Args)]
+struct DataArgs {
+    /// Directory of the .csv files, the one of the first csv sink by default.
+    #[arg(short, long)]
+    directory: Option<PathBuf>,
+    /// Start of the range: a day (2024-10-27), a local time (2024-10-27T13:00) or an RFC 3339 timestamp.
+    #[arg(short, long)]
+    from: Option<String>,
+    /// End of the range, like --from; a day given here is included.
+    #[arg(short, long)]
+    to: Option<String>,
+    /// Only these stations, e.g. 1,3.
+    #[arg(short, long, value_delimiter = ',')]
+    stations: Vec<u8>,
+    /// Only the stations of the hosts file matching the selector, e.g. site=north,role!=relay.
+    #[arg(long, conflicts_with = "stations")]
+    select: Option<Selector>,
+}
+
+impl DataArgs {
+    /// Builds the query, interpreting local times in the configured time zone. Exits with a usage error on invalid times.
+    /// A selector is resolved to the numbers of the stations it picks in the hosts file.
+    fn query(&self, config: &Config) -> Result<Query, Error> {
+        let bound = |text: &Option<String>, end| text.as_deref().map(|text| parse_bound(text, end, config.time.zone).unwrap_or_else(|| {
+            Cli::command().error(ErrorKind::ValueValidation, format!("invalid time \"{text}\", use 2024-10-27, 2024-10-27T13:00 or an RFC 3339 timestamp")).exit()
+        }));
+        let stations = match &self.select {
+            Some(selector) => commands::select_entries(config, selector)?.iter().map(|entry| entry.station_no).collect(),
+            None => self.stations.clone(),
+        };
+        Ok(Query { from: bound(&self.from, false), to: bound(&self.to, true), stations })
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let output = cli.output;
+    if let Command::Daemon { pidfile } = &cli.command {
+        // The daemon loads the config itself, so it can reload it on SIGHUP.
+        return exit_code(daemon::run(&cli.config, pidfile.as_deref(), output), output);
+    }
+    let config = match Config::load(&cli.config) {
+        Ok(config) => config,
+        Err(error) => return exit_code(Err(error), output),
+    };
+    let result = match cli.command {
+        Command::Log { station: Some(station), interval, .. } => commands::start_data_from_no(&config, station, interval, output),
+        Command::Log { ip: Some(ip), user, interval, .. } => commands::start_data_from_ip(&config, &user, ip, interval, output),
+        Command::Log { select, interval, .. } => commands::start_data_from_list(&config, &select.unwrap_or_default(), interval, output),
+        Command::Ping { target: Target { station: Some(station), .. }, count } => commands::ping_station(&config, station, count, output),
+        Command::Ping { target: Target { ip: Some(ip), user, .. }, count } => commands::ping_station_from_ip(&user, ip, count, output),
+        Command::Ping { target: Target { select: Some(selector), .. }, count } => commands::ping_selected(&config, &selector, count, output),
+        Command::Get { target: Target { station: Some(station), .. } } => commands::get_current_data_from_no(&config, station, output),
+        Command::Get { target: Target { ip: Some(ip), user, .. } } => commands::get_current_data_from_ip(&config, &user, ip, output),
+        Command::Get { target: Target { select: Some(selector), .. } } => commands::get_current_data_from_selector(&config, &selector, output),
+        Command::Ping { .. } | Command::Get { .. } => unreachable!("clap requires a station number, --ip or --select"),
+        Command::Stations { select } => commands::list_stations(&config, &select.unwrap_or_default(), output),
+        Command::Daemon { .. } => unreachable!("handled above"),
+        Command::Top { select } => dashboard::run(&config, &select.unwrap_or_default()),
+        Command::Discover { range, concurrency, rate, timeout, ssh, user, write } => {
+            let options = DiscoverOptions { concurrency: concurrency.into(), rate, timeout: Duration::from_millis(timeout), ssh, user, ..DiscoverOptions::default() };
+            commands::discover(&config, &range, &options, write, output)
+        },
+        Command::NotifyTest { notifier } => commands::notify_test(&config, notifier.as_deref(), output),
+        Command::Stats { data } => data.query(&config).and_then(|query| commands::stats(&config, data.directory.as_deref(), &query, output)),
+        Command::Report { data, file } => data.query(&config).and_then(|query| commands::report(&config, data.directory.as_deref(), &query, &file, output)),
+    };
+    exit_code(result, output)
+}
+
+/// Reports the error of a failed command and picks its exit code. Invalid hosts file entries count as config errors.
+fn exit_code(result: Result<(), Error>, output: OutputFormat) -> ExitCode {
+    let Err(error) = result else {
+        return ExitCode::SUCCESS;
+    };
+    Printer::new(output).message(format!("Error: {error}"));
+    match error {
+        Error::ConfigError { .. } | Error::InvalidAddress { .. } => ExitCode::from(EXIT_CONFIG),
+        _ => ExitCode::from(EXIT_FAILURE),
     }
-}
\ No newline at end of file
+}
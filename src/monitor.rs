@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::stations::groups::Schedule;
+use crate::stations::health::{HealthThresholds, HealthTracker};
+use crate::stations::hosts;
+use crate::stations::station::{CollectTimeouts, DataRow, Station};
+use crate::storage::sink::Batch;
+use crate::tools::config::Config;
+use crate::tools::deadline::CancelToken;
+use crate::tools::errors::Error;
+use crate::tools::time::TimeZoneSetting;
+
+/// Most stations gathered at the same time.
+const MAX_CONCURRENT_GATHERS: usize = 32;
+
+/// Gathers a sample of every station each `interval`, or at the interval set for it, and tracks their health, handing every cycle to a callback or channel.
+/// Unlike the logger and the daemon it writes nothing; results only go to the caller.
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    stations: Vec<Station>,
+    interval: Duration,
+    station_intervals: BTreeMap<u8, Duration>,
+    timeouts: CollectTimeouts,
+    health: HealthThresholds,
+    zone: TimeZoneSetting,
+}
+
+impl Monitor {
+    /// A monitor of the stations polling every 60 s with the default timeouts, health thresholds and local time.
+    pub fn new(stations: Vec<Station>) -> Self {
+        Self { stations, interval: Duration::from_secs(60), station_intervals: BTreeMap::new(), timeouts: CollectTimeouts::default(), health: HealthThresholds::default(), zone: TimeZoneSetting::default() }
+    }
+
+    /// A monitor of the stations of the configured hosts file, using the intervals of the config and its groups, its timeouts, health thresholds and time zone.
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        let entries = hosts::load(&config.hosts)?;
+        let stations = entries.iter().map(Station::from_entry).collect::<Result<Vec<Station>, Error>>()?;
+        Ok(Self::with_config(stations, config))
+    }
+
+    /// A monitor of the given stations, using the intervals of the config and its groups, its timeouts, health thresholds and time zone.
+    pub fn with_config(stations: Vec<Station>, config: &Config) -> Self {
+        let intervals: Vec<(u8, Duration)> = stations.iter().map(|station| (station.get_station_no(), Duration::from_secs(config.interval_of(&station.entry())))).collect();
+        Self::new(stations)
+            .interval(Duration::from_secs(config.interval))
+            .station_intervals(intervals)
+            .timeouts(config.timeouts.collect_timeouts())
+            .health(config.health.clone())
+            .time_zone(config.time.zone)
+    }
+
+    /// Time between the starts of two gathers of a station, 60 s by default.
+    /// A cycle lasts until its slowest station answered or timed out, up to the probe and ssh timeouts together,
+    /// and a cycle running longer than the interval of a station delays its next gather.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Gathers the given stations at intervals of their own instead of `interval`, e.g. those of a [[group]] with an interval.
+    pub fn station_intervals(mut self, intervals: impl IntoIterator<Item = (u8, Duration)>) -> Self {
+        self.station_intervals.extend(intervals);
+        self
+    }
+
+    /// Deadlines of the remote steps of every sample.
+    pub fn timeouts(mut self, timeouts: CollectTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// When a station counts as degraded or down.
+    pub fn health(mut self, health: HealthThresholds) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Time zone of the sample timestamps.
+    pub fn time_zone(mut self, zone: TimeZoneSetting) -> Self {
+        self.zone = zone;
+        self
+    }
+
+    pub fn stations(&self) -> &[Station] {
+        &self.stations
+    }
+
+    /// Gathers one sample of every station.
+    pub fn sample(&self, cancel: &CancelToken) -> Result<Vec<DataRow>, Error> {
+        self.sample_stations(self.stations.iter(), cancel)
+    }
+
+    /// Gathers the stations side by side, at most MAX_CONCURRENT_GATHERS at a time, so one that doesn't answer only holds up
+    /// the cycle until its timeouts instead of delaying every station after it. The samples are in the order of the stations.
+    fn sample_stations<'a>(&self, stations: impl Iterator<Item = &'a Station>, cancel: &CancelToken) -> Result<Vec<DataRow>, Error> {
+        let mut stations = stations.peekable();
+        let mut rows = vec![];
+        while stations.peek().is_some() {
+            let batch: Vec<&Station> = stations.by_ref().take(MAX_CONCURRENT_GATHERS).collect();
+            let gathered: Vec<Result<DataRow, Error>> = thread::scope(|scope| {
+                let gathers: Vec<_> = batch.iter().map(|station| scope.spawn(|| station.gather_data_set_with(&self.timeouts, cancel))).collect();
+                gathers.into_iter().map(|gather| gather.join().unwrap_or(Err(Error::InternalError))).collect()
+            });
+            for row in gathered {
+                rows.push(row?.in_zone(self.zone));
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Runs gather cycles until `cancel` is triggered, calling `on_cycle` with the samples and state changes of each.
+    /// A cycle gathers the stations that are due, so with station intervals a batch may hold only some of them.
+    /// A cancelled cycle is discarded and ends the run with Ok. An error returned by `on_cycle` ends the run with that error.
+    pub fn run(&self, cancel: &CancelToken, mut on_cycle: impl FnMut(Batch) -> Result<(), Error>) -> Result<(), Error> {
+        let mut cycles = Cycles::new(self.clone());
+        loop {
+            match cycles.run_due(cancel, |_| {}) {
+                Ok(batch) => on_cycle(batch)?,
+                Err(Error::Cancelled) => return Ok(()),
+                Err(error) => return Err(error),
+            }
+            if cancel.sleep(cycles.next_due().saturating_duration_since(Instant::now())).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn schedule(&self) -> Schedule {
+        Schedule::new(self.stations.iter().map(|station| {
+            let station_no = station.get_station_no();
+            (station_no, self.station_intervals.get(&station_no).copied().unwrap_or(self.interval))
+        }))
+    }
+
+    /// Runs the monitor on a thread of its own, sending every cycle to the returned handle.
+    pub fn spawn(self) -> MonitorHandle {
+        let cancel = CancelToken::new();
+        let (sender, receiver) = mpsc::channel();
+        let thread = {
+            let cancel = cancel.clone();
+            // A dropped receiver ends the run like a cancel.
+            thread::spawn(move || self.run(&cancel, |batch| sender.send(batch).map_err(|_| Error::Cancelled)))
+        };
+        MonitorHandle { cancel, receiver, thread }
+    }
+}
+
+/// The gather cycles of a monitor driven one at a time by the caller, e.g. to do other work between them like the daemon.
+/// Keeps the health of the stations and when each of them is due.
+#[derive(Debug, Clone)]
+pub struct Cycles {
+    monitor: Monitor,
+    health: HealthTracker,
+    schedule: Schedule,
+}
+
+impl Cycles {
+    /// Cycles of the monitor, with all stations due right away.
+    pub fn new(monitor: Monitor) -> Self {
+        Self { health: HealthTracker::new(monitor.health.clone()), schedule: monitor.schedule(), monitor }
+    }
+
+    pub fn monitor(&self) -> &Monitor {
+        &self.monitor
+    }
+
+    /// The health of the stations as of the last cycle.
+    pub fn health(&self) -> &HealthTracker {
+        &self.health
+    }
+
+    /// Gathers the stations that are due, calling `before_each` ahead of each of them as their gathers start, e.g. to feed a watchdog,
+    /// and returns their samples with the state changes. Fails with Error::Cancelled once `cancel` is triggered.
+    pub fn run_due(&mut self, cancel: &CancelToken, mut before_each: impl FnMut(&Station)) -> Result<Batch, Error> {
+        let due = self.schedule.take_due(Instant::now());
+        let stations = self.monitor.stations.iter().filter(|station| due.contains(&station.get_station_no())).inspect(|station| before_each(station));
+        let rows = self.monitor.sample_stations(stations, cancel)?;
+        let events = self.health.observe_all(&rows, self.monitor.zone.now());
+        Ok(Batch::new(rows, events))
+    }
+
+    /// Takes the stations due at `now` like `run_due`, observing the given rows instead of gathering them.
+    #[cfg(test)]
+    pub(crate) fn observe_due(&mut self, now: Instant, rows: &[DataRow]) -> Vec<u8> {
+        let due = self.schedule.take_due(now);
+        self.health.observe_all(rows, self.monitor.zone.now());
+        due
+    }
+
+    /// When the next station is due, one interval from now without stations.
+    pub fn next_due(&self) -> Instant {
+        self.schedule.next_due().unwrap_or_else(|| Instant::now() + self.monitor.interval)
+    }
+
+    /// Continues with another monitor, e.g. after the configuration was reloaded.
+    /// Stations no longer monitored are forgotten, the others keep their health state and are due one interval after their last gather.
+    pub fn reconfigure(&mut self, monitor: Monitor) {
+        self.health.thresholds = monitor.health.clone();
+        self.health.retain(&monitor.stations.iter().map(Station::get_station_no).collect::<Vec<u8>>());
+        let mut schedule = monitor.schedule();
+        schedule.carry_over(&self.schedule);
+        self.schedule = schedule;
+        self.monitor = monitor;
+    }
+}
+
+/// A monitor running on its own thread, see `Monitor::spawn`.
+pub struct MonitorHandle {
+    cancel: CancelToken,
+    receiver: Receiver<Batch>,
+    thread: JoinHandle<Result<(), Error>>,
+}
+
+impl MonitorHandle {
+    /// Receives the cycles as they complete, e.g. `for batch in handle.cycles().iter()`.
+    pub fn cycles(&self) -> &Receiver<Batch> {
+        &self.receiver
+    }
+
+    /// Token that stops the monitor when cancelled, e.g. from a signal handler.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Stops the monitor, discarding a running cycle, and returns the error it stopped with, if any.
+    pub fn stop(self) -> Result<(), Error> {
+        self.cancel.cancel();
+        drop(self.receiver);
+        match self.thread.join() {
+            Ok(Err(Error::Cancelled)) => Ok(()),
+            Ok(result) => result,
+            Err(_) => Err(Error::InternalError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(station_no: u8, role: Option<&str>) -> Station {
+        let builder = Station::builder(station_no, format!("10.0.0.{station_no}"));
+        match role {
+            Some(role) => builder.tag("role", role),
+            None => builder,
+        }.build().unwrap()
+    }
+
+    fn config(field_interval: u64) -> Config {
+        toml::from_str(&format!(
+            "interval = 60\n\n\
+            [[group]]\nname = \"field\"\nselect = \"role=field\"\ninterval = {field_interval}\n\n\
+            [[group]]\nname = \"relays\"\nselect = \"role=relay\"\ninterval = 30\n\n\
+            [[group]]\nname = \"watched\"\nselect = \"no=4\"\ninterval = 5\n"
+        )).unwrap()
+    }
+
+    /// The stations gathered at each second from `start` up to `seconds`.
+    fn gathers(cycles: &mut Cycles, start: Instant, seconds: u64) -> Vec<(u64, Vec<u8>)> {
+        (0..=seconds).map(|second| (second, cycles.schedule.take_due(start + Duration::from_secs(second)))).filter(|(_, due)| !due.is_empty()).collect()
+    }
+
+    #[test]
+    fn gathers_each_station_at_the_interval_of_its_groups() {
+        let stations = vec![station(1, Some("field")), station(2, None), station(3, Some("relay")), station(4, Some("field"))];
+        let mut cycles = Cycles::new(Monitor::with_config(stations, &config(10)));
+        let start = Instant::now();
+        let gathers = gathers(&mut cycles, start, 60);
+        for (station_no, times) in [(1, 7), (2, 2), (3, 3), (4, 13)] {
+            assert_eq!(gathers.iter().filter(|(_, due)| due.contains(&station_no)).count(), times, "station {station_no}: {gathers:?}");
+        }
+        assert_eq!(gathers[0], (0, vec![1, 2, 3, 4]));
+        assert_eq!(gathers[1], (5, vec![4]));
+        assert_eq!(gathers[2], (10, vec![1, 4]));
+        assert_eq!(gathers.last(), Some(&(60, vec![1, 2, 3, 4])));
+        assert_eq!(cycles.next_due(), start + Duration::from_secs(65));
+    }
+
+    #[test]
+    fn a_reload_keeps_when_the_stations_were_last_gathered() {
+        let stations = vec![station(1, Some("field")), station(2, None), station(3, Some("relay")), station(4, Some("field"))];
+        let mut cycles = Cycles::new(Monitor::with_config(stations, &config(10)));
+        let start = Instant::now();
+        gathers(&mut cycles, start, 10);
+        let stations = vec![station(1, Some("field")), station(3, Some("relay")), station(4, Some("field")), station(5, None)];
+        cycles.reconfigure(Monitor::with_config(stations, &config(20)));
+        assert_eq!(cycles.monitor().stations().len(), 4);
+        assert_eq!(cycles.schedule.take_due(start + Duration::from_secs(11)), [5]);
+        assert_eq!(cycles.next_due(), start + Duration::from_secs(15));
+        assert_eq!(cycles.schedule.take_due(start + Duration::from_secs(15)), [4]);
+        assert_eq!(cycles.schedule.take_due(start + Duration::from_secs(20)), [4]);
+        assert_eq!(cycles.schedule.take_due(start + Duration::from_secs(30)), [1, 3, 4]);
+    }
+
+    #[test]
+    fn without_stations_the_next_cycle_is_one_interval_away() {
+        let cycles = Cycles::new(Monitor::new(vec![]).interval(Duration::from_secs(30)));
+        let before = Instant::now();
+        let next_due = cycles.next_due();
+        assert!(next_due >= before + Duration::from_secs(30) && next_due <= Instant::now() + Duration::from_secs(30));
+    }
+}
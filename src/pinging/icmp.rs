@@ -51,7 +51,7 @@ impl<'a> EchoRequest<'a> {
         buffer[6] = (self.seq_cnt >> 8) as u8;
         buffer[7] = self.seq_cnt as u8;
 
-        if let Err(_) = (&mut buffer[8..]).write(self.payload) {
+        if (&mut buffer[8..]).write(self.payload).is_err() {
             return Err(Error::InvalidSize);
         }
 
@@ -68,7 +68,7 @@ pub struct EchoReply<'a> {
 
 impl<'a> EchoReply<'a> {
     pub fn decode<P: Proto>(buffer: &'a [u8]) -> Result<Self, Error> {
-        if buffer.as_ref().len() < HEADER_SIZE {
+        if buffer.len() < HEADER_SIZE {
             return Err(Error::InvalidSize);
         }
 
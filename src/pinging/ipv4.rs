@@ -56,7 +56,7 @@ impl<'a> IpV4Packet<'a> {
         };
 
         Ok(Self {
-            protocol: protocol,
+            protocol,
             data: &data[header_size..],
         })
     }
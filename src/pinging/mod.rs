@@ -2,7 +2,8 @@ mod icmp;
 mod ipv4;
 
 pub mod ping;
+pub mod train;
 
 pub use self::icmp::{EchoReply, EchoRequest, IcmpV4, IcmpV6, HEADER_SIZE as ICMP_HEADER_SIZE};
 
-pub use self::ipv4::IpV4Packet;
+pub use self::ipv4::{IpV4Packet, IpV4Protocol};
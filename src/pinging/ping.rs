@@ -1,188 +1,447 @@
-use std::io::Read;
+use std::fmt;
+use std::io::{self, ErrorKind, Read};
 use std::net::{IpAddr, SocketAddr};
-use std::time::{Duration,SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use rand::random;
+use serde::Serialize;
 use socket2::{Domain, Protocol, Socket, Type};
 
+use crate::tools::deadline::CancelToken;
 use crate::tools::errors::Error;
-use crate::pinging::{EchoReply, EchoRequest, IcmpV4, IcmpV6, IpV4Packet, ICMP_HEADER_SIZE};
+use crate::pinging::train::{self, Jitter, Probe, TrainMetrics};
+use crate::pinging::{EchoReply, EchoRequest, IcmpV4, IcmpV6, IpV4Packet, IpV4Protocol, ICMP_HEADER_SIZE};
 use crate::stations::station::Station;
-use crate::tools::math;
+use crate::tools::math::{self, RunningStats};
 
 const TOKEN_SIZE: usize = 32;
-const ECHO_REQUEST_BUFFER_SIZE: usize = ICMP_HEADER_SIZE + TOKEN_SIZE;
-type Token = [u8; TOKEN_SIZE];
+pub(crate) const ECHO_REQUEST_BUFFER_SIZE: usize = ICMP_HEADER_SIZE + TOKEN_SIZE;
+pub(crate) type Token = [u8; TOKEN_SIZE];
 
-pub struct PingReturn{
-    time: Duration,
-    seq_cnt: u16,
+/// Outcome of a series of pings: how many probes went out, the latencies (ms) of the answered ones and every probe of the train.
+#[derive(Debug, Clone, Default)]
+pub struct ProbeSummary{
+    pub sent: u16,
+    pub latency: Vec<f32>,
+    /// The probes by sequence number, with the order their replies arrived in.
+    pub probes: Vec<Probe>,
+}
+
+impl ProbeSummary{
+    /// Fraction of unanswered probes between 0 and 1, or None if nothing was sent.
+    pub fn loss(&self) -> Option<f32>{
+        if self.sent == 0 {
+            return None;
+        }
+        Some((self.sent as usize).saturating_sub(self.latency.len()) as f32 / self.sent as f32)
+    }
+
+    /// Mean, deviation and range of the answered probes.
+    pub fn latency_stats(&self) -> RunningStats{
+        self.latency.iter().map(|&latency| latency as f64).collect()
+    }
+
+    /// Jitter, delay variation, reordering and loss bursts of the probes, continuing the jitter estimate of earlier trains.
+    pub fn train_metrics(&self, jitter: &mut Jitter) -> TrainMetrics{
+        TrainMetrics::of(&self.probes, jitter)
+    }
 }
 
-pub fn ping(
-    addr: IpAddr,
-    timeout: Option<Duration>,
-    ttl: Option<u32>,
+/// Settings of a single echo request, e.g. `PingOptions::new().timeout(Duration::from_secs(2)).ttl(32)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PingOptions{
+    timeout: Duration,
+    ttl: u32,
     ident: Option<u16>,
-    seq_cnt: Option<u16>,
-    payload: Option<&Token>,
-) -> Result<PingReturn, Error> {
-    let time_start = SystemTime::now();
+    seq: u16,
+    payload: Option<Token>,
+}
 
-    let timeout = match timeout {
-        Some(timeout) => timeout,
-        None => Duration::from_secs(4),
-    };
+impl Default for PingOptions{
+    fn default() -> Self{
+        Self { timeout: Duration::from_secs(4), ttl: 64, ident: None, seq: 1, payload: None }
+    }
+}
+
+impl PingOptions{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    /// How long to wait for the reply, 4 s by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self{
+        self.timeout = timeout;
+        self
+    }
+
+    /// Time to live of the request, the hop limit for IPv6. 64 by default.
+    pub fn ttl(mut self, ttl: u32) -> Self{
+        self.ttl = ttl;
+        self
+    }
+
+    /// Identifier of the request, random by default.
+    pub fn ident(mut self, ident: u16) -> Self{
+        self.ident = Some(ident);
+        self
+    }
+
+    /// Sequence number of the request, 1 by default.
+    pub fn seq(mut self, seq: u16) -> Self{
+        self.seq = seq;
+        self
+    }
+
+    /// Payload of the request, random by default.
+    pub fn payload(mut self, payload: [u8; TOKEN_SIZE]) -> Self{
+        self.payload = Some(payload);
+        self
+    }
+}
+
+/// The answer to an echo request sent by `ping`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PingReply{
+    address: IpAddr,
+    seq: u16,
+    time: Duration,
+}
+
+impl PingReply{
+    /// Address the request was sent to.
+    pub fn address(&self) -> IpAddr{
+        self.address
+    }
+
+    /// Sequence number of the reply.
+    pub fn seq(&self) -> u16{
+        self.seq
+    }
+
+    /// Round trip time.
+    pub fn time(&self) -> Duration{
+        self.time
+    }
+
+    /// Round trip time in ms.
+    pub fn time_ms(&self) -> f64{
+        self.time.as_secs_f64() * 1000.0
+    }
+}
+
+/// Sends one echo request to `addr` and waits for its reply.
+/// Needs permission to open raw sockets, e.g. root or CAP_NET_RAW.
+pub fn ping(addr: IpAddr, options: &PingOptions) -> Result<PingReply, Error> {
+    let time_start = Instant::now();
+    let timeout = options.timeout;
 
     let dest = SocketAddr::new(addr, 0);
     let mut buffer = [0; ECHO_REQUEST_BUFFER_SIZE];
 
-    let default_payload: &Token = &random();
+    let payload: Token = options.payload.unwrap_or_else(random);
 
     let request = EchoRequest {
-        ident: ident.unwrap_or(random()),
-        seq_cnt: seq_cnt.unwrap_or(1),
-        payload: payload.unwrap_or(default_payload),
+        ident: options.ident.unwrap_or_else(random),
+        seq_cnt: options.seq,
+        payload: &payload,
+    };
+
+    encode_request(dest, &request, &mut buffer)?;
+    let mut socket = open_socket(dest, options.ttl)?;
+
+    socket.set_write_timeout(Some(timeout))?;
+
+    socket.send_to(&buffer, &dest.into())?;
+
+    let read = |buffer: &mut [u8], wait: Duration| {
+        socket.set_read_timeout(Some(wait))?;
+        socket.read(buffer)
     };
+    let (seq, time) = await_reply(read, &request, dest.is_ipv4(), time_start, timeout)?;
+    Ok(PingReply{ address: addr, seq, time })
+}
 
-    let mut socket = if dest.is_ipv4() {
-        if request.encode::<IcmpV4>(&mut buffer[..]).is_err() {
-            return Err(Error::InternalError.into());
+/// Reads packets with `read`, which waits at most the given time for one, until the reply to `request` arrives.
+/// Returns its sequence number and round trip time. Other and undecodable packets are skipped without extending the
+/// `timeout` counted from `time_start`.
+fn await_reply(
+    mut read: impl FnMut(&mut [u8], Duration) -> io::Result<usize>,
+    request: &EchoRequest,
+    ipv4: bool,
+    time_start: Instant,
+    timeout: Duration,
+) -> Result<(u16, Duration), Error> {
+    let mut buffer: [u8; 2048] = [0; 2048];
+    loop {
+        let time_elapsed = time_start.elapsed();
+        let remaining = timeout.saturating_sub(time_elapsed);
+        if remaining.is_zero() {
+            return Err(Error::Timeout { operation: "ping".into(), duration: time_elapsed });
         }
-        Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?
-    } else {
-        if request.encode::<IcmpV6>(&mut buffer[..]).is_err() {
-            return Err(Error::InternalError.into());
+
+        let size = match read(&mut buffer, remaining) {
+            Ok(size) => size,
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(Error::Timeout { operation: "ping".into(), duration: time_start.elapsed() });
+            },
+            Err(error) => return Err(error.into()),
+        };
+
+        let Ok(Some(reply)) = decode_reply(&buffer[..size], ipv4) else {
+            continue;
+        };
+        if reply.ident == request.ident && reply.payload.starts_with(request.payload) {
+            return Ok((reply.seq_cnt, time_start.elapsed()));
         }
-        Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?
+    }
+}
+
+/// Encodes the request into `buffer` as ICMP for IPv4 or ICMPv6.
+pub(crate) fn encode_request(dest: SocketAddr, request: &EchoRequest, buffer: &mut [u8]) -> Result<(), Error> {
+    let encoded = match dest.is_ipv4() {
+        true => request.encode::<IcmpV4>(buffer),
+        false => request.encode::<IcmpV6>(buffer),
     };
+    encoded.map_err(|_| Error::InternalError)
+}
 
+/// Opens a raw ICMP socket for `dest` sending with the given ttl (hop limit for IPv6).
+pub(crate) fn open_socket(dest: SocketAddr, ttl: u32) -> Result<Socket, Error> {
     if dest.is_ipv4() {
-        socket.set_ttl(ttl.unwrap_or(64))?;
+        let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?;
+        socket.set_ttl(ttl)?;
+        Ok(socket)
     } else {
-        socket.set_unicast_hops_v6(ttl.unwrap_or(64))?;
+        let socket = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?;
+        socket.set_unicast_hops_v6(ttl)?;
+        Ok(socket)
     }
+}
 
-    socket.set_write_timeout(Some(timeout))?;
+/// Decodes a packet read from a raw socket. None for packets that are no echo reply.
+pub(crate) fn decode_reply(buffer: &[u8], ipv4: bool) -> Result<Option<EchoReply<'_>>, Error> {
+    if !ipv4 {
+        return Ok(EchoReply::decode::<IcmpV6>(buffer).ok());
+    }
+    let ipv4_packet = match IpV4Packet::decode(buffer) {
+        Ok(packet) => packet,
+        Err(_) => return Err(Error::DecodeV4Error),
+    };
+    if ipv4_packet.protocol != IpV4Protocol::Icmp {
+        return Ok(None);
+    }
+    Ok(EchoReply::decode::<IcmpV4>(ipv4_packet.data).ok())
+}
 
-    socket.send_to(&mut buffer, &dest.into())?;
+/// Outcome of one echo request sent by `ping_station`, answered or not.
+#[derive(Debug, Clone, Serialize)]
+pub struct PingAttempt{
+    address: IpAddr,
+    seq: u16,
+    ttl: u32,
+    time_ms: Option<f32>,
+    error: Option<String>,
+}
 
-    // Loop until either an echo with correct ident was received or timeout is over
-    let mut time_elapsed = Duration::from_secs(0);
-    loop {
-        socket.set_read_timeout(Some(timeout - time_elapsed))?;
-
-        let mut buffer: [u8; 2048] = [0; 2048];
-        let mut seq_cnt_r = 0;
-        socket.read(&mut buffer)?;
-
-        let reply = if dest.is_ipv4() {
-            let ipv4_packet = match IpV4Packet::decode(&buffer) {
-                Ok(packet) => packet,
-                Err(_) => return Err(Error::DecodeV4Error.into()),
-            };
-            match EchoReply::decode::<IcmpV4>(ipv4_packet.data) {
-                Ok(reply) => {seq_cnt_r = reply.seq_cnt; reply},
-                Err(_) => continue,
-            }
-        } else {
-            match EchoReply::decode::<IcmpV6>(&buffer) {
-                Ok(reply) => reply,
-                Err(_) => continue,
-            }
-        };
+impl PingAttempt{
+    pub fn address(&self) -> IpAddr{
+        self.address
+    }
 
-        if reply.ident == request.ident {
-            time_elapsed = match SystemTime::now().duration_since(time_start) {
-                Ok(reply) => reply,
-                Err(_) => return Err(Error::InternalError.into()),
-            };
-            // Received correct ident
-            return Ok(PingReturn{time: time_elapsed, seq_cnt: seq_cnt_r});
-        }
+    pub fn seq(&self) -> u16{
+        self.seq
+    }
 
-        // If ident is not correct check if timeout is over
-        time_elapsed = match SystemTime::now().duration_since(time_start) {
-            Ok(reply) => reply,
-            Err(_) => return Err(Error::InternalError.into()),
-        };
-        if time_elapsed >= timeout {
-            let error = std::io::Error::new(std::io::ErrorKind::TimedOut, "Timeout occured");
-            return Err(Error::IoError { error: (error) });
+    pub fn ttl(&self) -> u32{
+        self.ttl
+    }
+
+    /// Round trip time in ms, None if the request failed.
+    pub fn time_ms(&self) -> Option<f32>{
+        self.time_ms
+    }
+
+    /// Why the request failed.
+    pub fn error(&self) -> Option<&str>{
+        self.error.as_deref()
+    }
+}
+
+impl fmt::Display for PingAttempt{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match (self.time_ms, &self.error) {
+            (Some(time), _) => write!(f, "32 bytes from {}: icmp_seq={} ttl={} time={time} ms", self.address, self.seq, self.ttl),
+            (None, error) => write!(f, "Problem during pinging {}. icmp_seq={} Error: {}", self.address, self.seq, error.as_deref().unwrap_or_default()),
         }
     }
 }
 
-pub fn ping_station(station: &Station, ping_count: u16) -> Vec<f32>{
+/// Totals of a `ping_station` run, the latency figures are None if no request was answered.
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct PingStatistics{
+    pub address: IpAddr,
+    pub transmitted: u16,
+    pub received: u16,
+    pub loss_pct: f32,
+    pub time_ms: f32,
+    pub min_ms: Option<f32>,
+    pub avg_ms: Option<f32>,
+    pub max_ms: Option<f32>,
+    pub mdev_ms: Option<f32>,
+    pub jitter_ms: Option<f32>,
+    pub ipdv_mean_abs_ms: Option<f32>,
+    pub ipdv_max_ms: Option<f32>,
+    pub reordered: u16,
+    pub loss_bursts: u16,
+    pub max_loss_burst: u16,
+}
+
+impl fmt::Display for PingStatistics{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        writeln!(f, "{} packets transmitted, {} recieved, {}% packet loss, time {} ms", self.transmitted, self.received, self.loss_pct, self.time_ms)?;
+        let show = |value: Option<f32>| value.unwrap_or_default();
+        writeln!(f, "min/avg/max/mdev = {}/{}/{}/{} ms", show(self.min_ms), show(self.avg_ms), show(self.max_ms), show(self.mdev_ms))?;
+        write!(f, "jitter/mean |ipdv|/max |ipdv| = {}/{}/{} ms, {} reordered, {} loss bursts (longest {})", show(self.jitter_ms), show(self.ipdv_mean_abs_ms), show(self.ipdv_max_ms), self.reordered, self.loss_bursts, self.max_loss_burst)
+    }
+}
+
+/// Pings the station `ping_count` times, one request per second, handing every outcome to `on_reply` as it arrives.
+/// Fails only if the station has an invalid address.
+pub fn ping_station(station: &Station, ping_count: u16, mut on_reply: impl FnMut(&PingAttempt)) -> Result<PingStatistics, Error>{
     let time_start = SystemTime::now();
-    let addr = station.get_ip_address().parse().expect("If we are able to create a Station type, the IPAdress must be correct.");
+    let addr = station.address()?;
     let timeout = Duration::from_secs(2);
     let mut seq_cnt= 1;
     let mut success_counter: u16 = 0;
     let mut fail_counter: u16 = 0;
-    let mut latency: Vec<f32> = Vec::new();
+    let mut latency = RunningStats::new();
+    let mut probes = vec![];
     let ttl: u32 = 64;
     let interval: u64 = 1;
     while success_counter + fail_counter < ping_count {
-        match ping(
-            addr,
-            Some(timeout),
-            Some(ttl),
-            Some(3),
-            Some(seq_cnt),
-            Some(&random()),
-        ){
+        let sent = SystemTime::now().duration_since(time_start).unwrap_or_default();
+        match ping(addr, &PingOptions::new().timeout(timeout).ttl(ttl).ident(3).seq(seq_cnt)){
             Ok(a) => {
-                latency.push(math::n_decimals(a.time.as_micros() as f32 / 1000.0, 4));
-                seq_cnt = a.seq_cnt;
-                println!("32 bytes from {addr}: icmp_seq={} ttl={} time={} ms",seq_cnt, ttl, math::n_decimals(a.time.as_micros() as f32 /1000.0, 4));
-                seq_cnt = seq_cnt+1;
-                success_counter = success_counter + 1;
+                let time_ms = math::n_decimals(a.time_ms() as f32, 4);
+                latency.push(time_ms as f64);
+                seq_cnt = a.seq();
+                probes.push(Probe{ seq: seq_cnt, sent, rtt: Some(a.time()), arrival: Some(success_counter as usize) });
+                on_reply(&PingAttempt{ address: addr, seq: seq_cnt, ttl, time_ms: Some(time_ms), error: None });
+                seq_cnt += 1;
+                success_counter += 1;
             },
             Err(error) => {
-                println!("Problem during pinging {}. icmp_seq={} Error: {error}",station.get_ip_address(), seq_cnt);
-                seq_cnt = seq_cnt + 1;
-                fail_counter = fail_counter + 1;
+                on_reply(&PingAttempt{ address: addr, seq: seq_cnt, ttl, time_ms: None, error: Some(error.to_string()) });
+                probes.push(Probe{ seq: seq_cnt, sent, rtt: None, arrival: None });
+                seq_cnt += 1;
+                fail_counter += 1;
                 continue;
             },
         }
         std::thread::sleep(Duration::from_secs(interval));
     }
-    let viter = latency.iter();
-    let avg = math::vec_mean(&latency);
-    let mdev = math::vec_mdev(&latency);
-    println!("{ping_count} packets transmitted, {success_counter} recieved, {}% packet loss, time {} ms", math::n_decimals((fail_counter/ping_count) as f32 *100.0, 4), math::n_decimals(SystemTime::now().duration_since(time_start).unwrap_or_else(|_|{Duration::from_secs(0)}).as_micros() as f32 / 1000.0, 4));
-    println!("min/avg/max/mdev = {}/{}/{}/{} ms", math::n_decimals(*viter.clone().min_by(|x, y| x.partial_cmp(&y).unwrap()).unwrap_or(&0.0), 4), math::n_decimals(avg, 4), math::n_decimals(*viter.clone().max_by(|x, y| x.partial_cmp(&y).unwrap()).unwrap_or(&0.0), 4), math::n_decimals(mdev, 4));
-    latency
+    let rounded = |value: Option<f64>| value.map(|value| math::n_decimals(value as f32, 4));
+    let train = TrainMetrics::of(&probes, &mut Jitter::new());
+    Ok(PingStatistics{
+        address: addr,
+        transmitted: ping_count,
+        received: success_counter,
+        loss_pct: math::n_decimals(fail_counter as f32 / ping_count.max(1) as f32 * 100.0, 4),
+        time_ms: math::n_decimals(SystemTime::now().duration_since(time_start).unwrap_or_default().as_micros() as f32 / 1000.0, 4),
+        min_ms: latency.min().map(|min| min as f32),
+        avg_ms: rounded(latency.mean()),
+        max_ms: latency.max().map(|max| max as f32),
+        mdev_ms: rounded(latency.stddev()),
+        jitter_ms: rounded(train.jitter_ms),
+        ipdv_mean_abs_ms: rounded(train.ipdv_mean_abs_ms),
+        ipdv_max_ms: rounded(train.ipdv_max_ms),
+        reordered: train.reordered,
+        loss_bursts: train.loss_bursts,
+        max_loss_burst: train.max_loss_burst,
+    })
 }
 
-pub fn ping_station_silent(station: &Station, ping_count: u16) -> Vec<f32>{
-    let addr = station.get_ip_address().parse().expect("If we are able to create a Station type, the IP Adress must be correct.");
+/// Pings the station without printing, sending the requests one second apart without waiting for the replies so reordering shows.
+/// Gives up once `deadline` is over or `cancel` is triggered.
+/// Returns the probes gathered so far on a deadline if at least one probe was answered, otherwise the timeout error.
+pub fn ping_station_silent(station: &Station, ping_count: u16, deadline: Duration, cancel: &CancelToken) -> Result<ProbeSummary, Error>{
+    let time_start = Instant::now();
+    let addr = station.address()?;
     let timeout = Duration::from_secs(2);
-    let mut success_counter: u16 = 0;
-    let mut fail_counter: u16 = 0;
-    let mut latency = Vec::new();
-    let ttl: u32 = 64;
-    let interval: u64 = 1;
-    while success_counter + fail_counter < ping_count {
-        match ping(
-            addr,
-            Some(timeout),
-            Some(ttl),
-            Some(3),
-            Some(5),
-            Some(&random()),
-        ){
-            Ok(a) => {
-                latency.push(a.time.as_micros() as f32 / 1000.0);
-                success_counter = success_counter + 1;
-            },
-            Err(_) => {
-                fail_counter = fail_counter + 1;
-                continue;
-            },
+    let interval = Duration::from_secs(1);
+    let probes = train::probe_train(addr, ping_count, interval, timeout, deadline, cancel)?;
+    let latency: Vec<f32> = probes.iter().filter_map(|probe| probe.rtt_ms()).map(|time_ms| time_ms as f32).collect();
+    if latency.is_empty() && time_start.elapsed() >= deadline {
+        return Err(Error::Timeout { operation: "ping".into(), duration: time_start.elapsed() });
+    }
+    Ok(ProbeSummary{ sent: probes.len() as u16, latency, probes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const REQUEST: EchoRequest<'static> = EchoRequest { ident: 0x1234, seq_cnt: 7, payload: &[0xab; TOKEN_SIZE] };
+
+    /// An IPv4 packet carrying the ICMP message of the given type, ident and payload.
+    fn packet(icmp_type: u8, ident: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 1, 0, 0, 127, 0, 0, 1, 127, 0, 0, 1];
+        packet.extend([icmp_type, 0, 0, 0, (ident >> 8) as u8, ident as u8, 0, REQUEST.seq_cnt as u8]);
+        packet.extend(payload);
+        packet
+    }
+
+    /// Reads the packets in turn after a short delay, starting over after the last one.
+    fn replay(packets: Vec<Vec<u8>>) -> impl FnMut(&mut [u8], Duration) -> io::Result<usize> {
+        let mut next = packets.into_iter().cycle();
+        move |buffer, wait| {
+            thread::sleep(wait.min(Duration::from_millis(5)));
+            let packet = next.next().unwrap();
+            buffer[..packet.len()].copy_from_slice(&packet);
+            Ok(packet.len())
         }
-        std::thread::sleep(Duration::from_secs(interval));
     }
-    latency
-}
\ No newline at end of file
+
+    fn other_packets() -> Vec<Vec<u8>> {
+        vec![
+            vec![0xff; 3],
+            vec![0x60; 40],
+            packet(3, REQUEST.ident, REQUEST.payload),
+            packet(0, REQUEST.ident + 1, REQUEST.payload),
+            packet(0, REQUEST.ident, &[0; TOKEN_SIZE]),
+        ]
+    }
+
+    #[test]
+    fn times_out_while_other_and_garbage_packets_arrive() {
+        let timeout = Duration::from_millis(150);
+        let time_start = Instant::now();
+        let result = await_reply(replay(other_packets()), &REQUEST, true, time_start, timeout);
+        let Err(Error::Timeout { duration, .. }) = result else {
+            panic!("expected a timeout, got {result:?}");
+        };
+        assert!(duration >= timeout, "{duration:?}");
+        assert!(time_start.elapsed() < Duration::from_secs(1), "{:?}", time_start.elapsed());
+    }
+
+    #[test]
+    fn skips_other_packets_until_the_reply() {
+        let mut packets = other_packets();
+        packets.push(packet(0, REQUEST.ident, REQUEST.payload));
+        let (seq, time) = await_reply(replay(packets), &REQUEST, true, Instant::now(), Duration::from_secs(5)).unwrap();
+        assert_eq!(seq, REQUEST.seq_cnt);
+        assert!(time < Duration::from_secs(1), "{time:?}");
+    }
+
+    #[test]
+    fn a_read_timeout_is_a_timeout() {
+        let read = |_: &mut [u8], wait: Duration| {
+            thread::sleep(wait);
+            Err(io::Error::from(ErrorKind::WouldBlock))
+        };
+        let result = await_reply(read, &REQUEST, true, Instant::now(), Duration::from_millis(20));
+        assert!(matches!(result, Err(Error::Timeout { .. })), "{result:?}");
+    }
+}
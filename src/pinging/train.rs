@@ -0,0 +1,264 @@
+//! Probe trains: a series of echo requests sent at a fixed interval without waiting for the replies, so replies can overtake
+//! each other, and the delay variation, reordering and loss bursts computed from their sequence numbers.
+
+use std::io::{ErrorKind, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use rand::random;
+use serde::Serialize;
+
+use crate::pinging::ping::{self, Token, ECHO_REQUEST_BUFFER_SIZE};
+use crate::pinging::EchoRequest;
+use crate::tools::deadline::CancelToken;
+use crate::tools::errors::Error;
+use crate::tools::math::RunningStats;
+
+/// Longest wait for a reply before checking `cancel` again.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// One echo request of a train.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Probe{
+    /// Sequence number, 1 for the first request of the train.
+    pub seq: u16,
+    /// When the request was sent, from the start of the train.
+    pub sent: Duration,
+    /// Round trip time, None if no reply arrived within the timeout.
+    pub rtt: Option<Duration>,
+    /// Position of the reply among all replies of the train, 0 for the first to arrive.
+    pub arrival: Option<usize>,
+}
+
+impl Probe{
+    /// Round trip time in ms.
+    pub fn rtt_ms(&self) -> Option<f64>{
+        self.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0)
+    }
+}
+
+/// Sends `count` echo requests to `addr`, one each `interval`, and collects the replies arriving within `timeout` of their request.
+/// Gives up once `deadline` is over, counting the requests still unanswered as lost, or when `cancel` is triggered.
+/// Needs permission to open raw sockets, e.g. root or CAP_NET_RAW.
+pub fn probe_train(addr: IpAddr, count: u16, interval: Duration, timeout: Duration, deadline: Duration, cancel: &CancelToken) -> Result<Vec<Probe>, Error>{
+    let time_start = Instant::now();
+    let dest = SocketAddr::new(addr, 0);
+    let ident: u16 = random();
+    let payload: Token = random();
+    let mut probes: Vec<Probe> = Vec::with_capacity(count as usize);
+    let mut arrivals = 0;
+    let mut socket = ping::open_socket(dest, 64)?;
+    socket.set_write_timeout(Some(timeout))?;
+    loop {
+        cancel.check()?;
+        let elapsed = time_start.elapsed();
+        if elapsed >= deadline {
+            break;
+        }
+        let next_send = (probes.len() < count as usize).then(|| interval * probes.len() as u32);
+        if next_send.is_some_and(|at| elapsed >= at) {
+            let seq = probes.len() as u16 + 1;
+            let mut buffer = [0; ECHO_REQUEST_BUFFER_SIZE];
+            let request = EchoRequest { ident, seq_cnt: seq, payload: &payload };
+            let sent = time_start.elapsed();
+            ping::encode_request(dest, &request, &mut buffer)?;
+            socket.send_to(&buffer, &dest.into())?;
+            probes.push(Probe { seq, sent, rtt: None, arrival: None });
+            continue;
+        }
+        let wait_until = match (next_send, probes.last()) {
+            (Some(at), _) => at,
+            (None, Some(last)) if arrivals < probes.len() => last.sent + timeout,
+            (None, _) => break,
+        };
+        if elapsed >= wait_until {
+            break;
+        }
+        socket.set_read_timeout(Some(wait_until.min(deadline).saturating_sub(elapsed).clamp(Duration::from_millis(1), POLL_INTERVAL)))?;
+        let mut buffer: [u8; 2048] = [0; 2048];
+        let size = match socket.read(&mut buffer) {
+            Ok(size) => size,
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(error) => return Err(error.into()),
+        };
+        let received = time_start.elapsed();
+        let Ok(Some(reply)) = ping::decode_reply(&buffer[..size], dest.is_ipv4()) else {
+            continue;
+        };
+        if reply.ident != ident || !reply.payload.starts_with(&payload) {
+            continue;
+        }
+        let Some(probe) = probes.iter_mut().find(|probe| probe.seq == reply.seq_cnt && probe.rtt.is_none()) else {
+            continue;
+        };
+        let rtt = received.saturating_sub(probe.sent);
+        if rtt <= timeout {
+            probe.rtt = Some(rtt);
+            probe.arrival = Some(arrivals);
+            arrivals += 1;
+        }
+    }
+    Ok(probes)
+}
+
+/// RFC 3550 interarrival jitter, J += (|D| - J) / 16 with D the difference of the round trip times of two replies in the order they arrived.
+/// Kept across trains like the estimate of an RTP receiver, so it settles over a few samples instead of restarting at 0 with each.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Jitter{
+    estimate: f64,
+    last: Option<f64>,
+    differences: u64,
+}
+
+impl Jitter{
+    pub fn new() -> Self{
+        Self::default()
+    }
+
+    /// Adds the round trip time (ms) of the next reply to arrive.
+    pub fn push(&mut self, rtt_ms: f64){
+        if let Some(last) = self.last {
+            self.estimate += ((rtt_ms - last).abs() - self.estimate) / 16.0;
+            self.differences += 1;
+        }
+        self.last = Some(rtt_ms);
+    }
+
+    /// The jitter in ms, None before the second reply.
+    pub fn estimate(&self) -> Option<f64>{
+        (self.differences > 0).then_some(self.estimate)
+    }
+}
+
+/// Delay variation, reordering and loss bursts of a probe train.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+#[non_exhaustive]
+pub struct TrainMetrics{
+    /// RFC 3550 interarrival jitter in ms, None with fewer than two replies so far.
+    pub jitter_ms: Option<f64>,
+    /// Mean |IPDV| in ms: the average of the absolute RFC 3393 IP packet delay variations between the replies to consecutive requests.
+    /// The signed IPDV of the RFC averages to about 0 over a train, so only its magnitude is kept. None without such a pair.
+    pub ipdv_mean_abs_ms: Option<f64>,
+    /// Max |IPDV| in ms, the largest absolute IP packet delay variation.
+    pub ipdv_max_ms: Option<f64>,
+    /// Replies that arrived after the reply to a later request.
+    pub reordered: u16,
+    /// Runs of consecutive unanswered requests.
+    pub loss_bursts: u16,
+    /// Length of the longest run of unanswered requests.
+    pub max_loss_burst: u16,
+}
+
+impl TrainMetrics{
+    /// Computes the metrics of the probes, sorted by sequence number, feeding their replies to `jitter` in the order they arrived.
+    pub fn of(probes: &[Probe], jitter: &mut Jitter) -> Self{
+        let mut replies: Vec<&Probe> = probes.iter().filter(|probe| probe.arrival.is_some()).collect();
+        replies.sort_by_key(|probe| probe.arrival);
+        let mut reordered = 0;
+        let mut highest_seq = 0;
+        for probe in &replies {
+            if let Some(rtt) = probe.rtt_ms() {
+                jitter.push(rtt);
+            }
+            if probe.seq < highest_seq {
+                reordered += 1;
+            }
+            highest_seq = highest_seq.max(probe.seq);
+        }
+        let ipdv: RunningStats = probes.windows(2).filter(|pair| pair[1].seq == pair[0].seq + 1).filter_map(|pair| match (pair[0].rtt_ms(), pair[1].rtt_ms()) {
+            (Some(first), Some(second)) => Some((second - first).abs()),
+            _ => None,
+        }).collect();
+        let mut loss_bursts = 0;
+        let mut max_loss_burst = 0;
+        let mut burst = 0;
+        for probe in probes {
+            match probe.rtt {
+                Some(_) => burst = 0,
+                None => {
+                    burst += 1;
+                    if burst == 1 {
+                        loss_bursts += 1;
+                    }
+                    max_loss_burst = max_loss_burst.max(burst);
+                },
+            }
+        }
+        Self { jitter_ms: jitter.estimate(), ipdv_mean_abs_ms: ipdv.mean(), ipdv_max_ms: ipdv.max(), reordered, loss_bursts, max_loss_burst }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A train from the send and receive times (ms) of its requests, None for a lost one.
+    fn train(times: &[(u64, Option<u64>)]) -> Vec<Probe> {
+        let mut received: Vec<u64> = times.iter().filter_map(|(_, received)| *received).collect();
+        received.sort();
+        times.iter().enumerate().map(|(i, &(sent, reply))| Probe {
+            seq: i as u16 + 1,
+            sent: Duration::from_millis(sent),
+            rtt: reply.map(|reply| Duration::from_millis(reply - sent)),
+            arrival: reply.map(|reply| received.iter().position(|&time| time == reply).unwrap()),
+        }).collect()
+    }
+
+    #[test]
+    fn jitter_and_ipdv_of_replies_in_order() {
+        // Round trip times 10, 20, 15 and 15 ms.
+        let probes = train(&[(0, Some(10)), (1000, Some(1020)), (2000, Some(2015)), (3000, Some(3015))]);
+        let mut jitter = Jitter::new();
+        let metrics = TrainMetrics::of(&probes, &mut jitter);
+        // J = 10/16, then J += (5 - J)/16, then J += (0 - J)/16.
+        let expected = 0.625 + (5.0 - 0.625) / 16.0;
+        let expected = expected - expected / 16.0;
+        assert!((metrics.jitter_ms.unwrap() - expected).abs() < 1e-9, "{metrics:?}");
+        assert_eq!((metrics.ipdv_mean_abs_ms, metrics.ipdv_max_ms), (Some(5.0), Some(10.0)));
+        assert_eq!((metrics.reordered, metrics.loss_bursts, metrics.max_loss_burst), (0, 0, 0));
+        // The next train goes on from the estimate, 15 to 25 ms adds a difference of 10.
+        let next = TrainMetrics::of(&train(&[(0, Some(25))]), &mut jitter);
+        assert!((next.jitter_ms.unwrap() - (expected + (10.0 - expected) / 16.0)).abs() < 1e-9, "{next:?}");
+        assert_eq!(next.ipdv_mean_abs_ms, None);
+    }
+
+    #[test]
+    fn jitter_needs_two_replies() {
+        let mut jitter = Jitter::new();
+        assert_eq!(jitter.estimate(), None);
+        jitter.push(12.0);
+        assert_eq!(jitter.estimate(), None);
+        jitter.push(12.0);
+        assert_eq!(jitter.estimate(), Some(0.0));
+    }
+
+    #[test]
+    fn counts_replies_overtaken_by_later_ones() {
+        // Round trip times 100, 20, 20 and 5 ms, so the replies arrive as 2, 4, 3, 1.
+        let probes = train(&[(0, Some(100)), (10, Some(30)), (20, Some(40)), (30, Some(35))]);
+        assert_eq!(probes.iter().map(|probe| probe.arrival.unwrap()).collect::<Vec<_>>(), [3, 0, 2, 1]);
+        let metrics = TrainMetrics::of(&probes, &mut Jitter::new());
+        assert_eq!(metrics.reordered, 2);
+        // The jitter follows the arrival order, 20, 5, 20, 100, the IPDV the sequence numbers, 100, 20, 20, 5.
+        let mut expected = 0.0;
+        for difference in [15.0, 15.0, 80.0] {
+            expected += (difference - expected) / 16.0;
+        }
+        assert!((metrics.jitter_ms.unwrap() - expected).abs() < 1e-9, "{metrics:?}");
+        assert!((metrics.ipdv_mean_abs_ms.unwrap() - 95.0 / 3.0).abs() < 1e-9, "{metrics:?}");
+        assert_eq!(metrics.ipdv_max_ms, Some(80.0));
+    }
+
+    #[test]
+    fn counts_runs_of_lost_requests() {
+        let replies = [true, false, false, true, false, true, false, false, false, true];
+        let times: Vec<(u64, Option<u64>)> = replies.iter().enumerate().map(|(i, &reply)| (i as u64 * 1000, reply.then_some(i as u64 * 1000 + 10))).collect();
+        let metrics = TrainMetrics::of(&train(&times), &mut Jitter::new());
+        assert_eq!((metrics.loss_bursts, metrics.max_loss_burst, metrics.reordered), (3, 3, 0));
+        // No two consecutive requests were answered.
+        assert_eq!((metrics.ipdv_mean_abs_ms, metrics.ipdv_max_ms), (None, None));
+        assert_eq!(metrics.jitter_ms, Some(0.0));
+        let lost = TrainMetrics::of(&train(&[(0, None), (1000, None), (2000, None)]), &mut Jitter::new());
+        assert_eq!((lost.loss_bursts, lost.max_loss_burst, lost.jitter_ms), (1, 3, None));
+    }
+}
@@ -1,76 +1,463 @@
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-use regex::Regex;
+use serde::Serialize;
+use signal_hook::consts::{SIGINT, SIGTERM};
 
 use crate::station::Station;
-use crate::station;
-use crate::tools::filecontrol;
-
-pub fn parse_config(args: &[String]) -> (&str, &str, &str){
-    let command = &args[1];
-    let parameter1 = &args[2];
-    let parameter2 = &args[3];
-    (command, parameter1, parameter2)
-}
-
-pub fn start_data_from_no(stat_no: u8){
-    let station = Station::connect_station(stat_no);
-    let datavec = vec![station.gather_data_set()];
-    filecontrol::write_data(datavec);
-}
-
-pub fn start_data_from_ip(usrname: &String, ipaddr: &String, interval: &String){
-    let station = Station::connect_station_by_ip(99, usrname, ipaddr);
-    loop{
-        let datavec = vec![station.gather_data_set()];
-        filecontrol::write_data(datavec);
-        std::thread::sleep(Duration::from_secs(interval.parse().unwrap()));
-    }
-}
-
-/// Parses the "./hosts.txt" file and writes the gathered data from the stations named in "./hosts.txt" into a .csv file named after the date.
-/// If no "./hosts.txt" exists, creates the file and panics.
-/// interval: u64: designates the interval between different data retrievals in seconds.
-pub fn start_data_from_list(interval: &String){
-    let mut svec: Vec<Station> = vec![];
-    if let Ok(lines) = filecontrol::read_lines("./hosts".into()) {
-        // Consumes the iterator, returns a String
-        for line in lines.flatten() {
-            let com = Regex::new(r"^[#]").unwrap();
-            if !line.is_empty() && !com.is_match(&line){
-                let linecut: Vec<&str> = line.split(" -").collect();
-                svec.push(Station::connect_station_by_ip(linecut[0].parse().unwrap(), &linecut[1].into(), &linecut[2].into()));
-            };
+use crate::alerting::notifier;
+use crate::alerting::{AlertEvent, AlertState};
+use crate::analysis::anomaly;
+use crate::analysis::report;
+use crate::analysis::stats::{self, Query, Summary};
+use crate::config::Config;
+use crate::stations::discovery::{self, Cidr, DiscoverOptions};
+use crate::stations::groups::Selector;
+use crate::stations::hosts::{self, HostEntry};
+use crate::storage::reader;
+use crate::pinging::ping::{PingAttempt, PingStatistics};
+use crate::monitor::Monitor;
+use crate::storage::sink::{FanOut, Record};
+use crate::tools::deadline::CancelToken;
+use crate::tools::output::{OutputFormat, Printer};
+use crate::Error;
+
+/// Returns a cancel token that is triggered by SIGINT or SIGTERM, so a running gather cycle can be aborted from the terminal.
+pub fn cancel_on_signal() -> CancelToken{
+    let cancel = CancelToken::new();
+    for signal in [SIGINT, SIGTERM]{
+        if let Err(error) = signal_hook::flag::register(signal, cancel.flag()){
+            eprintln!("Problem registering the handler for signal {signal}. Error: {error}");
+        }
+    }
+    cancel
+}
+
+/// Connects to a hosts file entry. Only text output checks whether the station answers, as that check prints its outcome.
+fn connect_entry(entry: &HostEntry, printer: &Printer) -> Result<Station, Error>{
+    match printer.format(){
+        OutputFormat::Text => Station::connect_entry(entry),
+        _ => Station::from_entry(entry),
+    }
+}
+
+/// Connects to the station with number `stat_no` in the hosts file.
+fn connect_from_hosts(config: &Config, stat_no: u8, printer: &Printer) -> Result<Station, Error>{
+    let entries = hosts::load(&config.hosts)?;
+    match entries.iter().find(|entry| entry.station_no == stat_no){
+        Some(entry) => connect_entry(entry, printer),
+        None => Err(Error::UnknownStation { station_no: stat_no, path: config.hosts.display().to_string() }),
+    }
+}
+
+/// The stations of the hosts file picked by the selector, all of them for the empty selector.
+/// Fails with Error::NoStationSelected if a selector picks none.
+pub fn select_entries(config: &Config, selector: &Selector) -> Result<Vec<HostEntry>, Error>{
+    let entries: Vec<HostEntry> = hosts::load(&config.hosts)?.into_iter().filter(|entry| config.selects(selector, entry)).collect();
+    if entries.is_empty() && !selector.is_empty(){
+        return Err(Error::NoStationSelected { selector: selector.to_string(), path: config.hosts.display().to_string() });
+    }
+    Ok(entries)
+}
+
+/// Connects to a station that is not in the hosts file as station 99.
+fn connect_ip(usrname: &str, ipaddr: IpAddr, printer: &Printer) -> Result<Station, Error>{
+    connect_entry(&HostEntry{ station_no: 99, usr_name: usrname.to_string(), ip_address: ipaddr.to_string(), hostname: None, tags: BTreeMap::new() }, printer)
+}
+
+/// Logs the station with number `stat_no` in the hosts file every `interval` seconds, see `log_stations`, until SIGINT or SIGTERM.
+pub fn start_data_from_no(config: &Config, stat_no: u8, interval: Option<u64>, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    let station = connect_from_hosts(config, stat_no, &printer)?;
+    log_stations(config, vec![station], interval, &mut printer)
+}
+
+/// Logs the station at `ipaddr` as station 99 every `interval` seconds, see `log_stations`, until SIGINT or SIGTERM.
+pub fn start_data_from_ip(config: &Config, usrname: &str, ipaddr: IpAddr, interval: Option<u64>, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    let station = connect_ip(usrname, ipaddr, &printer)?;
+    log_stations(config, vec![station], interval, &mut printer)
+}
+
+/// Logs the stations of the hosts file picked by the selector every `interval` seconds to the configured sinks (by default a .csv file named after the date).
+/// If the hosts file doesn't exist, creates a commented one to fill in.
+pub fn start_data_from_list(config: &Config, selector: &Selector, interval: Option<u64>, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    hosts::load_or_create(&config.hosts)?;
+    let svec = select_entries(config, selector)?.iter().map(|entry| connect_entry(entry, &printer)).collect::<Result<Vec<Station>, Error>>()?;
+    log_stations(config, svec, interval, &mut printer)
+}
+
+/// Gathers data from the stations every `interval` seconds and writes it with their state changes to the configured sinks.
+/// Without an `interval`, each station is gathered at the interval of its groups, or the configured one.
+/// Text output shows the state changes; JSON output prints every sample and state change as a line of its own, csv output every sample.
+/// SIGINT or SIGTERM cancels the running gather cycle and stops logging; the rows of a cancelled cycle are discarded.
+fn log_stations(config: &Config, svec: Vec<Station>, interval: Option<u64>, printer: &mut Printer) -> Result<(), Error>{
+    let entries: Vec<HostEntry> = svec.iter().map(Station::entry).collect();
+    let mut sinks = FanOut::spawn(&config.sinks());
+    sinks.stations(&entries);
+    let station_intervals = match interval{
+        Some(_) => vec![],
+        None => entries.iter().map(|entry| (entry.station_no, Duration::from_secs(config.interval_of(entry)))).collect(),
+    };
+    let monitor = Monitor::new(svec)
+        .interval(Duration::from_secs(interval.unwrap_or(config.interval)))
+        .station_intervals(station_intervals)
+        .timeouts(config.timeouts.collect_timeouts())
+        .health(config.health.clone())
+        .time_zone(config.time.zone);
+    let result = monitor.run(&cancel_on_signal(), |batch|{
+        let printed = printer.batch(&batch);
+        sinks.write(&batch);
+        // A closed pipe ends logging, e.g. when piped into `head`.
+        Ok(printed?)
+    });
+    sinks.shutdown();
+    printer.message("Logging cancelled.");
+    result
+}
+
+/// Prints the current data of the station with number `stat_no` in the hosts file.
+pub fn get_current_data_from_no(config: &Config, stat_no: u8, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    let station = connect_from_hosts(config, stat_no, &printer)?;
+    print_current_data(config, &station, &mut printer)
+}
+
+/// Prints the current data of every station of the hosts file picked by the selector.
+/// Returns the first probe error once all are printed.
+pub fn get_current_data_from_selector(config: &Config, selector: &Selector, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    let mut result = Ok(());
+    for entry in select_entries(config, selector)?{
+        let station = connect_entry(&entry, &printer)?;
+        match print_current_data(config, &station, &mut printer){
+            Err(error @ Error::ProbeError { .. }) => result = result.and(Err(error)),
+            other => other?,
+        }
+    }
+    result
+}
+
+pub fn get_current_data_from_ip(config: &Config, usrname: &str, ipaddr: IpAddr, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    let station = connect_ip(usrname, ipaddr, &printer)?;
+    print_current_data(config, &station, &mut printer)
+}
+
+/// Prints a DataRow of the station, returns a probe error if no metric could be collected.
+fn print_current_data(config: &Config, station: &Station, printer: &mut Printer) -> Result<(), Error>{
+    let data_row = station.gather_data_set_with(&config.timeouts.collect_timeouts(), &cancel_on_signal())?.in_zone(config.time.zone);
+    match printer.format(){
+        OutputFormat::Json => printer.json_line(&Record::Sample(&data_row))?,
+        _ => printer.record(&data_row)?,
+    }
+    if data_row.latency().is_none() && data_row.cpu_temperature().is_none(){
+        return Err(probe_error(station, data_row.error().unwrap_or("no metric could be collected")));
+    }
+    Ok(())
+}
+
+fn probe_error(station: &Station, message: &str) -> Error{
+    Error::ProbeError { station_no: station.get_station_no(), address: station.get_ip_address().clone(), message: message.to_string() }
+}
+
+/// Replies and totals of a ping, the JSON output of `ping`.
+#[derive(Serialize)]
+struct PingResult{
+    station_no: u8,
+    #[serde(flatten)]
+    statistics: PingStatistics,
+    replies: Vec<PingAttempt>,
+}
+
+/// Pings the station with number `stat_no` in the hosts file `count` times, returns a probe error if it never answered.
+pub fn ping_station(config: &Config, stat_no: u8, count: u16, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    let station = connect_from_hosts(config, stat_no, &printer)?;
+    print_ping(&station, count, &mut printer)
+}
+
+/// Pings every station of the hosts file picked by the selector `count` times.
+/// Returns the probe error of the first station that never answered once all are pinged.
+pub fn ping_selected(config: &Config, selector: &Selector, count: u16, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    let mut result = Ok(());
+    for entry in select_entries(config, selector)?{
+        let station = connect_entry(&entry, &printer)?;
+        match print_ping(&station, count, &mut printer){
+            Err(error @ Error::ProbeError { .. }) => result = result.and(Err(error)),
+            other => other?,
+        }
+    }
+    result
+}
+
+pub fn ping_station_from_ip(usrname: &str, ipaddr: IpAddr, count: u16, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    let station = connect_ip(usrname, ipaddr, &printer)?;
+    print_ping(&station, count, &mut printer)
+}
+
+/// Pings the station, printing the replies as they arrive as text or csv and everything at the end as JSON.
+fn print_ping(station: &Station, count: u16, printer: &mut Printer) -> Result<(), Error>{
+    let mut replies = vec![];
+    let mut printed = Ok(());
+    let statistics = station.ping_this_station(count, |reply|{
+        if printer.format() != OutputFormat::Json && printed.is_ok(){
+            printed = printer.record(reply);
         }
-        loop {
-            let mut datavec: Vec<station::DataRow> = vec![];
-            for i in &svec{
-                datavec.push(i.gather_data_set());
-            }
-            filecontrol::write_data(datavec);
-            std::thread::sleep(Duration::from_secs(interval.parse().unwrap()));
+        replies.push(reply.clone());
+    })?;
+    printed?;
+    let received = statistics.received;
+    match printer.format(){
+        OutputFormat::Json => printer.json_line(&PingResult{ station_no: station.get_station_no(), statistics, replies })?,
+        OutputFormat::Csv => {},
+        OutputFormat::Text => printer.record(&statistics)?,
+    }
+    if received == 0{
+        return Err(probe_error(station, &format!("no reply to {count} echo requests")));
+    }
+    Ok(())
+}
+
+/// Prints the stations of the hosts file picked by the selector.
+pub fn list_stations(config: &Config, selector: &Selector, output: OutputFormat) -> Result<(), Error>{
+    print_stations(config, selector, &mut Printer::new(output))
+}
+
+fn print_stations(config: &Config, selector: &Selector, printer: &mut Printer) -> Result<(), Error>{
+    let entries = select_entries(config, selector)?;
+    match printer.format(){
+        OutputFormat::Json => printer.json_line(&entries)?,
+        OutputFormat::Text if entries.is_empty() => printer.message(format!("No stations in the hosts file {}.", config.hosts.display())),
+        _ => {
+            printer.heading(format!("{:>3}  {:<16}  {:<15}  {:<20}  Tags", "No", "User", "Address", "Hostname"))?;
+            entries.iter().try_for_each(|entry| printer.record(entry))?;
+        },
+    }
+    Ok(())
+}
+
+/// Sweeps `range` and prints the hosts that answered as known, new, changed or missing stations of the hosts file.
+/// With `write`, the new hosts are added to the hosts file and the changed stations get their new address.
+pub fn discover(config: &Config, range: &Cidr, options: &DiscoverOptions, write: bool, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    let entries = if config.hosts.exists() { hosts::load(&config.hosts)? } else { vec![] };
+    let swept = range.hosts().count();
+    printer.message(format!("Sweeping {swept} addresses of {range}, at most {} per second.", options.rate));
+    let time_start = Instant::now();
+    let found = discovery::sweep(range, options, &cancel_on_signal())?;
+    let mut findings = discovery::compare(range, &entries, &found);
+    let updates = if write { discovery::entries_for(&mut findings, &entries, &options.user, &config.hosts)? } else { vec![] };
+    if !updates.is_empty(){
+        hosts::update(&config.hosts, &updates)?;
+    }
+    print_findings(range, swept, found.len(), &findings, &mut printer)?;
+    printer.message(format!("{} of {swept} addresses answered in {:.1} s.", found.len(), time_start.elapsed().as_secs_f64()));
+    if write{
+        printer.message(format!("Wrote {} entries to the hosts file {}.", updates.len(), config.hosts.display()));
+    }
+    Ok(())
+}
+
+fn print_findings(range: &Cidr, swept: usize, answered: usize, findings: &[discovery::Finding], printer: &mut Printer) -> Result<(), Error>{
+    match printer.format(){
+        OutputFormat::Json => printer.json_line(&serde_json::json!({ "range": range.to_string(), "swept": swept, "answered": answered, "findings": findings }))?,
+        _ => {
+            printer.heading(format!("{:<8} {:>3}  {:<15}  {:<20}  Latency", "Status", "No", "Address", "Hostname"))?;
+            findings.iter().try_for_each(|finding| printer.record(finding))?;
+        },
+    }
+    Ok(())
+}
+
+/// Outcome of sending the test alert through one notifier.
+#[derive(Serialize)]
+struct Delivery{
+    notifier: String,
+    delivered: bool,
+    error: Option<String>,
+}
+
+impl fmt::Display for Delivery{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match &self.error{
+            None => write!(f, "{}: ok", self.notifier),
+            Some(error) => write!(f, "{}: failed. Error: {error}", self.notifier),
         }
     }
 }
 
-pub fn get_current_data_from_no(stat_no: u8){
-    let station = Station::connect_station(stat_no);
-    let data_row = station.gather_data_set();
-    println!("{}", data_row);
+/// Sends a test alert through every configured notifier (or only the one called `name`) and reports the outcome of each.
+/// Returns the error of the first failed delivery, if any.
+pub fn notify_test(config: &Config, name: Option<&str>, output: OutputFormat) -> Result<(), Error>{
+    let mut printer = Printer::new(output);
+    let event = AlertEvent{
+        time: config.time.zone.now(),
+        rule: "notify-test".into(),
+        station_no: 0,
+        state: AlertState::Firing,
+        severity: "info".into(),
+        value: 0.0,
+        message: "This is a test alert sent by 'xbfisher notify-test'.".into(),
+        notifiers: vec![],
+    };
+    let notifiers: Vec<_> = notifier::build_all(&config.notifiers).into_iter().filter(|notifier| name.is_none_or(|name| notifier.name() == name)).collect();
+    if notifiers.is_empty(){
+        return Err(Error::NotifyError { notifier: name.unwrap_or_default().to_string(), message: "not configured".into() });
+    }
+    let deliveries: Vec<Delivery> = notifiers.iter().map(|notifier|{
+        let error = notifier::deliver(notifier.as_ref(), &event, &config.notify).err().map(|error| error.to_string());
+        Delivery{ notifier: notifier.name().to_string(), delivered: error.is_none(), error }
+    }).collect();
+    match output{
+        OutputFormat::Json => printer.json_line(&deliveries)?,
+        _ => deliveries.iter().try_for_each(|delivery| printer.record(delivery))?,
+    }
+    match deliveries.into_iter().find(|delivery| !delivery.delivered){
+        Some(Delivery{ notifier, error, .. }) => Err(Error::NotifyError { notifier, message: error.unwrap_or_default() }),
+        None => Ok(()),
+    }
+}
+
+/// Reads the samples matching the query from the configured csv directory, or `directory`, and summarizes them with their anomalies and trends.
+fn summarize(config: &Config, directory: Option<&Path>, query: &Query) -> Result<(Vec<reader::Sample>, Summary), Error>{
+    let csv = config.csv_output();
+    let data = reader::read_samples(directory.unwrap_or(&csv.directory), &csv.prefix, query.from, query.to)?;
+    let mut stations = stats::summarize(&data.samples, query);
+    anomaly::annotate(&mut stations, &data.samples, &config.anomaly);
+    let summary = Summary{ from: query.from, to: query.to, skipped: data.skipped, stations };
+    Ok((data.samples, summary))
 }
 
-pub fn get_current_data_from_ip(usrname: &String, ipaddr: &String){
-    let station = Station::connect_station_by_ip(99, usrname, ipaddr);
-    let data_row = station.gather_data_set();
-    println!("{}", data_row);
+/// Prints per-station statistics of the logged data read from `directory` (by default the configured .csv files).
+pub fn stats(config: &Config, directory: Option<&Path>, query: &Query, output: OutputFormat) -> Result<(), Error>{
+    print_stats(config, directory, query, &mut Printer::new(output))
 }
 
-pub fn ping_station(stat_no: u8, count: u16){
-    let station = Station::connect_station(stat_no);
-    station.ping_this_station(count);
+fn print_stats(config: &Config, directory: Option<&Path>, query: &Query, printer: &mut Printer) -> Result<(), Error>{
+    let (_, summary) = summarize(config, directory, query)?;
+    let format = printer.format();
+    printer.write_with(|mut out| summary.write(&mut out, format))?;
+    Ok(())
 }
 
-pub fn ping_station_from_ip(usrname: &String, ipaddr: &String, count: u16){
-    let station = Station::connect_station_by_ip(99, usrname, ipaddr);
-    station.ping_this_station(count);
-}
\ No newline at end of file
+/// Writes an HTML report with charts of the logged data to `file`. Station names are taken from the hosts file if it can be read.
+pub fn report(config: &Config, directory: Option<&Path>, query: &Query, file: &Path, output: OutputFormat) -> Result<(), Error>{
+    let printer = Printer::new(output);
+    let (samples, summary) = summarize(config, directory, query)?;
+    let names = hosts::load(&config.hosts).map(|entries| report::station_names(&entries)).unwrap_or_default();
+    let html = report::render(&summary, &samples, &names, config.time.zone.now());
+    fs::write(file, html).map_err(|error| Error::StorageError { path: file.display().to_string(), message: error.to_string() })?;
+    if output == OutputFormat::Json{
+        printer.json_line(&serde_json::json!({ "file": file, "stations": summary.stations.len() }))?;
+        return Ok(());
+    }
+    printer.message(format!("Wrote the report of {} stations to {}.", summary.stations.len(), file.display()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    use crate::stations::station::DataRow;
+    use crate::storage::csv::{CsvConfig, CsvLog};
+    use crate::tools::output::Captured;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("xbfisher-commands-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn config(directory: &Path) -> Config {
+        let hosts = directory.join("hosts.txt");
+        fs::write(&hosts, "1 -pi -10.0.0.1 -pi-north-1 -site=north\n2 -admin -10.0.0.2 -site=south\n3 -pi -10.0.0.3 -site=north\n").unwrap();
+        Config { hosts, ..Config::default() }
+    }
+
+    #[test]
+    fn prints_the_selected_stations_in_every_format() {
+        let directory = directory("stations");
+        let config = config(&directory);
+        let selector: Selector = "site=north".parse().unwrap();
+
+        let (mut printer, out, err) = Captured::printer(OutputFormat::Text);
+        print_stations(&config, &selector, &mut printer).unwrap();
+        let text = out.text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3, "{text}");
+        assert!(lines[0].starts_with(" No  User") && lines[0].ends_with("Tags"), "{}", lines[0]);
+        assert!(lines[1].starts_with("  1  pi") && lines[1].contains("pi-north-1") && lines[1].ends_with("site=north"), "{}", lines[1]);
+        assert_eq!(err.text(), "");
+
+        let (mut printer, out, _) = Captured::printer(OutputFormat::Json);
+        print_stations(&config, &selector, &mut printer).unwrap();
+        let entries: serde_json::Value = serde_json::from_str(out.text().trim_end()).unwrap();
+        assert_eq!(entries.as_array().map(Vec::len), Some(2));
+        assert_eq!(entries[1]["address"], "10.0.0.3");
+
+        let (mut printer, out, _) = Captured::printer(OutputFormat::Csv);
+        print_stations(&config, &selector, &mut printer).unwrap();
+        assert_eq!(out.text(), "station_no,user,address,hostname,tags\n1,pi,10.0.0.1,pi-north-1,site=north\n3,pi,10.0.0.3,,site=north\n");
+
+        fs::write(&config.hosts, "# StationNo -UserName -StationIP\n").unwrap();
+        let (mut printer, out, _) = Captured::printer(OutputFormat::Text);
+        print_stations(&config, &Selector::default(), &mut printer).unwrap();
+        assert!(out.text().starts_with("No stations in the hosts file"), "{}", out.text());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn prints_the_findings_of_a_sweep() {
+        let range: Cidr = "10.0.0.0/30".parse().unwrap();
+        let finding = discovery::Finding {
+            status: discovery::Status::New,
+            station_no: None,
+            address: "10.0.0.2".into(),
+            previous_address: None,
+            hostname: Some("pi-new".into()),
+            latency_ms: Some(1.5),
+            ssh: Some(true),
+        };
+
+        let (mut printer, out, _) = Captured::printer(OutputFormat::Text);
+        print_findings(&range, 2, 1, std::slice::from_ref(&finding), &mut printer).unwrap();
+        let text = out.text();
+        assert!(text.starts_with("Status") && text.lines().nth(1).is_some_and(|line| line.contains("pi-new")), "{text}");
+
+        let (mut printer, out, _) = Captured::printer(OutputFormat::Json);
+        print_findings(&range, 2, 1, &[finding], &mut printer).unwrap();
+        let report: serde_json::Value = serde_json::from_str(out.text().trim_end()).unwrap();
+        assert_eq!((report["swept"].as_u64(), report["answered"].as_u64()), (Some(2), Some(1)));
+        assert_eq!(report["findings"][0]["address"], "10.0.0.2");
+    }
+
+    #[test]
+    fn prints_the_statistics_through_the_printer() {
+        let directory = directory("stats");
+        let config = config(&directory);
+        let time = |time: &str| chrono::DateTime::parse_from_rfc3339(time).unwrap();
+        let mut log = CsvLog::new(CsvConfig { directory: directory.clone(), ..CsvConfig::default() });
+        log.write(&[DataRow::sample(1, time("2024-10-27T12:00:00+00:00"), 10.0), DataRow::sample(1, time("2024-10-27T12:01:00+00:00"), 20.0)]).unwrap();
+
+        let (mut printer, out, _) = Captured::printer(OutputFormat::Text);
+        print_stats(&config, Some(&directory), &Query::default(), &mut printer).unwrap();
+        assert!(out.text().starts_with("Data from - to -.\nStation"), "{}", out.text());
+
+        let (mut printer, out, _) = Captured::printer(OutputFormat::Json);
+        print_stats(&config, Some(&directory), &Query::default(), &mut printer).unwrap();
+        let summary: serde_json::Value = serde_json::from_str(&out.text()).unwrap();
+        assert_eq!(summary["stations"][0]["samples"], 2);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
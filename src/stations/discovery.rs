@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::pinging::ping::{self, PingOptions};
+use crate::stations::hosts::HostEntry;
+use crate::stations::station::Station;
+use crate::tools::deadline::CancelToken;
+use crate::tools::errors::Error;
+use crate::tools::math;
+
+/// Shortest prefix of a range that can be swept, so a typo can't start a sweep of millions of addresses.
+const MIN_PREFIX: u8 = 16;
+
+/// An IPv4 range in CIDR notation, e.g. 10.10.0.0/16, of at most 65536 addresses. A bare address is a /32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: Ipv4Addr,
+    prefix: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = text.split_once('/').unwrap_or((text, "32"));
+        let address: Ipv4Addr = address.parse().map_err(|_| format!("invalid IPv4 address \"{address}\""))?;
+        let prefix: u8 = prefix.parse().ok().filter(|prefix| *prefix <= 32).ok_or_else(|| format!("invalid prefix length \"{prefix}\", use {MIN_PREFIX} to 32"))?;
+        if prefix < MIN_PREFIX {
+            return Err(format!("the range {text} is too large, use a prefix length of at least {MIN_PREFIX}"));
+        }
+        Ok(Self { network: Ipv4Addr::from(u32::from(address) & mask(prefix)), prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl Cidr {
+    /// Whether the address is one of the host addresses of the range.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match address {
+            IpAddr::V4(address) => self.bounds().contains(&u32::from(address)),
+            IpAddr::V6(_) => false,
+        }
+    }
+
+    /// The host addresses of the range: all but the network and broadcast address, except for /31 and /32.
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        self.bounds().map(Ipv4Addr::from)
+    }
+
+    fn bounds(&self) -> RangeInclusive<u32> {
+        let first = u32::from(self.network);
+        let last = first | !mask(self.prefix);
+        if self.prefix < 31 { first + 1..=last - 1 } else { first..=last }
+    }
+}
+
+fn mask(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+}
+
+/// How `sweep` probes a range.
+#[derive(Debug, Clone)]
+pub struct DiscoverOptions {
+    /// Echo requests waiting for their reply at once.
+    pub concurrency: usize,
+    /// Echo requests sent per second at most.
+    pub rate: u32,
+    /// How long to wait for each echo reply.
+    pub timeout: Duration,
+    /// Check port 22 of the hosts that answer and read their host name over ssh as `user`.
+    pub ssh: bool,
+    pub user: String,
+    /// Deadline of the port check and of reading the host name.
+    pub ssh_timeout: Duration,
+}
+
+impl Default for DiscoverOptions {
+    fn default() -> Self {
+        Self { concurrency: 64, rate: 100, timeout: Duration::from_secs(1), ssh: false, user: "pi".into(), ssh_timeout: Duration::from_secs(5) }
+    }
+}
+
+/// A host that answered the sweep. The ssh fields are None if they were not checked or could not be read.
+#[derive(Debug, Clone, Serialize)]
+pub struct Host {
+    pub address: IpAddr,
+    pub latency_ms: f64,
+    /// Whether port 22 accepts connections.
+    pub ssh: Option<bool>,
+    pub hostname: Option<String>,
+}
+
+/// Pings every host address of the range once from `options.concurrency` threads, sending at most `options.rate` requests per second.
+/// Returns the hosts that answered, ordered by address.
+pub fn sweep(range: &Cidr, options: &DiscoverOptions, cancel: &CancelToken) -> Result<Vec<Host>, Error> {
+    let addresses: Vec<Ipv4Addr> = range.hosts().collect();
+    let period = Duration::from_secs(1) / options.rate.max(1);
+    let start = Instant::now();
+    // Index of the next address, which is also its slot in the send schedule.
+    let next = AtomicU64::new(0);
+    let probe = || -> Result<Vec<Host>, Error> {
+        let mut found = vec![];
+        loop {
+            let index = next.fetch_add(1, Ordering::SeqCst) as usize;
+            let Some(&address) = addresses.get(index) else {
+                return Ok(found);
+            };
+            cancel.sleep((start + period * index as u32).saturating_duration_since(Instant::now()))?;
+            match ping::ping(IpAddr::V4(address), &PingOptions::new().timeout(options.timeout)) {
+                Ok(reply) => found.push(inspect(reply.address(), reply.time_ms(), options, cancel)?),
+                Err(Error::IoError { error }) if error.kind() == ErrorKind::PermissionDenied => {
+                    return Err(Error::ProbeError { station_no: 0, address: address.to_string(), message: format!("{error}, sweeping needs permission to open raw sockets") });
+                },
+                Err(_) => {},
+            }
+        }
+    };
+    let mut hosts = vec![];
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..options.concurrency.clamp(1, addresses.len().max(1))).map(|_| scope.spawn(probe)).collect();
+        for worker in workers {
+            hosts.extend(worker.join().unwrap_or(Err(Error::InternalError))?);
+        }
+        Ok::<(), Error>(())
+    })?;
+    hosts.sort_by_key(|host| host.address);
+    Ok(hosts)
+}
+
+/// Checks the ssh port and reads the host name of a host that answered, if asked to.
+fn inspect(address: IpAddr, latency_ms: f64, options: &DiscoverOptions, cancel: &CancelToken) -> Result<Host, Error> {
+    let mut host = Host { address, latency_ms: math::round(latency_ms, 3), ssh: None, hostname: None };
+    if !options.ssh {
+        return Ok(host);
+    }
+    let open = TcpStream::connect_timeout(&SocketAddr::new(address, 22), options.ssh_timeout).is_ok();
+    host.ssh = Some(open);
+    if open {
+        match Station::builder(0, address).user(&options.user).build()?.read_hostname(options.ssh_timeout, cancel) {
+            Ok(hostname) => host.hostname = Some(hostname).filter(|hostname| !hostname.is_empty()),
+            Err(Error::Cancelled) => return Err(Error::Cancelled),
+            Err(_) => {},
+        }
+    }
+    Ok(host)
+}
+
+/// How a station of the hosts file or a host of the sweep compares to the other side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Answered at its configured address.
+    Known,
+    /// Answered, but is not in the hosts file.
+    New,
+    /// Found by its host name at another address, or another host name answered at its address.
+    Changed,
+    /// In the swept range, but did not answer.
+    Missing,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Known => "known",
+            Status::New => "new",
+            Status::Changed => "changed",
+            Status::Missing => "missing",
+        })
+    }
+}
+
+/// One line of the discovery report.
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub status: Status,
+    /// Station number in the hosts file, None for new hosts that were not written to it.
+    pub station_no: Option<u8>,
+    /// Address the host answered at, or the configured address of a missing station.
+    pub address: String,
+    /// Configured address of a changed station.
+    pub previous_address: Option<String>,
+    pub hostname: Option<String>,
+    pub latency_ms: Option<f64>,
+    pub ssh: Option<bool>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let station_no = self.station_no.map(|no| no.to_string()).unwrap_or_else(|| "-".into());
+        let latency = self.latency_ms.map(|latency| format!("{latency} ms")).unwrap_or_else(|| "-".into());
+        let ssh = match self.ssh {
+            Some(true) => "ssh open",
+            Some(false) => "ssh closed",
+            None => "",
+        };
+        let mut line = format!("{:<8} {station_no:>3}  {:<15}  {:<20}  {latency:<12} {ssh}", self.status.to_string(), self.address, self.hostname.as_deref().unwrap_or("-"));
+        if let Some(previous) = &self.previous_address {
+            line.push_str(&format!(" (was {previous})"));
+        }
+        f.write_str(line.trim_end())
+    }
+}
+
+/// Compares the hosts found in `range` with the hosts file entries.
+/// A station with a host name is looked up by it first, so a station that moved shows up as changed rather than missing and new.
+/// Stations outside the range that were not found by host name are left out.
+pub fn compare(range: &Cidr, entries: &[HostEntry], hosts: &[Host]) -> Vec<Finding> {
+    let mut claimed = vec![false; hosts.len()];
+    let mut findings = vec![];
+    for entry in entries {
+        let by_hostname = entry.hostname.as_ref().and_then(|hostname| {
+            hosts.iter().enumerate().position(|(i, host)| !claimed[i] && host.hostname.as_ref() == Some(hostname))
+        });
+        let by_address = || hosts.iter().enumerate().position(|(i, host)| !claimed[i] && host.address.to_string() == entry.ip_address);
+        let Some(i) = by_hostname.or_else(by_address) else {
+            if entry.ip_address.parse().is_ok_and(|address| range.contains(address)) {
+                findings.push(Finding { status: Status::Missing, station_no: Some(entry.station_no), address: entry.ip_address.clone(), previous_address: None, hostname: entry.hostname.clone(), latency_ms: None, ssh: None });
+            }
+            continue;
+        };
+        claimed[i] = true;
+        let host = &hosts[i];
+        let moved = host.address.to_string() != entry.ip_address;
+        let renamed = entry.hostname.is_some() && host.hostname.is_some() && entry.hostname != host.hostname;
+        let mut finding = found(host, if moved || renamed { Status::Changed } else { Status::Known });
+        finding.station_no = Some(entry.station_no);
+        finding.previous_address = moved.then(|| entry.ip_address.clone());
+        findings.push(finding);
+    }
+    findings.extend(hosts.iter().zip(claimed).filter(|(_, claimed)| !claimed).map(|(host, _)| found(host, Status::New)));
+    findings
+}
+
+fn found(host: &Host, status: Status) -> Finding {
+    Finding { status, station_no: None, address: host.address.to_string(), previous_address: None, hostname: host.hostname.clone(), latency_ms: Some(host.latency_ms), ssh: host.ssh }
+}
+
+/// The hosts file entries that apply the findings: new hosts get the lowest free station numbers (skipping 99, used for --ip)
+/// and `user`, changed stations their new address and host name. Fills in the station numbers of the new findings.
+/// path: &Path: the hosts file of `entries`, named in the error if it runs out of station numbers.
+pub fn entries_for(findings: &mut [Finding], entries: &[HostEntry], user: &str, path: &Path) -> Result<Vec<HostEntry>, Error> {
+    let mut used: Vec<u8> = entries.iter().map(|entry| entry.station_no).collect();
+    used.push(99);
+    let mut updates = vec![];
+    for finding in findings.iter_mut() {
+        match finding.status {
+            Status::New => {
+                let Some(station_no) = (1..=u8::MAX).find(|no| !used.contains(no)) else {
+                    return Err(Error::ConfigError { path: path.display().to_string(), message: "no free station number left".into() });
+                };
+                used.push(station_no);
+                finding.station_no = Some(station_no);
+                updates.push(HostEntry { station_no, usr_name: user.to_string(), ip_address: finding.address.clone(), hostname: finding.hostname.clone(), tags: BTreeMap::new() });
+            },
+            Status::Changed => {
+                let Some(entry) = entries.iter().find(|entry| Some(entry.station_no) == finding.station_no) else {
+                    continue;
+                };
+                updates.push(HostEntry { ip_address: finding.address.clone(), hostname: finding.hostname.clone().or(entry.hostname.clone()), ..entry.clone() });
+            },
+            Status::Known | Status::Missing => {},
+        }
+    }
+    Ok(updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() {
+        let cases = [
+            ("10.10.0.0/16", "10.10.0.0/16"),
+            ("10.10.3.7/24", "10.10.3.0/24"),
+            ("10.10.3.7", "10.10.3.7/32"),
+            ("10.10.3.6/31", "10.10.3.6/31"),
+        ];
+        for (text, expected) in cases {
+            let cidr: Cidr = text.parse().unwrap_or_else(|error| panic!("{text:?}: {error}"));
+            assert_eq!(cidr.to_string(), expected, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        for text in ["10.10.0.0/15", "0.0.0.0/0", "10.10.0.0/33", "10.10.0.0/", "10.10.0.0/x", "10.10.0/24", "fe80::1/64", ""] {
+            assert!(text.parse::<Cidr>().is_err(), "{text:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn contains_the_host_addresses() {
+        let cases = [
+            ("10.10.3.0/24", "10.10.3.1", true),
+            ("10.10.3.0/24", "10.10.3.254", true),
+            ("10.10.3.0/24", "10.10.3.0", false),
+            ("10.10.3.0/24", "10.10.3.255", false),
+            ("10.10.3.0/24", "10.10.4.1", false),
+            ("10.10.3.0/24", "::ffff:10.10.3.1", false),
+            ("10.10.3.6/31", "10.10.3.6", true),
+            ("10.10.3.6/31", "10.10.3.7", true),
+            ("10.10.3.7", "10.10.3.7", true),
+            ("10.10.3.7", "10.10.3.8", false),
+            ("10.10.0.0/16", "10.10.255.254", true),
+        ];
+        for (range, address, expected) in cases {
+            let cidr: Cidr = range.parse().unwrap();
+            assert_eq!(cidr.contains(address.parse().unwrap()), expected, "{range} contains {address}");
+        }
+    }
+
+    #[test]
+    fn lists_the_host_addresses() {
+        for (range, count) in [("10.10.3.0/24", 254), ("10.10.3.6/31", 2), ("10.10.3.7/32", 1), ("10.10.0.0/16", 65534)] {
+            assert_eq!(range.parse::<Cidr>().unwrap().hosts().count(), count, "{range}");
+        }
+        let hosts: Vec<Ipv4Addr> = "10.10.3.0/30".parse::<Cidr>().unwrap().hosts().collect();
+        assert_eq!(hosts, [Ipv4Addr::new(10, 10, 3, 1), Ipv4Addr::new(10, 10, 3, 2)]);
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::stations::hosts::HostEntry;
+
+/// Picks stations by comma separated terms that all have to hold, e.g. "site=north,role!=relay".
+/// A term compares a tag of the station, or its "no", "user", "address", "hostname" or "group"; these names take precedence over tags.
+/// `key!=value` also holds for stations without the key. The empty selector picks every station.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Selector {
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Term {
+    key: String,
+    value: String,
+    negated: bool,
+}
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let terms = text.split(',').map(str::trim).filter(|term| !term.is_empty()).map(|term| {
+            let (key, value, negated) = match term.split_once("!=") {
+                Some((key, value)) => (key, value, true),
+                None => term.split_once('=').map(|(key, value)| (key, value, false)).ok_or_else(|| format!("invalid selector term \"{term}\", use key=value or key!=value"))?,
+            };
+            if key.trim().is_empty() {
+                return Err(format!("invalid selector term \"{term}\", the key is missing"));
+            }
+            Ok(Term { key: key.trim().to_string(), value: value.trim().to_string(), negated })
+        }).collect::<Result<_, String>>()?;
+        Ok(Self { terms })
+    }
+}
+
+impl TryFrom<String> for Selector {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let terms: Vec<String> = self.terms.iter().map(|term| format!("{}{}{}", term.key, if term.negated { "!=" } else { "=" }, term.value)).collect();
+        f.write_str(&terms.join(","))
+    }
+}
+
+impl Selector {
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Whether the station matches, `groups` being the names of the groups it belongs to.
+    pub fn matches(&self, entry: &HostEntry, groups: &[&str]) -> bool {
+        self.terms.iter().all(|term| {
+            let value = term.value.as_str();
+            let equal = match term.key.as_str() {
+                "no" => entry.station_no.to_string() == value,
+                "user" => entry.usr_name == value,
+                "address" => entry.ip_address == value,
+                "hostname" => entry.hostname.as_deref() == Some(value),
+                "group" => groups.contains(&value),
+                tag => entry.tags.get(tag).is_some_and(|tag| tag == value),
+            };
+            equal != term.negated
+        })
+    }
+}
+
+/// A named set of stations from a [[group]] table of the config file, optionally gathered at an interval of its own.
+/// ```toml
+/// [[group]]
+/// name = "field"
+/// select = "role=field"
+/// interval = 30
+/// ```
+/// The selector of a group can't refer to other groups: its "group" terms see no groups.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupConfig {
+    pub name: String,
+    pub select: Selector,
+    /// Seconds between two gathers of the stations of the group, overriding `interval`.
+    pub interval: Option<u64>,
+}
+
+/// When each station is gathered next, for stations gathered at different intervals.
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    slots: Vec<Slot>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    station_no: u8,
+    interval: Duration,
+    due: Instant,
+    /// When the station was last taken as due, None before its first gather.
+    last: Option<Instant>,
+}
+
+impl Schedule {
+    /// A schedule of the stations with their intervals, all of them due right away.
+    pub fn new(intervals: impl IntoIterator<Item = (u8, Duration)>) -> Self {
+        let now = Instant::now();
+        Self { slots: intervals.into_iter().map(|(station_no, interval)| Slot { station_no, interval, due: now, last: None }).collect() }
+    }
+
+    /// The stations due at `now`. Each is due again one interval after `now`.
+    pub fn take_due(&mut self, now: Instant) -> Vec<u8> {
+        self.slots.iter_mut().filter(|slot| slot.due <= now).map(|slot| {
+            slot.due = now + slot.interval;
+            slot.last = Some(now);
+            slot.station_no
+        }).collect()
+    }
+
+    /// When the next station is due, None without stations.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.slots.iter().map(|slot| slot.due).min()
+    }
+
+    /// Takes over when the stations of `previous` were last gathered, e.g. after a reload, so they are due one of their
+    /// (possibly new) intervals after that instead of right away. Stations new to this schedule stay due right away.
+    pub fn carry_over(&mut self, previous: &Schedule) {
+        for slot in &mut self.slots {
+            if let Some(last) = previous.slots.iter().find(|old| old.station_no == slot.station_no).and_then(|old| old.last) {
+                slot.last = Some(last);
+                slot.due = last + slot.interval;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn entry() -> HostEntry {
+        let tags = BTreeMap::from([("site".to_string(), "north".to_string()), ("role".to_string(), "field".to_string())]);
+        HostEntry { station_no: 3, usr_name: "pi".into(), ip_address: "10.0.0.3".into(), hostname: Some("pi-3".into()), tags }
+    }
+
+    #[test]
+    fn parses_valid_selectors() {
+        let cases = [
+            ("", ""),
+            ("site=north", "site=north"),
+            (" site = north , role!=relay ", "site=north,role!=relay"),
+            ("site=north,,", "site=north"),
+            ("note=", "note="),
+            ("address=10.0.0.3", "address=10.0.0.3"),
+        ];
+        for (text, expected) in cases {
+            let selector: Selector = text.parse().unwrap_or_else(|error| panic!("{text:?}: {error}"));
+            assert_eq!(selector.to_string(), expected, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_selectors() {
+        for text in ["site", "site=north,role", "=north", " != relay", "site=north,=x"] {
+            assert!(text.parse::<Selector>().is_err(), "{text:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn matches_fields_tags_and_groups() {
+        let cases = [
+            ("", true),
+            ("no=3", true),
+            ("no=4", false),
+            ("user=pi,address=10.0.0.3", true),
+            ("hostname=pi-3", true),
+            ("hostname!=pi-3", false),
+            ("site=north", true),
+            ("site=south", false),
+            ("site!=south", true),
+            ("site=north,role=relay", false),
+            ("site=north,role!=relay", true),
+            ("rack=1", false),
+            ("rack!=1", true),
+            ("group=field", true),
+            ("group=relays", false),
+            ("group!=field", false),
+        ];
+        for (text, expected) in cases {
+            let selector: Selector = text.parse().unwrap();
+            assert_eq!(selector.matches(&entry(), &["field"]), expected, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn carries_the_last_gathers_over_to_a_new_schedule() {
+        let second = Duration::from_secs(1);
+        let mut previous = Schedule::new([(1, 10 * second), (2, 10 * second)]);
+        let start = Instant::now();
+        assert_eq!(previous.take_due(start), [1, 2]);
+        // Station 1 gets a longer interval and station 3 is new.
+        let mut schedule = Schedule::new([(1, 20 * second), (2, 10 * second), (3, 10 * second)]);
+        schedule.carry_over(&previous);
+        assert_eq!(schedule.take_due(start + second), [3]);
+        assert_eq!(schedule.next_due(), Some(start + 10 * second));
+        assert_eq!(schedule.take_due(start + 10 * second), [2]);
+        assert_eq!(schedule.take_due(start + 11 * second), [3]);
+        assert_eq!(schedule.take_due(start + 20 * second), [1, 2]);
+    }
+
+    #[test]
+    fn a_new_schedule_has_every_station_due() {
+        let mut schedule = Schedule::new([(1, Duration::from_secs(10)), (2, Duration::from_secs(60))]);
+        schedule.carry_over(&Schedule::default());
+        assert_eq!(schedule.take_due(Instant::now()), [1, 2]);
+    }
+}
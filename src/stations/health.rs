@@ -0,0 +1,363 @@
+use core::fmt;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+use serde::Deserialize;
+
+use crate::stations::station::{DataRow, MetricStatus};
+use crate::tools::time;
+
+/// Health of a station as seen by the logger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState{
+    /// No conclusive sample yet.
+    Unknown,
+    Up,
+    /// Reachable, but slow, lossy or failing to report its data.
+    Degraded,
+    Down,
+    /// Changing state too often for any single state to be meaningful.
+    Flapping,
+}
+
+impl fmt::Display for HealthState{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        let name = match self {
+            HealthState::Unknown => "unknown",
+            HealthState::Up => "up",
+            HealthState::Degraded => "degraded",
+            HealthState::Down => "down",
+            HealthState::Flapping => "flapping",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Thresholds driving the state machine, configured in the [health] section of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthThresholds{
+    /// Mean latency in ms above which a reachable station counts as degraded.
+    pub degraded_latency: f64,
+    /// Packet loss in percent at or above which a reachable station counts as degraded.
+    pub degraded_loss: f64,
+    /// Whether a failed temperature read degrades an otherwise healthy station.
+    pub degrade_on_collection_error: bool,
+    /// Consecutive unreachable samples before a station is marked down.
+    pub down_after: u32,
+    /// Consecutive reachable samples before a down station is marked up again.
+    pub up_after: u32,
+    /// Number of recent samples inspected for flapping.
+    pub flap_window: usize,
+    /// State changes within the window that mark a station as flapping.
+    /// It stops flapping once the changes within the window drop below half of this.
+    pub flap_threshold: usize,
+}
+
+impl Default for HealthThresholds{
+    fn default() -> Self{
+        Self {
+            degraded_latency: 200.0,
+            degraded_loss: 20.0,
+            degrade_on_collection_error: true,
+            down_after: 2,
+            up_after: 2,
+            flap_window: 20,
+            flap_threshold: 6,
+        }
+    }
+}
+
+/// A state transition of a single station.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StateEvent{
+    #[serde(rename = "Time", serialize_with = "time::serialize_rfc3339")]
+    pub time: DateTime<FixedOffset>,
+    #[serde(rename = "Station No")]
+    pub station_no: u8,
+    #[serde(rename = "From")]
+    pub from: HealthState,
+    #[serde(rename = "To")]
+    pub to: HealthState,
+    /// How long the station was in the `from` state, 0 for the first transition.
+    #[serde(rename = "Duration (s)", serialize_with = "serialize_secs")]
+    pub duration: Duration,
+    #[serde(rename = "Reason")]
+    pub reason: String,
+}
+
+impl fmt::Display for StateEvent{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "{}: Station {} changed from {} to {} after {} s ({})", time::rfc3339(&self.time), self.station_no, self.from, self.to, self.duration.as_secs(), self.reason)
+    }
+}
+
+fn serialize_secs<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>{
+    serializer.serialize_u64(duration.as_secs())
+}
+
+/// What a single sample says about the station.
+#[derive(Debug, Clone, PartialEq)]
+enum Verdict{
+    Good,
+    Degraded(String),
+    Unreachable(String),
+}
+
+/// State machine of a single station.
+#[derive(Debug, Clone)]
+pub struct StationHealth{
+    /// State without flap detection applied.
+    base: HealthState,
+    flapping: bool,
+    since: Option<DateTime<FixedOffset>>,
+    consecutive_unreachable: u32,
+    consecutive_reachable: u32,
+    /// For each of the last `flap_window` samples whether the base state changed.
+    changes: VecDeque<bool>,
+}
+
+impl Default for StationHealth{
+    fn default() -> Self{
+        Self { base: HealthState::Unknown, flapping: false, since: None, consecutive_unreachable: 0, consecutive_reachable: 0, changes: VecDeque::new() }
+    }
+}
+
+impl StationHealth{
+    /// The reported state, Flapping takes precedence over the base state.
+    pub fn state(&self) -> HealthState{
+        if self.flapping { HealthState::Flapping } else { self.base }
+    }
+
+    /// Time of the last reported state change.
+    pub fn since(&self) -> Option<DateTime<FixedOffset>>{
+        self.since
+    }
+
+    /// Feeds one sample into the state machine, returning the resulting transition if the reported state changed.
+    pub fn observe(&mut self, row: &DataRow, thresholds: &HealthThresholds) -> Option<(HealthState, HealthState, String)>{
+        let before = self.state();
+        let verdict = classify(row, thresholds);
+        match verdict {
+            Verdict::Unreachable(_) => {
+                self.consecutive_unreachable += 1;
+                self.consecutive_reachable = 0;
+            },
+            _ => {
+                self.consecutive_reachable += 1;
+                self.consecutive_unreachable = 0;
+            },
+        }
+
+        let base = match (&verdict, self.base) {
+            (Verdict::Unreachable(_), _) if self.consecutive_unreachable >= thresholds.down_after => HealthState::Down,
+            (Verdict::Unreachable(_), HealthState::Down) => HealthState::Down,
+            // Not down yet, keep the current state until `down_after` is reached.
+            (Verdict::Unreachable(_), state) => state,
+            (_, HealthState::Down) if self.consecutive_reachable < thresholds.up_after => HealthState::Down,
+            (Verdict::Good, _) => HealthState::Up,
+            (Verdict::Degraded(_), _) => HealthState::Degraded,
+        };
+        self.changes.push_back(base != self.base);
+        while self.changes.len() > thresholds.flap_window.max(1) {
+            self.changes.pop_front();
+        }
+        self.base = base;
+
+        let change_count = self.changes.iter().filter(|changed| **changed).count();
+        if !self.flapping && thresholds.flap_threshold > 0 && change_count >= thresholds.flap_threshold {
+            self.flapping = true;
+        } else if self.flapping && change_count < thresholds.flap_threshold.div_ceil(2) {
+            self.flapping = false;
+        }
+
+        let after = self.state();
+        if after == before {
+            return None;
+        }
+        let reason = match verdict {
+            _ if after == HealthState::Flapping => format!("{change_count} state changes in the last {} samples", self.changes.len()),
+            Verdict::Good => "probe answered within thresholds".into(),
+            Verdict::Degraded(reason) | Verdict::Unreachable(reason) => reason,
+        };
+        Some((before, after, reason))
+    }
+}
+
+fn classify(row: &DataRow, thresholds: &HealthThresholds) -> Verdict{
+    let latency = match row.latency() {
+        Some(latency) => latency,
+        None => return Verdict::Unreachable(match row.latency_status() {
+            MetricStatus::NoReply => "no probe answered".into(),
+            status => format!("probe failed ({status}): {}", row.error().unwrap_or_default()),
+        }),
+    };
+    if latency > thresholds.degraded_latency {
+        return Verdict::Degraded(format!("latency {latency} ms above {} ms", thresholds.degraded_latency));
+    }
+    if let Some(loss) = row.packet_loss().filter(|loss| *loss >= thresholds.degraded_loss) {
+        return Verdict::Degraded(format!("packet loss {loss} % at or above {} %", thresholds.degraded_loss));
+    }
+    if thresholds.degrade_on_collection_error && row.temperature_status() != MetricStatus::Ok {
+        return Verdict::Degraded(format!("collection error: {}", row.error().unwrap_or_default()));
+    }
+    Verdict::Good
+}
+
+/// Keeps the state machines of all stations of a logging session.
+#[derive(Debug, Clone, Default)]
+pub struct HealthTracker{
+    pub thresholds: HealthThresholds,
+    stations: HashMap<u8, StationHealth>,
+}
+
+impl HealthTracker{
+    pub fn new(thresholds: HealthThresholds) -> Self{
+        Self { thresholds, stations: HashMap::new() }
+    }
+
+    pub fn get(&self, station_no: u8) -> Option<&StationHealth>{
+        self.stations.get(&station_no)
+    }
+
+    /// Feeds a gathered cycle into the state machines and returns the transitions it caused.
+    pub fn observe_all(&mut self, datavec: &[DataRow], now: DateTime<FixedOffset>) -> Vec<StateEvent>{
+        datavec.iter().filter_map(|row| self.observe(row, now)).collect()
+    }
+
+    pub fn observe(&mut self, row: &DataRow, now: DateTime<FixedOffset>) -> Option<StateEvent>{
+        let station_no = row.station_no();
+        let health = self.stations.entry(station_no).or_default();
+        let (from, to, reason) = health.observe(row, &self.thresholds)?;
+        let duration = health.since.map(|since| (now - since).to_std().unwrap_or_default()).unwrap_or_default();
+        health.since = Some(now);
+        Some(StateEvent { time: now, station_no, from, to, duration, reason })
+    }
+
+    /// Forgets stations that are no longer logged.
+    pub fn retain(&mut self, station_nos: &[u8]){
+        self.stations.retain(|no, _| station_nos.contains(no));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One sample of station 1: Some(latency) for a reachable station, None for an unreachable one.
+    type Step = Option<f64>;
+
+    const UP: Step = Some(20.0);
+    const SLOW: Step = Some(500.0);
+    const DOWN: Step = None;
+
+    fn at(secs: i64) -> DateTime<FixedOffset> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap().fixed_offset()
+    }
+
+    fn row(step: Step, time: DateTime<FixedOffset>) -> DataRow {
+        match step {
+            Some(latency) => DataRow::sample(1, time, latency),
+            None => DataRow::unreachable(1, time),
+        }
+    }
+
+    /// Feeds the samples one a minute and returns the transitions together with the index of the sample causing them.
+    fn run(tracker: &mut HealthTracker, steps: &[Step]) -> Vec<(usize, HealthState, HealthState)> {
+        steps.iter().enumerate().filter_map(|(i, step)| {
+            let time = at(i as i64 * 60);
+            tracker.observe(&row(*step, time), time).map(|event| (i, event.from, event.to))
+        }).collect()
+    }
+
+    #[test]
+    fn moves_through_up_degraded_and_down() {
+        let mut tracker = HealthTracker::default();
+        let transitions = run(&mut tracker, &[UP, UP, SLOW, UP, DOWN, DOWN, DOWN, UP, UP]);
+        assert_eq!(transitions, [
+            (0, HealthState::Unknown, HealthState::Up),
+            (2, HealthState::Up, HealthState::Degraded),
+            (3, HealthState::Degraded, HealthState::Up),
+            // down_after = 2 unreachable samples.
+            (5, HealthState::Up, HealthState::Down),
+            // up_after = 2 reachable samples.
+            (8, HealthState::Down, HealthState::Up),
+        ]);
+        assert_eq!(tracker.get(1).unwrap().state(), HealthState::Up);
+        assert_eq!(tracker.get(1).unwrap().since(), Some(at(8 * 60)));
+    }
+
+    #[test]
+    fn a_single_unreachable_sample_keeps_the_state() {
+        let mut tracker = HealthTracker::default();
+        let transitions = run(&mut tracker, &[UP, DOWN, UP, DOWN, UP]);
+        assert_eq!(transitions, [(0, HealthState::Unknown, HealthState::Up)]);
+    }
+
+    #[test]
+    fn honours_the_configured_counters() {
+        let thresholds = HealthThresholds { down_after: 3, up_after: 1, ..HealthThresholds::default() };
+        let mut tracker = HealthTracker::new(thresholds);
+        let transitions = run(&mut tracker, &[DOWN, DOWN, DOWN, SLOW, DOWN, DOWN, DOWN, UP]);
+        assert_eq!(transitions, [
+            (2, HealthState::Unknown, HealthState::Down),
+            (3, HealthState::Down, HealthState::Degraded),
+            (6, HealthState::Degraded, HealthState::Down),
+            (7, HealthState::Down, HealthState::Up),
+        ]);
+    }
+
+    #[test]
+    fn reports_the_time_spent_in_the_previous_state() {
+        let mut tracker = HealthTracker::default();
+        let events: Vec<StateEvent> = [UP, UP, UP, SLOW].iter().enumerate()
+            .filter_map(|(i, step)| tracker.observe(&row(*step, at(i as i64 * 60)), at(i as i64 * 60)))
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].duration, Duration::ZERO);
+        assert_eq!(events[1].duration, Duration::from_secs(180));
+        assert_eq!(events[1].station_no, 1);
+        assert_eq!(events[1].time, at(180));
+        assert!(events[1].reason.contains("latency 500 ms"), "{}", events[1].reason);
+    }
+
+    #[test]
+    fn flaps_at_the_threshold_and_settles_below_half_of_it() {
+        let thresholds = HealthThresholds { flap_window: 10, flap_threshold: 4, ..HealthThresholds::default() };
+        let mut tracker = HealthTracker::new(thresholds);
+        // Unknown -> Up, then Up and Degraded alternate.
+        let transitions = run(&mut tracker, &[UP, SLOW, UP, SLOW, UP, SLOW, UP, UP, UP, UP, UP, UP, UP, UP, UP, UP]);
+        assert_eq!(transitions, [
+            (0, HealthState::Unknown, HealthState::Up),
+            (1, HealthState::Up, HealthState::Degraded),
+            (2, HealthState::Degraded, HealthState::Up),
+            // The fourth change within the window.
+            (3, HealthState::Up, HealthState::Flapping),
+            // The base state changed at samples 0 to 6. Flapping holds while at least 2 changes are within the window,
+            // and ends once the change of sample 5 leaves it.
+            (15, HealthState::Flapping, HealthState::Up),
+        ]);
+    }
+
+    #[test]
+    fn names_the_changes_when_it_starts_flapping() {
+        let mut tracker = HealthTracker::new(HealthThresholds { flap_window: 10, flap_threshold: 4, ..HealthThresholds::default() });
+        let events: Vec<StateEvent> = [UP, SLOW, UP, SLOW].iter().enumerate()
+            .filter_map(|(i, step)| tracker.observe(&row(*step, at(i as i64 * 60)), at(i as i64 * 60)))
+            .collect();
+        assert_eq!(events.last().unwrap().to, HealthState::Flapping);
+        assert_eq!(events.last().unwrap().reason, "4 state changes in the last 4 samples");
+    }
+
+    #[test]
+    fn forgets_stations_no_longer_logged() {
+        let mut tracker = HealthTracker::default();
+        let events = tracker.observe_all(&[DataRow::sample(1, at(0), 20.0), DataRow::sample(2, at(0), 20.0)], at(0));
+        assert_eq!(events.len(), 2);
+        tracker.retain(&[2]);
+        assert!(tracker.get(1).is_none());
+        assert_eq!(tracker.get(2).unwrap().state(), HealthState::Up);
+    }
+}
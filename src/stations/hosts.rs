@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use serde::{Serialize, Serializer};
+
+use crate::tools::errors::Error;
+use crate::tools::filecontrol;
+
+/// One line of the hosts file: "StationNo -UserName -StationIP", optionally followed by " -HostName" and tags like " -site=north -role=relay".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostEntry {
+    pub station_no: u8,
+    #[serde(rename = "user")]
+    pub usr_name: String,
+    #[serde(rename = "address")]
+    pub ip_address: String,
+    /// Host name of the station, written by `discover` to recognize the station at another address.
+    pub hostname: Option<String>,
+    /// Tags of the station, e.g. its site, role or hardware revision, for picking stations with a `Selector`.
+    #[serde(serialize_with = "serialize_tags")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl fmt::Display for HostEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let line = format!("{:>3}  {:<16}  {:<15}  {:<20}  {}", self.station_no, self.usr_name, self.ip_address, self.hostname.as_deref().unwrap_or_default(), self.tags_text());
+        f.write_str(line.trim_end())
+    }
+}
+
+impl HostEntry {
+    /// Parses a single hosts line. Returns None for blank lines, comments and malformed entries.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let linecut: Vec<&str> = line.split(" -").collect();
+        if linecut.len() < 3 {
+            return None;
+        }
+        // Fields after the address holding a '=' are tags, the first other one is the host name.
+        let (tags, rest): (Vec<&str>, Vec<&str>) = linecut[3..].iter().map(|field| field.trim()).filter(|field| !field.is_empty()).partition(|field| field.contains('='));
+        let tags = tags.iter().map(|tag| {
+            let (key, value) = tag.split_once('=').unwrap_or_default();
+            (!key.trim().is_empty()).then(|| (key.trim().to_string(), value.trim().to_string()))
+        }).collect::<Option<_>>()?;
+        Some(Self {
+            station_no: linecut[0].trim().parse().ok()?,
+            usr_name: linecut[1].trim().to_string(),
+            ip_address: linecut[2].trim().to_string(),
+            hostname: rest.first().map(|hostname| hostname.to_string()),
+            tags,
+        })
+    }
+
+    /// The entry as a line of the hosts file.
+    pub fn to_line(&self) -> String {
+        let mut line = format!("{} -{} -{}", self.station_no, self.usr_name, self.ip_address);
+        if let Some(hostname) = &self.hostname {
+            line += &format!(" -{hostname}");
+        }
+        for (key, value) in &self.tags {
+            line += &format!(" -{key}={value}");
+        }
+        line
+    }
+
+    /// The tags as "key=value" terms joined by commas, e.g. "role=relay,site=north".
+    pub fn tags_text(&self) -> String {
+        self.tags.iter().map(|(key, value)| format!("{key}={value}")).collect::<Vec<String>>().join(",")
+    }
+}
+
+/// Reads the station list from the hosts file. Malformed lines are reported and skipped, a station number listed twice is a config error.
+pub fn load(path: &Path) -> Result<Vec<HostEntry>, Error> {
+    let file = File::open(path).map_err(|error| config_error(path, error))?;
+    parse_lines(path, io::BufReader::new(file).lines())
+}
+
+/// Reads the station list like `load`, but creates a commented hosts file and returns a config error if none exists.
+pub fn load_or_create(path: &Path) -> Result<Vec<HostEntry>, Error> {
+    parse_lines(path, filecontrol::read_lines(path.display().to_string())?)
+}
+
+/// Writes the entries to the hosts file, replacing the lines of the same station numbers and appending the others.
+/// Comments and all other lines are kept. Creates the file if it doesn't exist.
+pub fn update(path: &Path, entries: &[HostEntry]) -> Result<(), Error> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(config_error(path, error)),
+    };
+    let mut pending: Vec<&HostEntry> = entries.iter().collect();
+    let mut lines: Vec<String> = text.lines().map(|line| {
+        match HostEntry::parse(line).and_then(|old| pending.iter().position(|entry| entry.station_no == old.station_no)) {
+            Some(i) => pending.remove(i).to_line(),
+            None => line.to_string(),
+        }
+    }).collect();
+    lines.extend(pending.iter().map(|entry| entry.to_line()));
+    // Written next to the hosts file and renamed, so a reading daemon never sees half of it.
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, lines.join("\n") + "\n").and_then(|()| fs::rename(&temporary, path)).map_err(|error| config_error(path, error))
+}
+
+/// Tags are written as one "key=value,…" field, so the entry stays a flat csv record.
+fn serialize_tags<S: Serializer>(tags: &BTreeMap<String, String>, serializer: S) -> Result<S::Ok, S::Error> {
+    let text: Vec<String> = tags.iter().map(|(key, value)| format!("{key}={value}")).collect();
+    serializer.serialize_str(&text.join(","))
+}
+
+fn config_error(path: &Path, error: io::Error) -> Error {
+    Error::ConfigError { path: path.display().to_string(), message: error.to_string() }
+}
+
+fn parse_lines<I: Iterator<Item = io::Result<String>>>(path: &Path, lines: I) -> Result<Vec<HostEntry>, Error> {
+    let mut entries: Vec<HostEntry> = vec![];
+    for line in lines {
+        let line = line.map_err(|error| config_error(path, error))?;
+        match HostEntry::parse(&line) {
+            // Two lines of one station would be gathered twice and mixed up in the data files.
+            Some(entry) if entries.iter().any(|other| other.station_no == entry.station_no) => {
+                return Err(Error::ConfigError { path: path.display().to_string(), message: format!("station {} is listed more than once", entry.station_no) });
+            },
+            Some(entry) => entries.push(entry),
+            None if line.trim().is_empty() || line.trim_start().starts_with('#') => {},
+            None => eprintln!("Skipping malformed hosts line: \"{line}\""),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts_file(name: &str, text: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("xbfisher-hosts-{name}-{}.txt", std::process::id()));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_entries_with_host_names_and_tags() {
+        let entry = HostEntry::parse("  7 -pi -10.0.0.7 -pi-7 -site=north -role = relay ").unwrap();
+        assert_eq!((entry.station_no, entry.usr_name.as_str(), entry.ip_address.as_str()), (7, "pi", "10.0.0.7"));
+        assert_eq!(entry.hostname.as_deref(), Some("pi-7"));
+        assert_eq!(entry.tags_text(), "role=relay,site=north");
+
+        let entry = HostEntry::parse("8 -pi -10.0.0.8 -site=south").unwrap();
+        assert_eq!(entry.hostname, None);
+        assert_eq!(entry.tags_text(), "site=south");
+        assert_eq!(HostEntry::parse("9 -pi -10.0.0.9").unwrap().tags, BTreeMap::new());
+    }
+
+    #[test]
+    fn skips_blank_lines_comments_and_malformed_entries() {
+        for line in ["", "   ", "# 1 -pi -10.0.0.1", "1 -pi", "one -pi -10.0.0.1", "300 -pi -10.0.0.1", "1 -pi -10.0.0.1 -=north"] {
+            assert_eq!(HostEntry::parse(line), None, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn writes_entries_that_parse_back_unchanged() {
+        for line in ["7 -pi -10.0.0.7 -pi-7 -role=relay -site=north", "8 -pi -10.0.0.8 -site=south", "9 -pi -10.0.0.9"] {
+            assert_eq!(HostEntry::parse(line).unwrap().to_line(), line);
+        }
+    }
+
+    #[test]
+    fn loads_the_stations_of_a_hosts_file() {
+        let path = hosts_file("load", "# StationNo -UserName -StationIP\n1 -pi -10.0.0.1\n\nbroken line\n2 -pi -10.0.0.2 -pi-2 -site=north\n");
+        let entries = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.station_no).collect::<Vec<u8>>(), [1, 2]);
+        assert_eq!(entries[1].tags_text(), "site=north");
+    }
+
+    #[test]
+    fn rejects_a_station_listed_twice() {
+        let path = hosts_file("duplicate", "1 -pi -10.0.0.1\n2 -pi -10.0.0.2\n1 -admin -10.0.0.11\n");
+        let result = load(&path);
+        fs::remove_file(&path).unwrap();
+        let Err(Error::ConfigError { message, .. }) = result else {
+            panic!("the duplicate station was accepted: {result:?}");
+        };
+        assert!(message.contains("station 1"), "{message}");
+    }
+}
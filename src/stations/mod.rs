@@ -1,2 +1,6 @@
 pub mod station;
-pub mod commands;
\ No newline at end of file
+pub mod commands;
+pub mod discovery;
+pub mod hosts;
+pub mod health;
+pub mod groups;
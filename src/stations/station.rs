@@ -1,100 +1,356 @@
-use core::{fmt, str};
+use core::fmt;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
 use std::process::Command;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use rand::random;
-use chrono::{Local, Timelike};
+use chrono::{DateTime, FixedOffset, Utc};
 
 use crate::{math, Error};
 use crate::pinging::ping;
+use crate::pinging::train::Jitter;
+use crate::stations::hosts::HostEntry;
+use crate::tools::deadline::{self, CancelToken};
+use crate::tools::time::{self, TimeZoneSetting};
 
-#[derive(serde::Serialize)]
+/// Version of the DataRow column layout, written in the optional schema header of the .csv files.
+/// Columns are only ever appended, so readers that look columns up by name keep working across versions.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// Outcome of collecting a single metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricStatus{
+    Ok,
+    /// The station did not answer any probe.
+    NoReply,
+    /// The collection step ran out of time.
+    Timeout,
+    Error,
+}
+
+impl fmt::Display for MetricStatus{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        f.write_str(match self {
+            MetricStatus::Ok => "ok",
+            MetricStatus::NoReply => "no_reply",
+            MetricStatus::Timeout => "timeout",
+            MetricStatus::Error => "error",
+        })
+    }
+}
+
+impl MetricStatus{
+    fn of(error: &Error) -> Self{
+        match error {
+            Error::Timeout { .. } => MetricStatus::Timeout,
+            _ => MetricStatus::Error,
+        }
+    }
+}
+
+/// One sample of a station. Missing values are written as empty fields and explained by the status columns.
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct DataRow{
-    #[serde(rename = "Time")]
-    time: String,
+    #[serde(rename = "Time", serialize_with = "time::serialize_rfc3339")]
+    time: DateTime<FixedOffset>,
+    #[serde(rename = "Epoch")]
+    epoch: i64,
     #[serde(rename = "Station No")]
-    no: String,
-    #[serde(rename = "Latency")]
-    ping_latency: String,
-    #[serde(rename = "CPU Temperature")]
-    cpu_temperature: String,
+    no: u8,
+    #[serde(rename = "Latency (ms)")]
+    ping_latency: Option<f64>,
+    #[serde(rename = "Latency Status")]
+    latency_status: MetricStatus,
+    #[serde(rename = "Packet Loss (%)")]
+    packet_loss: Option<f64>,
+    #[serde(rename = "CPU Temperature (C)")]
+    cpu_temperature: Option<f64>,
+    #[serde(rename = "CPU Temperature Status")]
+    temperature_status: MetricStatus,
+    /// Messages of the collection errors of this sample, separated by "; ".
+    #[serde(rename = "Error")]
+    error: String,
+    #[serde(rename = "Jitter (ms)")]
+    jitter: Option<f64>,
+    #[serde(rename = "Mean |IPDV| (ms)")]
+    ipdv_mean_abs: Option<f64>,
+    #[serde(rename = "Max |IPDV| (ms)")]
+    ipdv_max: Option<f64>,
+    #[serde(rename = "Reordered")]
+    reordered: Option<u16>,
+    #[serde(rename = "Loss Bursts")]
+    loss_bursts: Option<u16>,
+    #[serde(rename = "Max Loss Burst")]
+    max_loss_burst: Option<u16>,
+}
+
+impl DataRow{
+    /// Moment the sample was taken.
+    pub fn time(&self) -> DateTime<FixedOffset>{
+        self.time
+    }
+
+    /// Returns the row with its timestamp expressed in the given time zone. The instant, and so the epoch, stay the same.
+    pub fn in_zone(mut self, zone: TimeZoneSetting) -> Self{
+        self.time = zone.convert(self.time.to_utc());
+        self
+    }
+
+    pub fn station_no(&self) -> u8{
+        self.no
+    }
+
+    /// Mean latency in ms, None if no probe was answered.
+    pub fn latency(&self) -> Option<f64>{
+        self.ping_latency
+    }
+
+    pub fn latency_status(&self) -> MetricStatus{
+        self.latency_status
+    }
+
+    /// Packet loss in percent, 100 if no probe was answered and None if the probe itself failed, e.g. on a timeout or socket error.
+    pub fn packet_loss(&self) -> Option<f64>{
+        self.packet_loss
+    }
+
+    /// CPU temperature in C, None if it could not be read.
+    pub fn cpu_temperature(&self) -> Option<f64>{
+        self.cpu_temperature
+    }
+
+    pub fn temperature_status(&self) -> MetricStatus{
+        self.temperature_status
+    }
+
+    /// RFC 3550 interarrival jitter of the replies in ms, estimated over this and the earlier samples of the station. None before two replies.
+    pub fn jitter(&self) -> Option<f64>{
+        self.jitter
+    }
+
+    /// Mean |IPDV| in ms: the average of the absolute RFC 3393 IP packet delay variations between the replies to consecutive probes.
+    /// Not the signed IPDV of the RFC, which averages to about 0 over a train.
+    pub fn ipdv_mean_abs(&self) -> Option<f64>{
+        self.ipdv_mean_abs
+    }
+
+    /// Max |IPDV| in ms, the largest absolute IP packet delay variation between the replies to consecutive probes.
+    pub fn ipdv_max(&self) -> Option<f64>{
+        self.ipdv_max
+    }
+
+    /// Replies that arrived after the reply to a later probe, None if the probe failed.
+    pub fn reordered(&self) -> Option<u16>{
+        self.reordered
+    }
+
+    /// Runs of consecutive unanswered probes, None if the probe failed.
+    pub fn loss_bursts(&self) -> Option<u16>{
+        self.loss_bursts
+    }
+
+    /// Length of the longest run of unanswered probes, None if the probe failed.
+    pub fn max_loss_burst(&self) -> Option<u16>{
+        self.max_loss_burst
+    }
+
+    /// Returns the collection errors recorded in this row.
+    pub fn error(&self) -> Option<&str>{
+        Some(self.error.as_str()).filter(|error| !error.is_empty())
+    }
+}
+
+#[cfg(test)]
+impl DataRow{
+    /// A sample with a latency and a temperature and no errors, for the tests of the sinks.
+    pub(crate) fn sample(no: u8, time: DateTime<FixedOffset>, latency: f64) -> Self{
+        DataRow{
+            time,
+            epoch: time.timestamp(),
+            no,
+            ping_latency: Some(latency),
+            latency_status: MetricStatus::Ok,
+            packet_loss: Some(0.0),
+            cpu_temperature: Some(48.5),
+            temperature_status: MetricStatus::Ok,
+            error: String::new(),
+            jitter: None,
+            ipdv_mean_abs: None,
+            ipdv_max: None,
+            reordered: None,
+            loss_bursts: None,
+            max_loss_burst: None,
+        }
+    }
+
+    /// A sample of a station that answered none of the probes.
+    pub(crate) fn unreachable(no: u8, time: DateTime<FixedOffset>) -> Self{
+        DataRow{
+            ping_latency: None,
+            latency_status: MetricStatus::NoReply,
+            packet_loss: Some(100.0),
+            cpu_temperature: None,
+            temperature_status: MetricStatus::Error,
+            error: "temperature: ssh failed".into(),
+            ..DataRow::sample(no, time, 0.0)
+        }
+    }
 }
 
 impl fmt::Display for DataRow{
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
-        write!(f, "Time of Day: {}, Latency: {} ms, CPU Temp: {} C\n", self.time, self.ping_latency, self.cpu_temperature)
+        let show = |value: Option<f64>, status: MetricStatus| value.map(|value| value.to_string()).unwrap_or_else(|| status.to_string());
+        write!(f, "Time: {}, Station: {}, Latency: {} ms, Packet Loss: {} %, CPU Temp: {} C", time::rfc3339(&self.time), self.no,
+            show(self.ping_latency, self.latency_status), show(self.packet_loss, self.latency_status), show(self.cpu_temperature, self.temperature_status))?;
+        if let Some(jitter) = self.jitter {
+            write!(f, ", Jitter: {jitter} ms")?;
+        }
+        match self.error() {
+            Some(error) => write!(f, ", Error: {error}"),
+            None => Ok(()),
+        }
     }
 }
 
+/// Deadlines for the remote collection steps of a single station.
+#[derive(Clone, Copy, Debug)]
+pub struct CollectTimeouts{
+    /// Deadline for the whole latency probe: a train of five echo requests sent one second apart, each reply awaited for 2 seconds,
+    /// so a train that is not cut short takes up to 6 seconds.
+    pub probe: Duration,
+    /// Deadline for the ssh session reading the temperature, including connection setup.
+    pub ssh: Duration,
+}
+
+impl Default for CollectTimeouts{
+    fn default() -> Self{
+        Self { probe: Duration::from_secs(20), ssh: Duration::from_secs(10) }
+    }
+}
+
+/// A monitored station: its latency is probed with ICMP echo requests and its CPU temperature read over ssh.
+#[derive(Debug, Clone)]
 pub struct Station{
-    pub station_no: u8,
-    pub ip_address: String,
-    pub usr_name: String,
+    station_no: u8,
+    ip_address: String,
+    usr_name: String,
+    hostname: Option<String>,
+    tags: BTreeMap<String, String>,
+    /// Jitter estimate carried from one sample to the next, shared by the clones of the station.
+    jitter: Arc<Mutex<Jitter>>,
+}
+
+/// Builds a Station, e.g. `Station::builder(1, "10.10.1.2").user("pi").build()`.
+#[derive(Debug, Clone)]
+pub struct StationBuilder{
+    station_no: u8,
+    address: String,
+    user: String,
+    hostname: Option<String>,
+    tags: BTreeMap<String, String>,
+}
+
+impl StationBuilder{
+    /// ssh user reading the temperature, "pi" by default.
+    pub fn user(mut self, user: impl Into<String>) -> Self{
+        self.user = user.into();
+        self
+    }
+
+    /// Host name of the station, used by `discover` to recognize it at another address.
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self{
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Adds a tag like site=north, see `groups::Selector`.
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self{
+        self.tags.insert(key.into(), value.into());
+        self
+    }
+
+    /// Creates the station without checking whether it answers. Fails on an invalid address.
+    pub fn build(self) -> Result<Station, Error>{
+        let station = Station{ hostname: self.hostname, tags: self.tags, ..Station::new_no(self.station_no, &self.user, &self.address) };
+        station.address()?;
+        Ok(station)
+    }
 }
 
 impl Station{
-    fn new_no(st_no: u8, usr_name: &String, ipaddr: &String) -> Self{
-        Self { station_no: st_no, ip_address: ipaddr.to_string(), usr_name: usr_name.to_string()}
-    }
-
-    pub fn connect_station(stat_no: u8) -> Self{
-        let station = match stat_no{
-            0 => Self::new_no(0, &"frodo_central".into(), &"10.8.0.101".to_string()),
-            1 => Self::new_no(1, &"pi".into(), &"10.10.1.2".to_string()),
-            2 => Self::new_no(2, &"pi".into(), &"10.10.2.2".to_string()),
-            3 => Self::new_no(3, &"pi".into(), &"10.10.3.2".to_string()),
-            4 => Self::new_no(4, &"pi".into(), &"10.10.4.2".to_string()),
-            5 => Self::new_no(5, &"pi".into(), &"10.10.5.2".to_string()),
-            6 => Self::new_no(6, &"pi".into(), &"10.10.6.2".to_string()),
-            _ => panic!("An invalid station no!")
-        };
-        let timeout = Duration::from_secs(2);
-        match ping::ping(
-            station.get_ip_address().parse().unwrap_or_else(|error|{
-                panic!("Error reading this address: \"{}\". check if its correct. Error: {error}", station.get_ip_address());
-            }),
-            Some(timeout),
-            Some(166),
-            Some(3),
-            Some(5),
-            Some(&random()),
-        ){
-            Ok(_a) => {
-                println!("Station found, initiating connection.");
-            },
-            Err(error) => {
-                println!("Problem during pinging Station {}. Station might be offline, or has a different address, otherwise you do not have connection. Error: {error}.", station.get_ip_address());
-            },
+    pub fn builder(station_no: u8, address: impl ToString) -> StationBuilder{
+        StationBuilder { station_no, address: address.to_string(), user: "pi".into(), hostname: None, tags: BTreeMap::new() }
+    }
+
+    fn new_no(st_no: u8, usr_name: &str, ipaddr: &str) -> Self{
+        Self { station_no: st_no, ip_address: ipaddr.to_string(), usr_name: usr_name.to_string(), hostname: None, tags: BTreeMap::new(), jitter: Arc::default() }
+    }
+
+    /// Connects to a station of the built-in station list.
+    pub fn connect_station(stat_no: u8) -> Result<Self, Error>{
+        let (usr_name, ipaddr) = match stat_no{
+            0 => ("frodo_central", "10.8.0.101"),
+            1 => ("pi", "10.10.1.2"),
+            2 => ("pi", "10.10.2.2"),
+            3 => ("pi", "10.10.3.2"),
+            4 => ("pi", "10.10.4.2"),
+            5 => ("pi", "10.10.5.2"),
+            6 => ("pi", "10.10.6.2"),
+            _ => return Err(Error::UnknownStation { station_no: stat_no, path: "the built-in station list".into() }),
         };
-        station
+        Self::connect_station_by_ip(stat_no, usr_name, ipaddr)
+    }
+
+    /// Creates the station and pings it once, printing whether it answered. Fails only on an invalid address.
+    pub fn connect_station_by_ip(st_no: u8, username: &str, ipaddr: &str) -> Result<Self, Error>{
+        Self::new_no(st_no, username, ipaddr).announce()
     }
 
-    pub fn connect_station_by_ip(st_no: u8, username: &String, ipaddr: &String) -> Self{
-        let station = Self::new_no(st_no, username, ipaddr);
+    /// Creates the station of a hosts file entry, keeping its host name and tags, and pings it once like `connect_station_by_ip`.
+    pub fn connect_entry(entry: &HostEntry) -> Result<Self, Error>{
+        Self::from_entry(entry)?.announce()
+    }
+
+    /// Pings the station once and prints whether it answered.
+    fn announce(self) -> Result<Self, Error>{
+        let (st_no, ipaddr) = (self.station_no, &self.ip_address);
         let timeout = Duration::from_secs(2);
-        match ping::ping(
-            ipaddr.parse().unwrap_or_else(|error|{
-                panic!("Error reading this address: \"{ipaddr}\". check if its correct. Error: {error}");
-            }),
-            Some(timeout),
-            Some(166),
-            Some(3),
-            Some(5),
-            Some(&random()),
-        ){
+        match ping::ping(self.address()?, &ping::PingOptions::new().timeout(timeout).ttl(166).ident(3).seq(5)){
             Ok(_a) => {
-                println!("Station {st_no} with ip: {ipaddr} found. Initiating connection.");
+                eprintln!("Station {st_no} with ip: {ipaddr} found. Initiating connection.");
             },
             Err(error) => {
-                println!("Problem during pinging Station {st_no} with ip: {ipaddr}. Station might be offline, or has a different address, otherwise you do not have connection. Error: {error}.");
+                eprintln!("Problem during pinging Station {st_no} with ip: {ipaddr}. Station might be offline, or has a different address, otherwise you do not have connection. Error: {error}.");
             },
         };
-        station
+        Ok(self)
+    }
+
+    /// A station for a hosts file entry, without checking whether it answers. Fails on an invalid address.
+    pub fn from_entry(entry: &HostEntry) -> Result<Self, Error> {
+        let builder = Self::builder(entry.station_no, &entry.ip_address).user(&entry.usr_name);
+        let builder = entry.tags.iter().fold(builder, |builder, (key, value)| builder.tag(key, value));
+        match &entry.hostname {
+            Some(hostname) => builder.hostname(hostname),
+            None => builder,
+        }.build()
+    }
+
+    /// Returns the hosts file entry describing this station.
+    pub fn entry(&self) -> HostEntry {
+        HostEntry { station_no: self.station_no, usr_name: self.usr_name.clone(), ip_address: self.ip_address.clone(), hostname: self.hostname.clone(), tags: self.tags.clone() }
     }
 
     pub fn get_ip_address(&self) -> &String {
         &self.ip_address
     }
 
+    /// The parsed ip address of the station.
+    pub fn address(&self) -> Result<IpAddr, Error> {
+        self.ip_address.parse().map_err(|_| Error::InvalidAddress { station_no: self.station_no, address: self.ip_address.clone() })
+    }
+
     pub fn get_station_no(&self) -> u8 {
         self.station_no
     }
@@ -103,43 +359,125 @@ impl Station{
         &self.usr_name
     }
 
-    pub fn ping_this_station(&self, count: u16) -> Vec<f32>{
-        ping::ping_station(self, count)
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
     }
 
-    fn ping_this_station_silent(&self, count: u16) -> Vec<f32>{
-        ping::ping_station_silent(self, count)
+    pub fn tags(&self) -> &BTreeMap<String, String> {
+        &self.tags
     }
 
-    pub fn get_current_temperature(&self) -> Result<String, Error>{
-        let mut remote_data = Command::new("ssh");
-        let username_ip = format!("{}@{}", self.get_user_name(), self.get_ip_address());
-        let home_dir = format!("/home/hea-data/.ssh/id_rsa");
+    pub fn ping_this_station(&self, count: u16, on_reply: impl FnMut(&ping::PingAttempt)) -> Result<ping::PingStatistics, Error>{
+        ping::ping_station(self, count, on_reply)
+    }
+
+    fn ping_this_station_silent(&self, count: u16, deadline: Duration, cancel: &CancelToken) -> Result<ping::ProbeSummary, Error>{
+        ping::ping_station_silent(self, count, deadline, cancel)
+    }
+
+    pub fn get_current_temperature(&self) -> Result<f64, Error>{
+        self.get_current_temperature_with(CollectTimeouts::default().ssh, &CancelToken::new())
+    }
+
+    /// Reads the temperature over ssh, killing the session if it takes longer than `timeout` or `cancel` is triggered.
+    pub fn get_current_temperature_with(&self, timeout: Duration, cancel: &CancelToken) -> Result<f64, Error>{
         // We have a station with a broken temp detector so we are using secondary temp detector if its that station.
         let loc = match self.get_ip_address().as_str() {
-            "10.8.0.110" => format!("/sys/class/thermal/thermal_zone1/temp"),
-            _ => format!("/sys/class/thermal/thermal_zone0/temp")
+            "10.8.0.110" => "/sys/class/thermal/thermal_zone1/temp",
+            _ => "/sys/class/thermal/thermal_zone0/temp"
         };
-        let data = remote_data.args(["-i", home_dir.as_str(), username_ip.as_str(),"cat", loc.as_str()]).output().unwrap_or_else(|error|{panic!("Error: {error}")});
-        let array = &*data.stdout;
-        match str::from_utf8(array).unwrap().trim().parse::<i32>() {
-            Ok(a) => Ok(math::n_decimals(a as f32 /1000.0, 4).to_string()),
-            Err(_x) => Err(Error::InternalError) ,
+        let output = self.ssh(&["cat", loc], timeout, cancel)?;
+        match output.parse::<i32>() {
+            Ok(a) => Ok(a as f64 / 1000.0),
+            Err(error) => Err(Error::ParseError { what: format!("temperature of station {}", self.station_no), input: output, message: error.to_string() }),
         }
     }
 
+    /// Reads the host name of the station over ssh.
+    pub fn read_hostname(&self, timeout: Duration, cancel: &CancelToken) -> Result<String, Error>{
+        self.ssh(&["hostname"], timeout, cancel)
+    }
+
+    /// Runs a command on the station over ssh and returns its trimmed output,
+    /// killing the session if it takes longer than `timeout` or `cancel` is triggered.
+    pub fn ssh(&self, command: &[&str], timeout: Duration, cancel: &CancelToken) -> Result<String, Error>{
+        let mut remote_data = Command::new("ssh");
+        let username_ip = format!("{}@{}", self.get_user_name(), self.get_ip_address());
+        let home_dir = "/home/hea-data/.ssh/id_rsa";
+        let connect_timeout = format!("ConnectTimeout={}", timeout.as_secs().max(1));
+        remote_data.args(["-i", home_dir, "-o", "BatchMode=yes", "-o", connect_timeout.as_str(), username_ip.as_str()]).args(command);
+        let data = match deadline::output_with_deadline(&mut remote_data, "ssh", timeout, cancel) {
+            Err(Error::IoError { error }) => return Err(self.ssh_error(format!("could not run ssh: {error}"))),
+            data => data?,
+        };
+        if !data.status.success() {
+            let stderr = String::from_utf8_lossy(&data.stderr);
+            let message = stderr.lines().map(str::trim).rfind(|line| !line.is_empty()).map(str::to_string).unwrap_or_else(|| data.status.to_string());
+            return Err(self.ssh_error(message));
+        }
+        Ok(String::from_utf8_lossy(&data.stdout).trim().to_string())
+    }
+
+    fn ssh_error(&self, message: String) -> Error{
+        Error::SshError { station_no: self.station_no, address: self.ip_address.clone(), message }
+    }
+
     /// Gathers data from the station and returns it as DataRow
-    pub fn gather_data_set(&self) -> DataRow{
-        let date: chrono::DateTime<Local> = chrono::offset::Local::now();
-        let latency = math::n_decimals(math::vec_mean(&self.ping_this_station_silent(5)), 4).to_string();
-        DataRow{
-            no: self.station_no.to_string(),
-            ping_latency: latency,
-            cpu_temperature: match self.get_current_temperature(){
-                Ok(a) => a,
-                Err(error) => format!("Error: {}", error).to_string()
+    pub fn gather_data_set(&self) -> Result<DataRow, Error>{
+        self.gather_data_set_with(&CollectTimeouts::default(), &CancelToken::new())
+    }
+
+    /// Gathers data from the station like `gather_data_set`, bounding every remote step by `timeouts`.
+    /// A step that runs out of time is recorded in the DataRow as a timeout with its duration.
+    /// The latency comes from a probe train rather than sequential pings: the requests go out on schedule without waiting for the replies,
+    /// so a slow reply no longer delays the next request, replies can arrive out of order and a reply later than 2 seconds counts as lost.
+    /// Returns Error::Cancelled if `cancel` is triggered before the data set is complete.
+    pub fn gather_data_set_with(&self, timeouts: &CollectTimeouts, cancel: &CancelToken) -> Result<DataRow, Error>{
+        let date = TimeZoneSetting::default().convert(Utc::now());
+        let mut errors: Vec<String> = vec![];
+        let (ping_latency, latency_status, packet_loss, train) = match self.ping_this_station_silent(5, timeouts.probe, cancel){
+            Ok(probe) => {
+                let train = probe.train_metrics(&mut self.jitter.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+                match probe.latency.is_empty() {
+                    true => (None, MetricStatus::NoReply, Some(100.0), Some(train)),
+                    false => (
+                        probe.latency_stats().mean().map(|mean| math::round(mean, 3)),
+                        MetricStatus::Ok,
+                        probe.loss().map(|loss| math::round(loss as f64 * 100.0, 2)),
+                        Some(train),
+                    ),
+                }
             },
-            time: format!("{}:{}", date.hour(), date.minute()),
-        }
+            Err(Error::Cancelled) => return Err(Error::Cancelled),
+            Err(error) => {
+                errors.push(error.to_string());
+                (None, MetricStatus::of(&error), None, None)
+            },
+        };
+        let (cpu_temperature, temperature_status) = match self.get_current_temperature_with(timeouts.ssh, cancel){
+            Ok(a) => (Some(a), MetricStatus::Ok),
+            Err(Error::Cancelled) => return Err(Error::Cancelled),
+            Err(error) => {
+                errors.push(format!("temperature: {error}"));
+                (None, MetricStatus::of(&error))
+            },
+        };
+        Ok(DataRow{
+            time: date,
+            epoch: date.timestamp(),
+            no: self.station_no,
+            ping_latency,
+            latency_status,
+            packet_loss,
+            cpu_temperature,
+            temperature_status,
+            error: errors.join("; "),
+            jitter: train.and_then(|train| train.jitter_ms).map(|jitter| math::round(jitter, 3)),
+            ipdv_mean_abs: train.and_then(|train| train.ipdv_mean_abs_ms).map(|ipdv| math::round(ipdv, 3)),
+            ipdv_max: train.and_then(|train| train.ipdv_max_ms).map(|ipdv| math::round(ipdv, 3)),
+            reordered: train.map(|train| train.reordered),
+            loss_bursts: train.map(|train| train.loss_bursts),
+            max_loss_burst: train.map(|train| train.max_loss_burst),
+        })
     }
 }
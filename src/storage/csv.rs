@@ -0,0 +1,443 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset};
+use csv::{ReaderBuilder, StringRecord, Writer, WriterBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::stations::station::{self, DataRow};
+use crate::storage::rotation::{RotationPolicy, Rotator};
+use crate::storage::sink::{Batch, Sink};
+use crate::tools::errors::Error;
+
+/// Options of the data .csv files, set in the [csv] section of the config file.
+/// Example:
+/// ```toml
+/// [csv]
+/// directory = "./data"
+/// rotation = "daily"
+/// max_size = 10485760
+/// compression = "zstd"
+/// retention_days = 90
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvConfig {
+    pub directory: PathBuf,
+    /// Start of the file names, followed by the period, e.g. "station_list_2024-10-27.csv".
+    pub prefix: String,
+    /// Start new files with a "# xbfisher schema <version>" comment line.
+    pub schema_header: bool,
+    #[serde(flatten)]
+    pub policy: RotationPolicy,
+}
+
+impl Default for CsvConfig {
+    fn default() -> Self {
+        Self { directory: PathBuf::from("./data"), prefix: "station_list".into(), schema_header: false, policy: RotationPolicy::default() }
+    }
+}
+
+/// Appends DataRows to the rotated data files, keeping the current file open between cycles,
+/// and state events to the "station_events_<period>.csv" files in the same directory, rotated and retained the same way.
+/// Every write is flushed and synced to disk, and a write that fails partway is cut off again, so retrying the batch doesn't duplicate rows.
+/// When a file is opened, a torn last line left by a crash is cut off
+/// and the existing header is compared with the current columns; a file with other columns is moved aside instead of appended to.
+pub struct CsvLog {
+    options: CsvConfig,
+    rotator: Rotator,
+    events: RecordLog,
+    open: Option<OpenFile>,
+}
+
+struct OpenFile {
+    path: PathBuf,
+    writer: Writer<File>,
+}
+
+impl CsvLog {
+    pub fn new(options: CsvConfig) -> Self {
+        let rotator = Rotator::new(&options.directory, &options.prefix, "csv", options.policy.clone());
+        let events = RecordLog::new(&options, "station_events");
+        Self { options, rotator, events, open: None }
+    }
+
+    pub fn options(&self) -> &CsvConfig {
+        &self.options
+    }
+
+    /// Writes the rows, each to the file of its period, and syncs them to disk. On failure, none of the rows are left in the files.
+    pub fn write(&mut self, datavec: &[DataRow]) -> io::Result<()> {
+        let mut rollback = Rollback::default();
+        let result = self.append(datavec, &mut rollback);
+        if result.is_err() {
+            self.undo(&rollback);
+        }
+        result
+    }
+
+    fn append(&mut self, datavec: &[DataRow], rollback: &mut Rollback) -> io::Result<()> {
+        let Some(first) = datavec.first() else {
+            return Ok(());
+        };
+        let header = header_of(first)?;
+        if let Some(open) = self.open.as_ref() {
+            rollback.record(&open.path)?;
+        }
+        for row in datavec {
+            let path = self.rotator.path_for(row.time())?;
+            if self.open.as_ref().is_none_or(|open| open.path != path) {
+                self.close()?;
+                self.rotator.activate(&path)?;
+                let open = open_checked(&path, &header, self.options.schema_header)?;
+                // After open_checked, which may have moved a file aside and buffers the header of a new one.
+                rollback.record(&path)?;
+                self.open = Some(open);
+            }
+            if let Some(open) = self.open.as_mut() {
+                open.writer.serialize(row).map_err(io::Error::other)?;
+            }
+        }
+        self.sync()
+    }
+
+    /// Flushes the open file and syncs it to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if let Some(open) = self.open.as_mut() {
+            open.writer.flush()?;
+            open.writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    pub fn close(&mut self) -> io::Result<()> {
+        self.sync()?;
+        self.open = None;
+        Ok(())
+    }
+
+    /// Drops the open file and cuts the files back to their length before the failed write.
+    fn undo(&mut self, rollback: &Rollback) {
+        self.open = None;
+        rollback.apply();
+    }
+}
+
+impl Sink for CsvLog {
+    fn write(&mut self, batch: &Batch) -> Result<(), Error> {
+        let mut rollback = Rollback::default();
+        let result = self.append(&batch.rows, &mut rollback)
+            .and_then(|()| self.events.append(&batch.events, |event| event.time, &mut rollback))
+            .map_err(|error| Error::StorageError { path: self.options.directory.display().to_string(), message: error.to_string() });
+        if result.is_err() {
+            self.undo(&rollback);
+        }
+        result
+    }
+}
+
+/// Records other than samples, e.g. state events or alerts, in .csv files named "<prefix>_<period>.csv"
+/// next to the data files, rotated, compressed and retained by the policy of the data files.
+pub struct RecordLog {
+    rotator: Rotator,
+}
+
+impl RecordLog {
+    pub fn new(options: &CsvConfig, prefix: &str) -> Self {
+        Self { rotator: Rotator::new(&options.directory, prefix, "csv", options.policy.clone()) }
+    }
+
+    /// Appends the records, each to the file of the period of `time_of` it. On failure, none of the records are left in the files.
+    pub fn write<T: Serialize>(&mut self, records: &[T], time_of: impl Fn(&T) -> DateTime<FixedOffset>) -> Result<(), Error> {
+        let mut rollback = Rollback::default();
+        let result = self.append(records, time_of, &mut rollback);
+        if result.is_err() {
+            rollback.apply();
+        }
+        result.map_err(|error| Error::StorageError { path: self.rotator.directory().display().to_string(), message: error.to_string() })
+    }
+
+    fn append<T: Serialize>(&mut self, records: &[T], time_of: impl Fn(&T) -> DateTime<FixedOffset>, rollback: &mut Rollback) -> io::Result<()> {
+        let mut start = 0;
+        while start < records.len() {
+            let path = self.rotator.path_for(time_of(&records[start]))?;
+            let mut end = start + 1;
+            while end < records.len() && self.rotator.path_for(time_of(&records[end]))? == path {
+                end += 1;
+            }
+            self.rotator.activate(&path)?;
+            rollback.record(&path)?;
+            append_records(&path, &records[start..end])?;
+            start = end;
+        }
+        Ok(())
+    }
+}
+
+/// Lengths of the files a write appends to, taken before the write, so a failed write can be cut off again.
+#[derive(Debug, Default)]
+struct Rollback(Vec<(PathBuf, u64)>);
+
+impl Rollback {
+    /// Notes the current length of `path`, 0 if it doesn't exist yet, unless it was noted before.
+    fn record(&mut self, path: &Path) -> io::Result<()> {
+        if self.0.iter().any(|(recorded, _)| recorded == path) {
+            return Ok(());
+        }
+        let len = match fs::metadata(path) {
+            Ok(metadata) => metadata.len(),
+            Err(error) if error.kind() == ErrorKind::NotFound => 0,
+            Err(error) => return Err(error),
+        };
+        self.0.push((path.to_path_buf(), len));
+        Ok(())
+    }
+
+    fn apply(&self) {
+        for (path, len) in &self.0 {
+            let result = OpenOptions::new().write(true).open(path).and_then(|file| {
+                file.set_len(*len)?;
+                file.sync_all()
+            });
+            match result {
+                Err(error) if error.kind() != ErrorKind::NotFound => eprintln!("Problem cutting off the failed write to {}. Error: {error}", path.display()),
+                _ => {},
+            }
+        }
+    }
+}
+
+impl Drop for CsvLog {
+    fn drop(&mut self) {
+        if let Err(error) = self.close() {
+            eprintln!("Problem closing the data file. Error: {error}");
+        }
+    }
+}
+
+/// The header record the current DataRow layout produces.
+fn header_of(row: &DataRow) -> io::Result<StringRecord> {
+    let mut wtr = WriterBuilder::new().has_headers(true).from_writer(vec![]);
+    wtr.serialize(row).map_err(io::Error::other)?;
+    let buffer = wtr.into_inner().map_err(|error| io::Error::other(error.to_string()))?;
+    let mut rdr = ReaderBuilder::new().has_headers(true).from_reader(buffer.as_slice());
+    Ok(rdr.headers().map_err(io::Error::other)?.clone())
+}
+
+/// Opens `path` for appending after repairing a torn last line and making sure it has the expected header.
+fn open_checked(path: &Path, header: &StringRecord, schema_header: bool) -> io::Result<OpenFile> {
+    if path.exists() {
+        truncate_torn_line(path)?;
+        let existing = ReaderBuilder::new().has_headers(true).comment(Some(b'#')).from_path(path).map_err(io::Error::other)?
+            .headers().map_err(io::Error::other)?.clone();
+        if !existing.is_empty() && &existing != header {
+            // The new name doesn't end in .csv, so the file is neither appended to nor compressed, but the retention still deletes it.
+            let aside = PathBuf::from(format!("{}.schema-{}", path.display(), chrono::Utc::now().timestamp()));
+            eprintln!("The header of {} does not match the current columns, moving it to {}.", path.display(), aside.display());
+            fs::rename(path, &aside)?;
+        }
+    }
+    let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+    let empty = file.metadata()?.len() == 0;
+    let has_header = !empty && !ReaderBuilder::new().has_headers(true).comment(Some(b'#')).from_path(path).map_err(io::Error::other)?
+        .headers().map_err(io::Error::other)?.is_empty();
+    if empty && schema_header {
+        writeln!(file, "# xbfisher schema {}", station::SCHEMA_VERSION)?;
+    }
+    let mut writer = WriterBuilder::new().has_headers(false).from_writer(file);
+    if !has_header {
+        writer.write_record(header).map_err(io::Error::other)?;
+    }
+    Ok(OpenFile { path: path.to_path_buf(), writer })
+}
+
+/// Appends the records to a .csv file, creating it and its directory if needed and writing the header if the file is new or empty.
+pub(crate) fn append_records<T: Serialize>(path: &Path, records: &[T]) -> io::Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let header = fs::metadata(path).map(|metadata| metadata.len() == 0).unwrap_or(true);
+    let file = OpenOptions::new().append(true).create(true).open(path)?;
+    let mut wtr = WriterBuilder::new().has_headers(header).from_writer(file);
+    for record in records {
+        wtr.serialize(record).map_err(io::Error::other)?;
+    }
+    wtr.flush()?;
+    wtr.get_ref().sync_data()
+}
+
+/// Cuts off an incomplete last line, e.g. one torn by a power cut, so appended records start on a line of their own.
+pub(crate) fn truncate_torn_line(path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(());
+    }
+    let mut last = [0u8; 1];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    if last[0] == b'\n' {
+        return Ok(());
+    }
+    // Search backwards for the end of the last complete line.
+    let mut end = len;
+    let mut keep = 0;
+    let mut buffer = vec![0u8; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(buffer.len() as u64);
+        let chunk = &mut buffer[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        if let Some(position) = chunk.iter().rposition(|byte| *byte == b'\n') {
+            keep = start + position as u64 + 1;
+            break;
+        }
+        end = start;
+    }
+    eprintln!("Removing a torn line of {} bytes at the end of {}.", len - keep, path.display());
+    file.set_len(keep)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stations::health::{HealthState, StateEvent};
+
+    fn row(no: u8, time: &str) -> DataRow {
+        DataRow::sample(no, DateTime::parse_from_rfc3339(time).unwrap(), 12.5)
+    }
+
+    fn lines(path: &Path) -> usize {
+        fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn a_failed_write_is_cut_off_so_the_retry_does_not_duplicate_rows() {
+        let directory = std::env::temp_dir().join(format!("xbfisher-csv-rollback-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let mut log = CsvLog::new(CsvConfig { directory: directory.clone(), ..CsvConfig::default() });
+        log.write(&[row(1, "2024-10-27T23:58:00+00:00")]).unwrap();
+        let first = directory.join("station_list_2024-10-27.csv");
+        assert_eq!(lines(&first), 2);
+        // The file of the next day can't be opened, after the rows of the first one were written.
+        let second = directory.join("station_list_2024-10-28.csv");
+        fs::create_dir_all(&second).unwrap();
+        let rows = [row(1, "2024-10-27T23:59:00+00:00"), row(2, "2024-10-27T23:59:00+00:00"), row(1, "2024-10-28T00:00:00+00:00")];
+        assert!(Sink::write(&mut log, &Batch::new(rows.to_vec(), vec![])).is_err());
+        assert_eq!(lines(&first), 2);
+        fs::remove_dir(&second).unwrap();
+        Sink::write(&mut log, &Batch::new(rows.to_vec(), vec![])).unwrap();
+        assert_eq!(lines(&first), 4);
+        assert_eq!(lines(&second), 2);
+        drop(log);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn state_events_are_rotated_like_the_data_files() {
+        let directory = std::env::temp_dir().join(format!("xbfisher-csv-events-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let mut log = CsvLog::new(CsvConfig { directory: directory.clone(), ..CsvConfig::default() });
+        let event = |time: &str| StateEvent {
+            time: DateTime::parse_from_rfc3339(time).unwrap(),
+            station_no: 1,
+            from: HealthState::Up,
+            to: HealthState::Down,
+            duration: std::time::Duration::from_secs(60),
+            reason: "no probe answered".into(),
+        };
+        let events = vec![event("2024-10-27T23:59:00+00:00"), event("2024-10-28T00:01:00+00:00"), event("2024-10-28T00:02:00+00:00")];
+        Sink::write(&mut log, &Batch::new(vec![row(1, "2024-10-28T00:02:00+00:00")], events)).unwrap();
+        assert_eq!(lines(&directory.join("station_events_2024-10-27.csv")), 2);
+        assert_eq!(lines(&directory.join("station_events_2024-10-28.csv")), 3);
+        assert_eq!(lines(&directory.join("station_list_2024-10-28.csv")), 2);
+        drop(log);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// An empty directory of its own for each test.
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("xbfisher-csv-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn header() -> String {
+        let record = header_of(&row(1, "2024-10-27T12:00:00+00:00")).unwrap();
+        record.iter().collect::<Vec<_>>().join(",")
+    }
+
+    #[test]
+    fn cuts_off_a_torn_last_line() {
+        let directory = directory("torn");
+        let path = directory.join("torn.csv");
+        fs::write(&path, "Time,Station No\n2024-10-27T12:00:00+00:00,1\n2024-10-27T12:01:00+00:00,").unwrap();
+        truncate_torn_line(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Time,Station No\n2024-10-27T12:00:00+00:00,1\n");
+        // A file ending in a complete line is left as it is.
+        truncate_torn_line(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Time,Station No\n2024-10-27T12:00:00+00:00,1\n");
+        // Without any line break the whole file is torn, including past the size of one read.
+        let long = "x".repeat(10_000);
+        fs::write(&path, &long).unwrap();
+        truncate_torn_line(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn appends_after_a_torn_line_on_a_line_of_its_own() {
+        let directory = directory("append-torn");
+        let path = directory.join("station_list_2024-10-27.csv");
+        fs::write(&path, format!("{}\n2024-10-27T12:00:00+00:00,1,12.", header())).unwrap();
+        let mut log = CsvLog::new(CsvConfig { directory: directory.clone(), ..CsvConfig::default() });
+        log.write(&[row(1, "2024-10-27T12:01:00+00:00")]).unwrap();
+        drop(log);
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], header());
+        assert!(lines[1].starts_with("2024-10-27T12:01:00+00:00,"), "{}", lines[1]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn an_empty_file_gets_a_header() {
+        let directory = directory("empty");
+        let path = directory.join("station_list_2024-10-27.csv");
+        fs::write(&path, "").unwrap();
+        let mut log = CsvLog::new(CsvConfig { directory: directory.clone(), schema_header: true, ..CsvConfig::default() });
+        log.write(&[row(1, "2024-10-27T12:00:00+00:00")]).unwrap();
+        drop(log);
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], format!("# xbfisher schema {}", station::SCHEMA_VERSION));
+        assert_eq!(lines[1], header());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn a_file_with_the_old_header_is_moved_aside() {
+        let directory = directory("aside");
+        let path = directory.join("station_list_2024-10-27.csv");
+        let old = "Time,Station No,Latency (ms),Packet Loss (%),CPU Temperature (C)\n27/10/2024 12:00,1,12.5,0,48.5\n";
+        fs::write(&path, old).unwrap();
+        let mut log = CsvLog::new(CsvConfig { directory: directory.clone(), ..CsvConfig::default() });
+        log.write(&[row(1, "2024-10-27T12:01:00+00:00")]).unwrap();
+        drop(log);
+        let aside = Rotator::new(&directory, "station_list", "csv", RotationPolicy::default()).aside_files().unwrap();
+        assert_eq!(aside.len(), 1);
+        assert_eq!(fs::read_to_string(&aside[0]).unwrap(), old);
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().next(), Some(header().as_str()));
+        assert_eq!(content.lines().count(), 2);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::stations::hosts::HostEntry;
+use crate::storage::influx;
+use crate::storage::sink::{Batch, Sink};
+use crate::storage::spool::Spool;
+use crate::tools::errors::Error;
+
+/// Encoding of the pushed data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpFormat {
+    /// InfluxDB line protocol, see `influx::encode`.
+    #[default]
+    LineProtocol,
+    /// JSON Lines with samples and state events, as written by the "jsonl" sink.
+    JsonLines,
+}
+
+/// Options of an "http" sink, which pushes batches to an HTTP endpoint, by default as InfluxDB line protocol.
+/// Example for InfluxDB 2:
+/// ```toml
+/// [[sink]]
+/// kind = "http"
+/// url = "http://influx.example.org:8086/api/v2/write?org=ops&bucket=stations"
+/// token = "..."
+/// spool = "./data/spool/influx"
+///
+/// [sink.tags]
+/// site = "north"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Write endpoint. For line protocol "precision=s" is added to the query if missing.
+    pub url: String,
+    #[serde(default)]
+    pub format: HttpFormat,
+    /// Sent as "Authorization: Token <token>", as InfluxDB 2 expects.
+    pub token: Option<String>,
+    /// Further request headers, e.g. "Authorization" for basic authentication.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Put before the measurement names of the line protocol.
+    #[serde(default)]
+    pub measurement_prefix: String,
+    /// Tags added to every line of the line protocol.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Lines (or JSON records) per request.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Directory holding the requests not yet delivered. Every http sink needs its own.
+    #[serde(default = "default_spool")]
+    pub spool: PathBuf,
+    /// Bytes the spool may take before the oldest requests are deleted.
+    #[serde(default = "default_max_spool")]
+    pub max_spool: u64,
+    /// Request timeout in seconds.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_batch_size() -> usize {
+    5000
+}
+
+fn default_spool() -> PathBuf {
+    PathBuf::from("./data/spool")
+}
+
+fn default_max_spool() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl HttpConfig {
+    fn endpoint(&self) -> String {
+        if self.format != HttpFormat::LineProtocol || self.url.contains("precision=") {
+            return self.url.clone();
+        }
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        format!("{}{separator}precision=s", self.url)
+    }
+}
+
+/// Why a request was not delivered.
+enum PushError {
+    /// The server refused the data itself, retrying won't help.
+    Rejected(String),
+    /// The server could not be reached or is unavailable for now.
+    Unavailable(String),
+}
+
+/// Pushes batches to an HTTP endpoint. Every batch is spooled to disk first and removed once the server accepted it,
+/// so data gathered while the server is unreachable is delivered, oldest first, once it is back.
+/// Requests the server rejects as malformed, unauthorized or sent to an unknown endpoint are renamed to ".rejected" and skipped;
+/// they count towards `max_spool` like the waiting ones.
+pub struct HttpSink {
+    options: HttpConfig,
+    agent: ureq::Agent,
+    spool: Spool,
+    names: HashMap<u8, String>,
+    failing: bool,
+}
+
+impl HttpSink {
+    pub fn open(options: HttpConfig) -> Result<Self, Error> {
+        let spool = Spool::open(&options.spool, options.max_spool).map_err(|error| Error::StorageError { path: options.spool.display().to_string(), message: error.to_string() })?;
+        let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(options.timeout)).build();
+        Ok(Self { options, agent, spool, names: HashMap::new(), failing: false })
+    }
+
+    fn encode(&self, batch: &Batch) -> Result<Vec<String>, Error> {
+        Ok(match self.options.format {
+            HttpFormat::LineProtocol => influx::encode(batch, &self.options.measurement_prefix, &self.options.tags, &self.names),
+            HttpFormat::JsonLines => batch.to_json_lines().map_err(|error| Error::SinkError { sink: self.options.url.clone(), message: error.to_string() })?
+                .lines().map(str::to_string).collect(),
+        })
+    }
+
+    fn post(&self, body: &str) -> Result<(), PushError> {
+        let content_type = match self.options.format {
+            HttpFormat::LineProtocol => "text/plain; charset=utf-8",
+            HttpFormat::JsonLines => "application/x-ndjson",
+        };
+        let mut request = self.agent.post(&self.options.endpoint()).set("Content-Type", content_type);
+        if let Some(token) = &self.options.token {
+            request = request.set("Authorization", &format!("Token {token}"));
+        }
+        for (name, value) in &self.options.headers {
+            request = request.set(name, value);
+        }
+        match request.send_string(body) {
+            Ok(_) => Ok(()),
+            // Malformed data, or credentials and an endpoint that won't change until the config does.
+            Err(ureq::Error::Status(code @ (400 | 401 | 403 | 404 | 413 | 422), response)) => {
+                Err(PushError::Rejected(format!("status {code}: {}", response.into_string().unwrap_or_default().trim())))
+            },
+            Err(error) => Err(PushError::Unavailable(error.to_string())),
+        }
+    }
+
+    /// Delivers the spooled requests oldest first, stopping at the first one the server doesn't take.
+    fn push_spooled(&mut self) -> Result<(), Error> {
+        let files = self.spool.files().map_err(|error| self.spool_error(error))?;
+        let waiting = files.len();
+        for (i, path) in files.into_iter().enumerate() {
+            let body = fs::read_to_string(&path).map_err(|error| self.spool_error(error))?;
+            match self.post(&body) {
+                Ok(()) => fs::remove_file(&path).map_err(|error| self.spool_error(error))?,
+                Err(PushError::Rejected(message)) => {
+                    eprintln!("{} rejected {}, keeping it as .rejected. Error: {message}", self.options.url, path.display());
+                    fs::rename(&path, path.with_extension("rejected")).map_err(|error| self.spool_error(error))?;
+                },
+                Err(PushError::Unavailable(message)) => {
+                    if !self.failing {
+                        eprintln!("Problem pushing to {}, spooling to {} until it is back. Error: {message}", self.options.url, self.spool.directory().display());
+                        self.failing = true;
+                    }
+                    eprintln!("{} requests waiting in {}.", waiting - i, self.spool.directory().display());
+                    return Ok(());
+                },
+            }
+        }
+        if self.failing {
+            eprintln!("Pushing to {} again, delivered {waiting} spooled requests.", self.options.url);
+            self.failing = false;
+        }
+        Ok(())
+    }
+
+    fn spool_error(&self, error: std::io::Error) -> Error {
+        Error::StorageError { path: self.spool.directory().display().to_string(), message: error.to_string() }
+    }
+}
+
+impl Sink for HttpSink {
+    fn stations(&mut self, entries: &[HostEntry]) -> Result<(), Error> {
+        self.names = entries.iter().map(|entry| (entry.station_no, entry.usr_name.clone())).collect();
+        Ok(())
+    }
+
+    /// Only fails if the batch could not be spooled; delivery problems are retried from the spool.
+    fn write(&mut self, batch: &Batch) -> Result<(), Error> {
+        let lines = self.encode(batch)?;
+        for chunk in lines.chunks(self.options.batch_size.max(1)) {
+            self.spool.push(&(chunk.join("\n") + "\n")).map_err(|error| self.spool_error(error))?;
+        }
+        self.push_spooled()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stations::station::DataRow;
+    use crate::tools::stand_in;
+    use chrono::DateTime;
+    use std::path::Path;
+
+    fn options(url: &str, spool: &Path) -> HttpConfig {
+        HttpConfig {
+            url: url.to_string(),
+            format: HttpFormat::LineProtocol,
+            token: Some("secret".into()),
+            headers: BTreeMap::from([("X-Site".to_string(), "north".to_string())]),
+            measurement_prefix: String::new(),
+            tags: BTreeMap::from([("site".to_string(), "north".to_string())]),
+            batch_size: 5000,
+            spool: spool.to_path_buf(),
+            max_spool: 1024 * 1024,
+            timeout: 5,
+        }
+    }
+
+    /// An empty spool directory of its own for each test.
+    fn spool(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("xbfisher-http-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn batch(latency: f64) -> Batch {
+        Batch::new(vec![DataRow::sample(3, DateTime::parse_from_rfc3339("2024-10-27T12:00:00+00:00").unwrap(), latency)], vec![])
+    }
+
+    fn entries() -> Vec<HostEntry> {
+        vec![HostEntry { station_no: 3, usr_name: "pi".into(), ip_address: "10.0.0.3".into(), hostname: None, tags: BTreeMap::new() }]
+    }
+
+    fn files(directory: &Path, extension: &str) -> usize {
+        fs::read_dir(directory).unwrap().filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|found| found == extension)).count()
+    }
+
+    #[test]
+    fn posts_line_protocol_with_the_configured_headers() {
+        let server = stand_in::http(vec![204]);
+        let spool = spool("post");
+        let mut sink = HttpSink::open(options(&server.url("/api/v2/write?bucket=stations"), &spool)).unwrap();
+        sink.stations(&entries()).unwrap();
+        sink.write(&batch(12.5)).unwrap();
+        let requests = server.received();
+        assert_eq!(requests.len(), 1);
+        let request = &requests[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.target, "/api/v2/write?bucket=stations&precision=s");
+        assert_eq!(request.header("Content-Type"), Some("text/plain; charset=utf-8"));
+        assert_eq!(request.header("Authorization"), Some("Token secret"));
+        assert_eq!(request.header("X-Site"), Some("north"));
+        assert_eq!(request.body, [
+            "latency,site=north,station=pi,station_no=3 value=12.5,status=\"ok\" 1730030400",
+            "packet_loss,site=north,station=pi,station_no=3 value=0,status=\"ok\" 1730030400",
+            "cpu_temperature,site=north,station=pi,station_no=3 value=48.5,status=\"ok\" 1730030400",
+            "",
+        ].join("\n"));
+        assert_eq!(files(&spool, "spool"), 0);
+        fs::remove_dir_all(&spool).unwrap();
+    }
+
+    #[test]
+    fn posts_json_lines_to_the_url_as_given() {
+        let server = stand_in::http(vec![200]);
+        let spool = spool("json");
+        let mut sink = HttpSink::open(HttpConfig { format: HttpFormat::JsonLines, token: None, ..options(&server.url("/ingest"), &spool) }).unwrap();
+        sink.write(&batch(12.5)).unwrap();
+        let request = server.received().remove(0);
+        assert_eq!(request.target, "/ingest");
+        assert_eq!(request.header("Content-Type"), Some("application/x-ndjson"));
+        assert_eq!(request.header("Authorization"), None);
+        let record: serde_json::Value = serde_json::from_str(request.body.lines().next().unwrap()).unwrap();
+        assert_eq!(record["Latency (ms)"], 12.5);
+        fs::remove_dir_all(&spool).unwrap();
+    }
+
+    #[test]
+    fn keeps_rejected_requests_aside_and_goes_on() {
+        let server = stand_in::http(vec![400, 204]);
+        let spool = spool("rejected");
+        let mut sink = HttpSink::open(options(&server.url("/write"), &spool)).unwrap();
+        sink.write(&batch(12.5)).unwrap();
+        assert_eq!((files(&spool, "spool"), files(&spool, "rejected")), (0, 1));
+        sink.write(&batch(13.5)).unwrap();
+        assert_eq!((files(&spool, "spool"), files(&spool, "rejected")), (0, 1));
+        let requests = server.received();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].body.contains("value=13.5"));
+        fs::remove_dir_all(&spool).unwrap();
+    }
+
+    #[test]
+    fn rejects_requests_refused_for_their_credentials_or_endpoint() {
+        for status in [401, 403, 404] {
+            let server = stand_in::http(vec![status]);
+            let spool = spool(&format!("refused-{status}"));
+            let mut sink = HttpSink::open(options(&server.url("/write"), &spool)).unwrap();
+            sink.write(&batch(12.5)).unwrap();
+            assert_eq!((files(&spool, "spool"), files(&spool, "rejected")), (0, 1), "status {status}");
+            fs::remove_dir_all(&spool).unwrap();
+        }
+    }
+
+    #[test]
+    fn the_spool_limit_bounds_the_rejected_requests() {
+        let server = stand_in::http(vec![400; 8]);
+        let spool = spool("rejected-limit");
+        let body = HttpSink::open(options(&server.url("/write"), &spool)).unwrap().encode(&batch(0.5)).unwrap().join("\n").len() as u64 + 1;
+        let mut sink = HttpSink::open(HttpConfig { max_spool: 3 * body, ..options(&server.url("/write"), &spool) }).unwrap();
+        for latency in 0..8 {
+            sink.write(&batch(latency as f64 + 0.5)).unwrap();
+        }
+        assert_eq!((files(&spool, "spool"), files(&spool, "rejected")), (0, 3));
+        fs::remove_dir_all(&spool).unwrap();
+    }
+
+    #[test]
+    fn spools_on_server_errors_and_delivers_oldest_first() {
+        let server = stand_in::http(vec![503, 204, 204]);
+        let spool = spool("unavailable");
+        let mut sink = HttpSink::open(options(&server.url("/write"), &spool)).unwrap();
+        sink.write(&batch(12.5)).unwrap();
+        assert_eq!(files(&spool, "spool"), 1);
+        sink.write(&batch(13.5)).unwrap();
+        assert_eq!(files(&spool, "spool"), 0);
+        let bodies: Vec<String> = server.received().into_iter().map(|request| request.body).collect();
+        assert_eq!(bodies.len(), 3);
+        assert_eq!(bodies[0], bodies[1]);
+        assert!(bodies[1].contains("value=12.5") && bodies[2].contains("value=13.5"));
+        fs::remove_dir_all(&spool).unwrap();
+    }
+
+    #[test]
+    fn spools_while_the_server_is_unreachable() {
+        let spool = spool("refused");
+        let mut sink = HttpSink::open(options(&format!("http://{}/write", stand_in::refused_address()), &spool)).unwrap();
+        sink.write(&batch(12.5)).unwrap();
+        sink.write(&batch(13.5)).unwrap();
+        assert_eq!(files(&spool, "spool"), 2);
+        fs::remove_dir_all(&spool).unwrap();
+    }
+}
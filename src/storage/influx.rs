@@ -0,0 +1,56 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::alerting::rules::Metric;
+use crate::storage::sink::Batch;
+
+/// Encodes samples and state events as InfluxDB line protocol with second precision.
+/// Every metric is a measurement of its own with a "value" and a "status" field, tagged with the station number and user name.
+/// State events go to the "station_state" measurement.
+pub fn encode(batch: &Batch, prefix: &str, tags: &BTreeMap<String, String>, names: &HashMap<u8, String>) -> Vec<String> {
+    let mut lines = vec![];
+    for row in &batch.rows {
+        let tag_set = tag_set(row.station_no(), tags, names);
+        for metric in Metric::ALL {
+            let mut fields = format!("status=\"{}\"", metric.status(row));
+            if let Some(value) = metric.value(row).filter(|value| value.is_finite()) {
+                fields = format!("value={value},{fields}");
+            }
+            lines.push(format!("{}{tag_set} {fields} {}", escape_measurement(&format!("{prefix}{metric}")), row.time().timestamp()));
+        }
+    }
+    for event in &batch.events {
+        lines.push(format!(
+            "{}{} state=\"{}\",previous=\"{}\",duration_s={}i,reason=\"{}\" {}",
+            escape_measurement(&format!("{prefix}station_state")),
+            tag_set(event.station_no, tags, names),
+            event.to,
+            event.from,
+            event.duration.as_secs(),
+            escape_string(&event.reason),
+            event.time.timestamp(),
+        ));
+    }
+    lines
+}
+
+/// ",key=value" pairs sorted by key, as recommended for write performance.
+fn tag_set(station_no: u8, tags: &BTreeMap<String, String>, names: &HashMap<u8, String>) -> String {
+    let mut all = tags.clone();
+    all.insert("station_no".into(), station_no.to_string());
+    if let Some(name) = names.get(&station_no) {
+        all.insert("station".into(), name.clone());
+    }
+    all.iter().filter(|(_, value)| !value.is_empty()).map(|(key, value)| format!(",{}={}", escape_tag(key), escape_tag(value))).collect()
+}
+
+fn escape_measurement(name: &str) -> String {
+    name.replace(',', "\\,").replace(' ', "\\ ")
+}
+
+fn escape_tag(text: &str) -> String {
+    text.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+fn escape_string(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', " ")
+}
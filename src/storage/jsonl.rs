@@ -0,0 +1,89 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::storage::csv::truncate_torn_line;
+use crate::storage::rotation::{RotationPolicy, Rotator};
+use crate::storage::sink::{Batch, Sink};
+use crate::tools::errors::Error;
+
+/// Options of a "jsonl" sink: rotated JSON Lines files with one sample or state event per line.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JsonlConfig {
+    pub directory: PathBuf,
+    /// Start of the file names, followed by the period, e.g. "station_list_2024-10-27.jsonl".
+    pub prefix: String,
+    #[serde(flatten)]
+    pub policy: RotationPolicy,
+}
+
+impl Default for JsonlConfig {
+    fn default() -> Self {
+        Self { directory: PathBuf::from("./data"), prefix: "station_list".into(), policy: RotationPolicy::default() }
+    }
+}
+
+/// Appends samples and state events as JSON objects to the rotated .jsonl files. Every write is synced to disk.
+pub struct JsonlLog {
+    options: JsonlConfig,
+    rotator: Rotator,
+    open: Option<(PathBuf, BufWriter<File>)>,
+}
+
+impl JsonlLog {
+    pub fn new(options: JsonlConfig) -> Self {
+        let rotator = Rotator::new(&options.directory, &options.prefix, "jsonl", options.policy.clone());
+        Self { options, rotator, open: None }
+    }
+
+    fn append(&mut self, batch: &Batch) -> io::Result<()> {
+        for record in batch.records() {
+            let path = self.rotator.path_for(record.time())?;
+            if self.open.as_ref().is_none_or(|(open, _)| *open != path) {
+                self.close()?;
+                self.rotator.activate(&path)?;
+                if path.exists() {
+                    truncate_torn_line(&path)?;
+                }
+                let file = OpenOptions::new().append(true).create(true).open(&path)?;
+                self.open = Some((path, BufWriter::new(file)));
+            }
+            if let Some((_, writer)) = self.open.as_mut() {
+                serde_json::to_writer(&mut *writer, &record)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        self.sync()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        if let Some((_, writer)) = self.open.as_mut() {
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.sync()?;
+        self.open = None;
+        Ok(())
+    }
+}
+
+impl Sink for JsonlLog {
+    fn write(&mut self, batch: &Batch) -> Result<(), Error> {
+        self.append(batch).map_err(|error| Error::StorageError { path: self.options.directory.display().to_string(), message: error.to_string() })
+    }
+}
+
+impl Drop for JsonlLog {
+    fn drop(&mut self) {
+        if let Err(error) = self.close() {
+            eprintln!("Problem closing the JSON Lines file. Error: {error}");
+        }
+    }
+}
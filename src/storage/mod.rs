@@ -0,0 +1,12 @@
+pub mod csv;
+pub mod http;
+pub mod influx;
+pub mod jsonl;
+pub mod mqtt;
+pub mod reader;
+pub mod rotation;
+pub mod sink;
+pub mod spool;
+pub mod sqlite;
+pub mod stdout;
+pub mod tcp;
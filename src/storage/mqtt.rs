@@ -0,0 +1,343 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS, RecvTimeoutError};
+use serde::Deserialize;
+
+use crate::alerting::rules::Metric;
+use crate::storage::sink::{Batch, Sink};
+use crate::tools::errors::Error;
+
+/// How often the connection thread checks whether the sink was closed.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often a write waiting for room in the full request queue tries again.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Requests the client queues for the connection thread.
+const QUEUE_CAPACITY: usize = 100;
+
+/// Options of an "mqtt" sink, which publishes the latest reading of every station.
+/// Topics, with "xbfisher" as the default prefix:
+/// - "xbfisher/<station no>/<metric>": the value, e.g. "xbfisher/3/latency" = "12.5", left out if it could not be collected
+/// - "xbfisher/<station no>/<metric>/status": "ok", "no_reply", "timeout" or "error"
+/// - "xbfisher/<station no>/sample": the whole sample as JSON
+/// - "xbfisher/<station no>/state": the health state, published on every state change
+/// - "xbfisher/status": "online" while connected, "offline" after shutting down or, through the Last Will, losing the connection
+///
+/// Example:
+/// ```toml
+/// [[sink]]
+/// kind = "mqtt"
+/// host = "broker.example.org"
+/// username = "xbfisher"
+/// password = "..."
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Defaults to "xbfisher-<pid>".
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+    /// Publish the readings as retained messages, so new subscribers get the last values right away.
+    #[serde(default = "default_retain")]
+    pub retain: bool,
+    /// 0, 1 or 2.
+    #[serde(default = "default_qos")]
+    pub qos: u8,
+    /// Seconds between keep-alive pings.
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: u64,
+    /// Seconds to wait before reconnecting after the connection was lost.
+    #[serde(default = "default_reconnect")]
+    pub reconnect: u64,
+    /// Seconds to wait for the broker when the sink is opened, and for it to take a message while writing.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_topic_prefix() -> String {
+    "xbfisher".into()
+}
+
+fn default_retain() -> bool {
+    true
+}
+
+fn default_qos() -> u8 {
+    1
+}
+
+fn default_keep_alive() -> u64 {
+    30
+}
+
+fn default_reconnect() -> u64 {
+    5
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+/// Publishes readings to an MQTT broker. The connection is kept by a thread of its own, which reconnects after a lost connection.
+/// Writes fail while disconnected, so the sink's error policy decides what happens to the readings in the meantime
+/// and the fan-out opens a new connection for the next attempt.
+pub struct MqttSink {
+    options: MqttConfig,
+    qos: QoS,
+    client: Client,
+    connected: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    connection: Option<JoinHandle<()>>,
+}
+
+impl MqttSink {
+    /// Connects to the broker, failing if it doesn't accept the connection within the timeout.
+    pub fn open(options: MqttConfig) -> Result<Self, Error> {
+        let qos = match options.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            qos => return Err(Error::SinkError { sink: options.host.clone(), message: format!("invalid qos {qos}, use 0, 1 or 2") }),
+        };
+        let client_id = options.client_id.clone().unwrap_or_else(|| format!("xbfisher-{}", std::process::id()));
+        let status_topic = format!("{}/status", options.topic_prefix);
+        let mut mqtt_options = MqttOptions::new(client_id, options.host.clone(), options.port);
+        mqtt_options.set_keep_alive(Duration::from_secs(options.keep_alive.max(5)));
+        mqtt_options.set_last_will(LastWill::new(status_topic.clone(), "offline", qos, true));
+        if let Some(username) = &options.username {
+            mqtt_options.set_credentials(username.clone(), options.password.clone().unwrap_or_default());
+        }
+        let (client, mut connection) = Client::new(mqtt_options, QUEUE_CAPACITY);
+
+        let connected = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (thread_client, thread_connected, thread_stop) = (client.clone(), Arc::clone(&connected), Arc::clone(&stop));
+        let address = format!("{}:{}", options.host, options.port);
+        let reconnect = Duration::from_secs(options.reconnect.max(1));
+        let connection_thread = thread::spawn(move || {
+            loop {
+                // Polling with a timeout lets the thread notice `stop` even if the broker never closes the connection.
+                let notification = match connection.recv_timeout(POLL_INTERVAL) {
+                    Ok(notification) => notification,
+                    Err(RecvTimeoutError::Timeout) if thread_stop.load(Ordering::SeqCst) => break,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                match notification {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        eprintln!("Connected to the MQTT broker at {address}.");
+                        thread_connected.store(true, Ordering::SeqCst);
+                        if let Err(error) = thread_client.try_publish(status_topic.as_str(), qos, true, "online") {
+                            eprintln!("Problem publishing to the MQTT broker at {address}. Error: {error}");
+                        }
+                    },
+                    Ok(_) => {},
+                    Err(error) => {
+                        let was_connected = thread_connected.swap(false, Ordering::SeqCst);
+                        if thread_stop.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        if was_connected {
+                            eprintln!("Lost the connection to the MQTT broker at {address}, reconnecting every {} s. Error: {error}", reconnect.as_secs());
+                        }
+                        thread::sleep(reconnect);
+                    },
+                }
+            }
+        });
+        let sink = Self { options, qos, client, connected, stop, connection: Some(connection_thread) };
+        let deadline = Instant::now() + Duration::from_secs(sink.options.timeout);
+        while !sink.connected.load(Ordering::SeqCst) {
+            if Instant::now() >= deadline {
+                return Err(Error::SinkError { sink: sink.options.host.clone(), message: format!("no connection to the broker within {} s", sink.options.timeout) });
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        Ok(sink)
+    }
+
+    fn topic(&self, station_no: u8, name: &str) -> String {
+        format!("{}/{station_no}/{name}", self.options.topic_prefix)
+    }
+
+    /// Queues the message for the connection thread. A batch holds more messages than the queue, so a full queue is waited on
+    /// while the connection thread hands the messages to the broker, up to `timeout` seconds and as long as the connection holds.
+    fn publish(&self, topic: String, payload: String) -> Result<(), Error> {
+        let deadline = Instant::now() + Duration::from_secs(self.options.timeout);
+        loop {
+            match self.client.try_publish(topic.as_str(), self.qos, self.options.retain, payload.as_str()) {
+                Ok(()) => return Ok(()),
+                Err(_) if self.connected.load(Ordering::SeqCst) && Instant::now() < deadline => thread::sleep(QUEUE_POLL_INTERVAL),
+                Err(error) => return Err(Error::SinkError { sink: self.options.host.clone(), message: error.to_string() }),
+            }
+        }
+    }
+}
+
+impl Sink for MqttSink {
+    fn write(&mut self, batch: &Batch) -> Result<(), Error> {
+        if !self.connected.load(Ordering::SeqCst) {
+            return Err(Error::SinkError { sink: self.options.host.clone(), message: "not connected to the broker".into() });
+        }
+        for row in &batch.rows {
+            let station_no = row.station_no();
+            for metric in Metric::ALL {
+                if let Some(value) = metric.value(row) {
+                    self.publish(self.topic(station_no, &metric.to_string()), value.to_string())?;
+                }
+                self.publish(self.topic(station_no, &format!("{metric}/status")), metric.status(row).to_string())?;
+            }
+            let sample = serde_json::to_string(row).map_err(|error| Error::SinkError { sink: self.options.host.clone(), message: error.to_string() })?;
+            self.publish(self.topic(station_no, "sample"), sample)?;
+        }
+        for event in &batch.events {
+            self.publish(self.topic(event.station_no, "state"), event.to.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MqttSink {
+    /// Marks xbfisher offline and disconnects cleanly, so the broker doesn't publish the Last Will.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if self.connected.load(Ordering::SeqCst) {
+            let _ = self.client.try_publish(format!("{}/status", self.options.topic_prefix), self.qos, true, "offline");
+        }
+        let _ = self.client.try_disconnect();
+        if let Some(connection) = self.connection.take() {
+            let _ = connection.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stations::health::{HealthState, StateEvent};
+    use crate::stations::station::DataRow;
+    use crate::tools::stand_in::{self, MqttMessage};
+    use chrono::DateTime;
+
+    fn options(broker: &stand_in::StandIn<MqttMessage>) -> MqttConfig {
+        MqttConfig {
+            host: broker.address.ip().to_string(),
+            port: broker.address.port(),
+            client_id: Some("xbfisher-test".into()),
+            username: None,
+            password: None,
+            topic_prefix: default_topic_prefix(),
+            retain: true,
+            qos: 1,
+            keep_alive: default_keep_alive(),
+            reconnect: 1,
+            timeout: 5,
+        }
+    }
+
+    fn batch(latency: f64) -> Batch {
+        let time = DateTime::parse_from_rfc3339("2024-10-27T12:00:00+00:00").unwrap();
+        let event = StateEvent { time, station_no: 3, from: HealthState::Unknown, to: HealthState::Up, duration: Duration::ZERO, reason: "answered".into() };
+        Batch::new(vec![DataRow::sample(3, time, latency)], vec![event])
+    }
+
+    fn message(topic: &str, payload: &str) -> MqttMessage {
+        MqttMessage { topic: topic.into(), payload: payload.into(), qos: 1, retain: true }
+    }
+
+    /// Waits for the next message on `topic`, skipping the others.
+    fn wait_for(broker: &stand_in::StandIn<MqttMessage>, topic: &str) -> MqttMessage {
+        loop {
+            let message = broker.wait_next().unwrap_or_else(|| panic!("nothing published on {topic}"));
+            if message.topic == topic {
+                return message;
+            }
+        }
+    }
+
+    #[test]
+    fn publishes_the_readings_under_the_topic_layout() {
+        let broker = stand_in::mqtt(None);
+        let mut sink = MqttSink::open(options(&broker)).unwrap();
+        assert_eq!(broker.wait_next(), Some(message("xbfisher/status", "online")));
+        sink.write(&batch(12.5)).unwrap();
+        let expected = [
+            ("xbfisher/3/latency", "12.5"),
+            ("xbfisher/3/latency/status", "ok"),
+            ("xbfisher/3/packet_loss", "0"),
+            ("xbfisher/3/packet_loss/status", "ok"),
+            ("xbfisher/3/cpu_temperature", "48.5"),
+            ("xbfisher/3/cpu_temperature/status", "ok"),
+        ];
+        for (topic, payload) in expected {
+            assert_eq!(broker.wait_next(), Some(message(topic, payload)));
+        }
+        let sample = broker.wait_next().unwrap();
+        assert_eq!(sample.topic, "xbfisher/3/sample");
+        let sample: serde_json::Value = serde_json::from_str(&sample.payload).unwrap();
+        assert_eq!((sample["Station No"].as_u64(), sample["Latency (ms)"].as_f64()), (Some(3), Some(12.5)));
+        assert_eq!(broker.wait_next(), Some(message("xbfisher/3/state", "up")));
+        // A clean shutdown marks xbfisher offline itself and disconnects, so the broker has no Last Will to publish.
+        drop(sink);
+        assert_eq!(broker.wait_next(), Some(message("xbfisher/status", "offline")));
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(broker.received(), []);
+    }
+
+    #[test]
+    fn publishes_every_reading_of_a_large_batch() {
+        let broker = stand_in::mqtt(None);
+        let mut sink = MqttSink::open(options(&broker)).unwrap();
+        assert_eq!(broker.wait_next(), Some(message("xbfisher/status", "online")));
+        let time = DateTime::parse_from_rfc3339("2024-10-27T12:00:00+00:00").unwrap();
+        let rows: Vec<DataRow> = (0..500).map(|i| DataRow::sample((i % 250) as u8 + 1, time, i as f64)).collect();
+        sink.write(&Batch::new(rows, vec![])).unwrap();
+        // Three values, three statuses and the sample of every row.
+        let mut latencies = vec![];
+        for _ in 0..500 * 7 {
+            let message = broker.wait_next().expect("a reading went missing");
+            if message.topic.ends_with("/latency") {
+                latencies.push(message.payload.parse::<f64>().unwrap());
+            }
+        }
+        assert_eq!(latencies, (0..500).map(|i| i as f64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reconnects_after_losing_the_connection() {
+        // The broker drops the first connection after the "online" status and the first reading.
+        let broker = stand_in::mqtt(Some(2));
+        let mut sink = MqttSink::open(options(&broker)).unwrap();
+        assert_eq!(broker.wait_next(), Some(message("xbfisher/status", "online")));
+        sink.write(&batch(12.5)).unwrap();
+        assert_eq!(broker.wait_next(), Some(message("xbfisher/3/latency", "12.5")));
+        assert_eq!(wait_for(&broker, "xbfisher/status"), message("xbfisher/status", "offline"));
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while sink.write(&Batch::default()).is_ok() {
+            assert!(Instant::now() < deadline, "writes still succeed without a connection");
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(wait_for(&broker, "xbfisher/status"), message("xbfisher/status", "online"));
+        sink.write(&batch(13.5)).unwrap();
+        loop {
+            let message = wait_for(&broker, "xbfisher/3/latency");
+            if message.payload == "13.5" {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveTime, TimeZone};
+use csv::{ReaderBuilder, StringRecord};
+
+use crate::storage::rotation::{self, RotationPolicy, Rotator};
+use crate::tools::errors::Error;
+
+/// A sample read back from the data files, whichever schema version wrote it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub time: DateTime<FixedOffset>,
+    pub station_no: u8,
+    /// Mean latency in ms, None if the station did not answer.
+    pub latency: Option<f64>,
+    pub packet_loss: Option<f64>,
+    pub cpu_temperature: Option<f64>,
+    pub error: Option<String>,
+}
+
+impl Sample {
+    /// Whether the station answered the probe.
+    pub fn reachable(&self) -> bool {
+        self.latency.is_some()
+    }
+}
+
+/// Samples read from a set of data files, with the number of records that could not be read.
+#[derive(Debug, Clone, Default)]
+pub struct Samples {
+    pub samples: Vec<Sample>,
+    pub skipped: usize,
+}
+
+/// Reads the data files of a csv directory: the rotated files, compressed or not, and the older
+/// "<prefix>_date_<M>_<D>_<YYYY>.csv" files with "H:M" times. Files whose name lies outside `from`..`to` are not opened.
+/// The samples are sorted by time.
+pub fn read_samples(directory: &Path, prefix: &str, from: Option<DateTime<FixedOffset>>, to: Option<DateTime<FixedOffset>>) -> Result<Samples, Error> {
+    let storage_error = |path: &Path, error: io::Error| Error::StorageError { path: path.display().to_string(), message: error.to_string() };
+    let rotator = Rotator::new(directory, prefix, "csv", RotationPolicy::default());
+    let mut result = Samples::default();
+    let legacy = rotator.legacy_files().map_err(|error| storage_error(directory, error))?;
+    for path in legacy.into_iter().chain(rotator.data_files().map_err(|error| storage_error(directory, error))?) {
+        let date = rotation::file_date(&path, prefix);
+        // A day in a file name is a local day, allow for the offset of the bounds.
+        if let Some(date) = date {
+            if from.is_some_and(|from| date < from.date_naive().pred_opt().unwrap_or(NaiveDate::MIN))
+                || to.is_some_and(|to| date > to.date_naive().succ_opt().unwrap_or(NaiveDate::MAX)) {
+                continue;
+            }
+        }
+        read_file(&path, date, &mut result).map_err(|error| storage_error(&path, error))?;
+    }
+    result.samples.retain(|sample| from.is_none_or(|from| sample.time >= from) && to.is_none_or(|to| sample.time < to));
+    result.samples.sort_by_key(|sample| sample.time);
+    Ok(result)
+}
+
+fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(path)?);
+    let name = path.to_string_lossy();
+    Ok(if name.ends_with(".gz") {
+        Box::new(flate2::read::MultiGzDecoder::new(file))
+    } else if name.ends_with(".zst") {
+        Box::new(zstd::Decoder::new(file)?)
+    } else {
+        Box::new(file)
+    })
+}
+
+fn read_file(path: &Path, date: Option<NaiveDate>, result: &mut Samples) -> io::Result<()> {
+    let mut rdr = ReaderBuilder::new().has_headers(true).flexible(true).comment(Some(b'#')).from_reader(open(path)?);
+    let headers = rdr.headers().map_err(|error| io::Error::other(format!("{}: {error}", path.display())))?.clone();
+    let columns = Columns::new(&headers);
+    for record in rdr.records() {
+        let Ok(record) = record else {
+            result.skipped += 1;
+            continue;
+        };
+        // The first versions wrote a whole cycle as one record, repeating the columns for every station.
+        for chunk in 0..record.len().div_ceil(columns.width.max(1)) {
+            match columns.sample(&record, chunk * columns.width, date) {
+                Some(sample) => result.samples.push(sample),
+                None => result.skipped += 1,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Positions of the known columns within the (first) group of columns of a header.
+struct Columns {
+    width: usize,
+    time: Option<usize>,
+    station_no: Option<usize>,
+    latency: Option<usize>,
+    packet_loss: Option<usize>,
+    cpu_temperature: Option<usize>,
+    error: Option<usize>,
+}
+
+impl Columns {
+    fn new(headers: &StringRecord) -> Self {
+        let width = (1..headers.len()).find(|width| headers.get(*width) == headers.get(0)).unwrap_or(headers.len());
+        let find = |names: &[&str]| headers.iter().take(width).position(|header| names.contains(&header));
+        Self {
+            width,
+            time: find(&["Time"]),
+            station_no: find(&["Station No"]),
+            latency: find(&["Latency (ms)", "Latency"]),
+            packet_loss: find(&["Packet Loss (%)", "Packet Loss"]),
+            cpu_temperature: find(&["CPU Temperature (C)", "CPU Temperature"]),
+            error: find(&["Error"]),
+        }
+    }
+
+    fn sample(&self, record: &StringRecord, offset: usize, date: Option<NaiveDate>) -> Option<Sample> {
+        let field = |column: Option<usize>| column.and_then(|column| record.get(offset + column)).map(str::trim);
+        let number = |column: Option<usize>| field(column).and_then(|value| value.parse::<f64>().ok()).filter(|value| value.is_finite());
+        let time = parse_time(field(self.time)?, date)?;
+        // Older versions wrote the error message into the metric columns.
+        let legacy_errors: Vec<&str> = [self.latency, self.cpu_temperature].into_iter()
+            .filter_map(|column| field(column).filter(|value| value.starts_with("Error")))
+            .collect();
+        let error = field(self.error).filter(|error| !error.is_empty()).map(str::to_string)
+            .or_else(|| Some(legacy_errors.join("; ")).filter(|error| !error.is_empty()));
+        Some(Sample {
+            time,
+            station_no: field(self.station_no)?.parse().ok()?,
+            latency: number(self.latency),
+            packet_loss: number(self.packet_loss),
+            cpu_temperature: number(self.cpu_temperature),
+            error,
+        })
+    }
+}
+
+/// RFC 3339 timestamps, or the "H:M" local times of the oldest files combined with the day in their name.
+fn parse_time(text: &str, date: Option<NaiveDate>) -> Option<DateTime<FixedOffset>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time);
+    }
+    let time = NaiveTime::parse_from_str(text, "%H:%M").ok()?;
+    Local.from_local_datetime(&date?.and_time(time)).earliest().map(|time| time.fixed_offset())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use crate::stations::station::DataRow;
+    use crate::storage::csv::{CsvConfig, CsvLog};
+
+    fn time(text: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(text).unwrap()
+    }
+
+    /// A data directory with a file of every schema version and compression:
+    /// - 2024-10-24: the oldest layout, one record per cycle with "H:M" times, NaN latencies and errors in the temperature column
+    /// - 2024-10-25: zstd compressed, current columns
+    /// - 2024-10-26: gzip compressed, current columns, with an unreachable sample and an unreadable record
+    /// - 2024-10-27: written by the csv sink with a schema comment
+    fn fixture(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("xbfisher-reader-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        // As the first versions wrote it: unpadded minutes, NaN for the mean latency of no replies and the error in the temperature column.
+        fs::write(directory.join("station_list_date_10_24_2024.csv"), "\
+            Time,Station No,Latency,CPU Temperature,Time,Station No,Latency,CPU Temperature\n\
+            9:5,1,NaN,Error: internal error,9:5,2,2.75,39.5\n\
+            12:0,1,3.5,41.25,12:0,2,NaN,Error: internal error\n").unwrap();
+        let header = "Time,Epoch,Station No,Latency (ms),Latency Status,Packet Loss (%),CPU Temperature (C),Temperature Status,Error\n";
+        let mut encoder = zstd::Encoder::new(fs::File::create(directory.join("station_list_2024-10-25.csv.zst")).unwrap(), 0).unwrap();
+        writeln!(encoder, "{header}2024-10-25T12:00:00+00:00,1729857600,1,4.5,ok,0,42,ok,").unwrap();
+        encoder.finish().unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(fs::File::create(directory.join("station_list_2024-10-26.csv.gz")).unwrap(), flate2::Compression::default());
+        write!(encoder, "{header}\
+            2024-10-26T12:00:00+00:00,1729944000,1,5.5,ok,0,43,ok,\n\
+            2024-10-26T12:01:00+00:00,1729944060,1,,no_reply,100,,error,no probe answered\n\
+            yesterday,0,1,1,ok,0,1,ok,\n").unwrap();
+        encoder.finish().unwrap();
+        let mut log = CsvLog::new(CsvConfig { directory: directory.clone(), schema_header: true, ..CsvConfig::default() });
+        log.write(&[
+            DataRow::sample(1, time("2024-10-27T12:00:00+00:00"), 10.0),
+            DataRow::sample(2, time("2024-10-27T12:00:00+00:00"), 20.0),
+            DataRow::sample(1, time("2024-10-27T12:01:00+00:00"), 11.0),
+        ]).unwrap();
+        directory
+    }
+
+    #[test]
+    fn reads_every_schema_version_and_compression() {
+        let directory = fixture("all");
+        let read = read_samples(&directory, "station_list", None, None).unwrap();
+        assert_eq!(read.skipped, 1);
+        let latencies: Vec<(u8, Option<f64>)> = read.samples.iter().map(|sample| (sample.station_no, sample.latency)).collect();
+        assert_eq!(latencies, [(1, None), (2, Some(2.75)), (1, Some(3.5)), (2, None), (1, Some(4.5)), (1, Some(5.5)), (1, None), (1, Some(10.0)), (2, Some(20.0)), (1, Some(11.0))]);
+
+        let at = |hour, minute| Local.from_local_datetime(&NaiveDate::from_ymd_opt(2024, 10, 24).unwrap().and_hms_opt(hour, minute, 0).unwrap()).earliest().unwrap().fixed_offset();
+        let legacy = |time, station_no, latency, cpu_temperature, error: Option<&str>| Sample { time, station_no, latency, packet_loss: None, cpu_temperature, error: error.map(str::to_string) };
+        assert_eq!(read.samples[..4], [
+            legacy(at(9, 5), 1, None, None, Some("Error: internal error")),
+            legacy(at(9, 5), 2, Some(2.75), Some(39.5), None),
+            legacy(at(12, 0), 1, Some(3.5), Some(41.25), None),
+            legacy(at(12, 0), 2, None, None, Some("Error: internal error")),
+        ]);
+        assert!(!read.samples[3].reachable());
+
+        let unreachable = &read.samples[6];
+        assert_eq!(unreachable.time, time("2024-10-26T12:01:00+00:00"));
+        assert!(!unreachable.reachable());
+        assert_eq!((unreachable.packet_loss, unreachable.cpu_temperature), (Some(100.0), None));
+        assert_eq!(unreachable.error.as_deref(), Some("no probe answered"));
+
+        let current = &read.samples[7];
+        assert_eq!((current.packet_loss, current.cpu_temperature, current.error.as_deref()), (Some(0.0), Some(48.5), None));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_samples_within_the_range() {
+        let directory = fixture("range");
+        let read = read_samples(&directory, "station_list", Some(time("2024-10-26T12:00:00+00:00")), Some(time("2024-10-27T12:01:00+00:00"))).unwrap();
+        let times: Vec<String> = read.samples.iter().map(|sample| crate::tools::time::rfc3339(&sample.time)).collect();
+        assert_eq!(times, ["2024-10-26T12:00:00+00:00", "2024-10-26T12:01:00+00:00", "2024-10-27T12:00:00+00:00", "2024-10-27T12:00:00+00:00"]);
+        // Files of days well outside the range are not even opened.
+        let read = read_samples(&directory, "station_list", Some(time("2024-10-28T00:00:00+00:00")), None).unwrap();
+        assert!(read.samples.is_empty());
+        assert_eq!(read.skipped, 0);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,453 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use serde::Deserialize;
+
+/// When a new data file is started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    /// One file per day: "<prefix>_2024-10-27.csv".
+    #[default]
+    Daily,
+    /// One file per hour: "<prefix>_2024-10-27T13.csv".
+    Hourly,
+    /// A new file whenever the current one reaches `max_size`, named after its first sample: "<prefix>_2024-10-27T13-05-00.csv".
+    Size,
+}
+
+/// How closed data files are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+}
+
+/// Default size limit of the `size` rotation.
+pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Rotation, compression and retention of the files of one data directory.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RotationPolicy {
+    pub rotation: Rotation,
+    /// Size in bytes at which a file is closed and the next one started. With daily or hourly rotation
+    /// the next file of the same period gets a sequence number: "<prefix>_2024-10-27_001.csv".
+    pub max_size: Option<u64>,
+    pub compression: Compression,
+    /// Closed files older than this many days are deleted.
+    pub retention_days: Option<u64>,
+    /// Oldest closed files are deleted while all files together are larger than this many bytes.
+    pub retention_size: Option<u64>,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self { rotation: Rotation::Daily, max_size: None, compression: Compression::None, retention_days: None, retention_size: None }
+    }
+}
+
+/// Picks the file each sample goes to and maintains the closed files.
+/// All state is derived from the directory listing, so a restart continues the current file.
+/// Files of the older "<prefix>_date_<M>_<D>_<YYYY>.csv" naming are never written to or compressed, only deleted by the retention.
+#[derive(Debug)]
+pub struct Rotator {
+    directory: PathBuf,
+    prefix: String,
+    /// Extension of the uncompressed files, e.g. "csv".
+    extension: String,
+    policy: RotationPolicy,
+    active: Option<PathBuf>,
+    /// The compression and retention started by the last switch to another file.
+    maintenance: Option<JoinHandle<()>>,
+}
+
+impl Rotator {
+    pub fn new(directory: &Path, prefix: &str, extension: &str, policy: RotationPolicy) -> Self {
+        Self { directory: directory.to_path_buf(), prefix: prefix.to_string(), extension: extension.to_string(), policy, active: None, maintenance: None }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn active(&self) -> Option<&Path> {
+        self.active.as_deref()
+    }
+
+    /// Returns the file a sample taken at `time` belongs to.
+    pub fn path_for(&self, time: DateTime<FixedOffset>) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.directory)?;
+        let path = match self.policy.rotation {
+            Rotation::Daily => self.period_path(&time.format("%Y-%m-%d").to_string())?,
+            Rotation::Hourly => self.period_path(&time.format("%Y-%m-%dT%H").to_string())?,
+            Rotation::Size => self.size_path(time)?,
+        };
+        Ok(path)
+    }
+
+    /// Makes `path` the file being written to. Switching to another file compresses the closed ones and applies the retention
+    /// on a background thread, so the previous file has to be flushed before. Compressing a large file takes a few seconds,
+    /// which would otherwise hold up the write of the first sample of the new file; problems are reported on stderr.
+    pub fn activate(&mut self, path: &Path) -> io::Result<()> {
+        if self.active.as_deref() != Some(path) {
+            self.active = Some(path.to_path_buf());
+            self.finish_maintenance();
+            let rotator = self.clone_settings();
+            let handle = thread::Builder::new().name("rotation".into()).spawn(move || {
+                if let Err(error) = rotator.maintain() {
+                    eprintln!("Problem maintaining the data files in {}. Error: {error}", rotator.directory.display());
+                }
+            })?;
+            self.maintenance = Some(handle);
+        }
+        Ok(())
+    }
+
+    /// Waits for the compression and retention started by the last switch of files.
+    pub fn finish_maintenance(&mut self) {
+        if let Some(handle) = self.maintenance.take() {
+            let _ = handle.join();
+        }
+    }
+
+    fn clone_settings(&self) -> Self {
+        Self { directory: self.directory.clone(), prefix: self.prefix.clone(), extension: self.extension.clone(), policy: self.policy.clone(), active: self.active.clone(), maintenance: None }
+    }
+
+    /// Newest file of the period, or the next sequence number if it is full.
+    fn period_path(&self, period: &str) -> io::Result<PathBuf> {
+        let stem = format!("{}_{period}", self.prefix);
+        let mut sequence = 0;
+        loop {
+            let name = match sequence {
+                0 => format!("{stem}.{}", self.extension),
+                n => format!("{stem}_{n:03}.{}", self.extension),
+            };
+            let path = self.directory.join(&name);
+            let closed = self.policy.compression.extension().is_some_and(|extension| self.directory.join(format!("{name}.{extension}")).exists());
+            let next = self.directory.join(format!("{stem}_{:03}.{}", sequence + 1, self.extension));
+            if !closed && !self.is_full(&path) && !next.exists() {
+                return Ok(path);
+            }
+            sequence += 1;
+        }
+    }
+
+    fn size_path(&self, time: DateTime<FixedOffset>) -> io::Result<PathBuf> {
+        if let Some(path) = self.data_files()?.into_iter().rev().find(|path| self.is_plain(path)) {
+            if !self.is_full(&path) {
+                return Ok(path);
+            }
+        }
+        Ok(self.directory.join(format!("{}_{}.{}", self.prefix, time.format("%Y-%m-%dT%H-%M-%S"), self.extension)))
+    }
+
+    fn is_full(&self, path: &Path) -> bool {
+        let max_size = match self.policy.rotation {
+            Rotation::Size => self.policy.max_size.unwrap_or(DEFAULT_MAX_SIZE),
+            _ => match self.policy.max_size {
+                Some(max_size) => max_size,
+                None => return false,
+            },
+        };
+        fs::metadata(path).is_ok_and(|metadata| metadata.len() >= max_size)
+    }
+
+    /// Whether `path` is an uncompressed data file.
+    fn is_plain(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|extension| extension == self.extension.as_str())
+    }
+
+    /// Data files of this rotator (plain and compressed), sorted by name and so by age.
+    /// Only names starting with an ISO date after the prefix match, so older files are left out.
+    pub fn data_files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = self.matching_files(|name| self.is_data_name(name) && !name.starts_with("date_") && NaiveDate::parse_from_str(name.get(..10).unwrap_or_default(), "%Y-%m-%d").is_ok())?;
+        files.sort();
+        Ok(files)
+    }
+
+    /// Files of the older "<prefix>_date_<M>_<D>_<YYYY>.csv" naming (plain and compressed), sorted by the day in their name.
+    pub fn legacy_files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files: Vec<(NaiveDate, PathBuf)> = self.matching_files(|name| self.is_data_name(name) && name.starts_with("date_"))?.into_iter()
+            .filter_map(|path| Some((file_date(&path, &self.prefix)?, path)))
+            .collect();
+        files.sort();
+        Ok(files.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Files with a header that did not match the current columns, moved aside as "<data file>.schema-<timestamp>", sorted by name.
+    pub fn aside_files(&self) -> io::Result<Vec<PathBuf>> {
+        let marker = format!(".{}.schema-", self.extension);
+        let mut files = self.matching_files(|name| name.split_once(&marker).is_some_and(|(_, timestamp)| timestamp.parse::<i64>().is_ok()))?;
+        files.sort();
+        Ok(files)
+    }
+
+    /// Whether "<prefix>_<rest>" names a data file, "<rest>" ending in the extension, optionally compressed.
+    fn is_data_name(&self, rest: &str) -> bool {
+        rest.trim_end_matches(".gz").trim_end_matches(".zst").ends_with(&format!(".{}", self.extension))
+    }
+
+    /// Files named "<prefix>_<rest>" whose <rest> passes `accept`.
+    fn matching_files(&self, accept: impl Fn(&str) -> bool) -> io::Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if name.strip_prefix(&self.prefix).and_then(|rest| rest.strip_prefix('_')).is_some_and(&accept) {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Closed files, including the ones moved aside, from the oldest to the newest day in their name, older files first within a day.
+    fn closed_files(&self) -> io::Result<Vec<PathBuf>> {
+        let legacy = self.legacy_files()?;
+        let mut files: Vec<(Option<NaiveDate>, bool, PathBuf)> = legacy.into_iter().map(|path| (file_date(&path, &self.prefix), false, path))
+            .chain(self.data_files()?.into_iter().chain(self.aside_files()?).map(|path| (file_date(&path, &self.prefix), true, path)))
+            .filter(|(_, _, path)| Some(path) != self.active.as_ref())
+            .collect();
+        files.sort();
+        Ok(files.into_iter().map(|(_, _, path)| path).collect())
+    }
+
+    /// Compresses every closed file and deletes the files falling out of the retention.
+    /// Interrupted compressions leave the original file in place and are redone.
+    pub fn maintain(&self) -> io::Result<()> {
+        // Only the temporary files of this rotator's own extension, another rotator may share the directory and prefix.
+        for path in self.matching_files(|name| name.strip_suffix(".tmp").is_some_and(|name| self.is_data_name(name)))? {
+            fs::remove_file(&path)?;
+        }
+        let closed: Vec<PathBuf> = self.data_files()?.into_iter().filter(|path| Some(path) != self.active.as_ref()).collect();
+        if let Some(extension) = self.policy.compression.extension() {
+            for path in closed.iter().filter(|path| self.is_plain(path)) {
+                if let Err(error) = compress(path, self.policy.compression, extension) {
+                    eprintln!("Problem compressing {}. Error: {error}", path.display());
+                }
+            }
+        }
+        self.apply_retention()
+    }
+
+    fn apply_retention(&self) -> io::Result<()> {
+        let mut closed: Vec<(PathBuf, u64, SystemTime)> = vec![];
+        let mut total: u64 = 0;
+        if let Some(active) = self.active.as_ref() {
+            total += fs::metadata(active).map(|metadata| metadata.len()).unwrap_or(0);
+        }
+        for path in self.closed_files()? {
+            let metadata = fs::metadata(&path)?;
+            total += metadata.len();
+            closed.push((path, metadata.len(), metadata.modified()?));
+        }
+        if let Some(days) = self.policy.retention_days {
+            let cutoff = SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60);
+            for (path, size, modified) in closed.iter().filter(|(_, _, modified)| *modified < cutoff) {
+                eprintln!("Deleting {} (last written {} days ago).", path.display(), SystemTime::now().duration_since(*modified).unwrap_or_default().as_secs() / 86400);
+                fs::remove_file(path)?;
+                total -= size;
+            }
+            closed.retain(|(_, _, modified)| *modified >= cutoff);
+        }
+        if let Some(max_total) = self.policy.retention_size {
+            for (path, size, _) in &closed {
+                if total <= max_total {
+                    break;
+                }
+                eprintln!("Deleting {} to keep the data directory below {max_total} bytes.", path.display());
+                fs::remove_file(path)?;
+                total -= size;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Rotator {
+    fn drop(&mut self) {
+        self.finish_maintenance();
+    }
+}
+
+/// The day in the name of a data file, from either naming scheme.
+pub fn file_date(path: &Path, prefix: &str) -> Option<NaiveDate> {
+    let name = path.file_name()?.to_str()?.strip_prefix(prefix)?.strip_prefix('_')?;
+    if let Some(legacy) = name.strip_prefix("date_") {
+        let stem = legacy.split('.').next()?;
+        let parts: Vec<u32> = stem.split('_').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+        let [month, day, year] = parts[..] else {
+            return None;
+        };
+        return NaiveDate::from_ymd_opt(year as i32, month, day);
+    }
+    NaiveDate::parse_from_str(name.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// Compresses `path` into "<path>.<extension>" through a temporary file and removes the original once the result is durable.
+/// The result keeps the modification time of the original, which the retention goes by.
+fn compress(path: &Path, compression: Compression, extension: &str) -> io::Result<()> {
+    let target = PathBuf::from(format!("{}.{extension}", path.display()));
+    let temporary = PathBuf::from(format!("{}.{extension}.tmp", path.display()));
+    let input = File::open(path)?;
+    let modified = input.metadata()?.modified()?;
+    let mut input = BufReader::new(input);
+    let output = BufWriter::new(File::create(&temporary)?);
+    let file = match compression {
+        Compression::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?
+        },
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?
+        },
+        Compression::None => return Ok(()),
+    };
+    let mut file = file.into_inner().map_err(|error| error.into_error())?;
+    file.flush()?;
+    file.set_modified(modified)?;
+    file.sync_all()?;
+    fs::rename(&temporary, &target)?;
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for each test.
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("xbfisher-rotation-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn names(files: Vec<PathBuf>) -> Vec<String> {
+        files.iter().map(|path| path.file_name().unwrap().to_string_lossy().into_owned()).collect()
+    }
+
+    fn time(text: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(text).unwrap()
+    }
+
+    #[test]
+    fn legacy_files_are_kept_apart_and_ordered_by_their_day() {
+        let directory = directory("legacy");
+        for name in ["station_list_date_10_27_2024.csv", "station_list_date_9_1_2024.csv.gz", "station_list_2024-10-28.csv", "station_list_2024-10-28T13-05-00.csv.zst", "station_list_events.csv", "other_2024-10-28.csv"] {
+            fs::write(directory.join(name), "x").unwrap();
+        }
+        let rotator = Rotator::new(&directory, "station_list", "csv", RotationPolicy::default());
+        assert_eq!(names(rotator.data_files().unwrap()), ["station_list_2024-10-28.csv", "station_list_2024-10-28T13-05-00.csv.zst"]);
+        assert_eq!(names(rotator.legacy_files().unwrap()), ["station_list_date_9_1_2024.csv.gz", "station_list_date_10_27_2024.csv"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn size_rotation_never_continues_a_legacy_file() {
+        let directory = directory("size");
+        fs::write(directory.join("station_list_date_10_27_2024.csv"), "x").unwrap();
+        let rotator = Rotator::new(&directory, "station_list", "csv", RotationPolicy { rotation: Rotation::Size, ..RotationPolicy::default() });
+        let path = rotator.path_for(time("2024-10-28T13:05:00+01:00")).unwrap();
+        assert_eq!(path, directory.join("station_list_2024-10-28T13-05-00.csv"));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn retention_deletes_the_oldest_days_first() {
+        let directory = directory("retention");
+        for name in ["station_list_date_10_27_2024.csv", "station_list_date_9_1_2024.csv", "station_list_2024-10-28.csv", "station_list_2024-10-29.csv"] {
+            fs::write(directory.join(name), "0123456789").unwrap();
+        }
+        let mut rotator = Rotator::new(&directory, "station_list", "csv", RotationPolicy { retention_size: Some(25), ..RotationPolicy::default() });
+        rotator.activate(&directory.join("station_list_2024-10-29.csv")).unwrap();
+        rotator.finish_maintenance();
+        assert!(rotator.legacy_files().unwrap().is_empty());
+        assert_eq!(names(rotator.data_files().unwrap()), ["station_list_2024-10-28.csv", "station_list_2024-10-29.csv"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn retention_includes_files_moved_aside() {
+        let directory = directory("aside");
+        for name in ["station_list_2024-10-27.csv.schema-1730000000", "station_list_2024-10-28.csv", "station_list_2024-10-29.csv"] {
+            fs::write(directory.join(name), "0123456789").unwrap();
+        }
+        let mut rotator = Rotator::new(&directory, "station_list", "csv", RotationPolicy { retention_size: Some(25), ..RotationPolicy::default() });
+        assert_eq!(names(rotator.aside_files().unwrap()), ["station_list_2024-10-27.csv.schema-1730000000"]);
+        assert_eq!(names(rotator.data_files().unwrap()), ["station_list_2024-10-28.csv", "station_list_2024-10-29.csv"]);
+        rotator.activate(&directory.join("station_list_2024-10-29.csv")).unwrap();
+        rotator.finish_maintenance();
+        assert!(rotator.aside_files().unwrap().is_empty());
+        assert_eq!(names(rotator.data_files().unwrap()), ["station_list_2024-10-28.csv", "station_list_2024-10-29.csv"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn compressed_files_keep_the_age_the_retention_goes_by() {
+        let directory = directory("compressed-age");
+        let day = Duration::from_secs(24 * 60 * 60);
+        for (name, age) in [("station_list_2024-09-01.csv", 40), ("station_list_2024-10-01.csv", 10), ("station_list_2024-10-29.csv", 0)] {
+            let path = directory.join(name);
+            fs::write(&path, "0123456789").unwrap();
+            File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now() - day * age).unwrap();
+        }
+        let policy = RotationPolicy { compression: Compression::Gzip, retention_days: Some(30), ..RotationPolicy::default() };
+        let mut rotator = Rotator::new(&directory, "station_list", "csv", policy);
+        rotator.activate(&directory.join("station_list_2024-10-29.csv")).unwrap();
+        rotator.finish_maintenance();
+        assert_eq!(names(rotator.data_files().unwrap()), ["station_list_2024-10-01.csv.gz", "station_list_2024-10-29.csv"]);
+        let age = SystemTime::now().duration_since(fs::metadata(directory.join("station_list_2024-10-01.csv.gz")).unwrap().modified().unwrap()).unwrap();
+        assert!(age >= day * 10 && age < day * 11, "{age:?}");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn leaves_the_temporary_files_of_other_extensions() {
+        let directory = directory("temporary");
+        for name in ["station_list_2024-10-28.csv.gz.tmp", "station_list_2024-10-28.jsonl.gz.tmp", "station_list_2024-10-28.jsonl.zst.tmp"] {
+            fs::write(directory.join(name), "x").unwrap();
+        }
+        let rotator = Rotator::new(&directory, "station_list", "jsonl", RotationPolicy::default());
+        rotator.maintain().unwrap();
+        let mut left = names(fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().path()).collect());
+        left.sort();
+        assert_eq!(left, ["station_list_2024-10-28.csv.gz.tmp"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn closed_files_are_compressed_in_the_background() {
+        let directory = directory("compression");
+        fs::write(directory.join("station_list_2024-10-28.csv"), "Time,Station No\n").unwrap();
+        let mut rotator = Rotator::new(&directory, "station_list", "csv", RotationPolicy { compression: Compression::Gzip, ..RotationPolicy::default() });
+        rotator.activate(&directory.join("station_list_2024-10-29.csv")).unwrap();
+        rotator.finish_maintenance();
+        assert_eq!(names(rotator.data_files().unwrap()), ["station_list_2024-10-28.csv.gz"]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,586 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+use crate::stations::health::StateEvent;
+use crate::stations::hosts::HostEntry;
+use crate::stations::station::DataRow;
+use crate::storage::csv::{CsvConfig, CsvLog};
+use crate::storage::http::{HttpConfig, HttpSink};
+use crate::storage::jsonl::{JsonlConfig, JsonlLog};
+use crate::storage::mqtt::{MqttConfig, MqttSink};
+use crate::storage::sqlite::{SqliteConfig, SqliteStore};
+use crate::storage::stdout::{StdoutConfig, StdoutSink};
+use crate::storage::tcp::{TcpConfig, TcpSink};
+use crate::tools::errors::Error;
+
+/// The output of one or more gather cycles.
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+    pub rows: Vec<DataRow>,
+    pub events: Vec<StateEvent>,
+}
+
+impl Batch {
+    pub fn new(rows: Vec<DataRow>, events: Vec<StateEvent>) -> Self {
+        Self { rows, events }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty() && self.events.is_empty()
+    }
+
+    /// Samples followed by state events, each tagged with its type.
+    pub fn records(&self) -> impl Iterator<Item = Record<'_>> {
+        self.rows.iter().map(Record::Sample).chain(self.events.iter().map(Record::StateEvent))
+    }
+
+    /// The records as JSON Lines, one object per line.
+    pub fn to_json_lines(&self) -> serde_json::Result<String> {
+        let mut text = String::new();
+        for record in self.records() {
+            text.push_str(&serde_json::to_string(&record)?);
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    fn append(&mut self, other: Batch) {
+        self.rows.extend(other.rows);
+        self.events.extend(other.events);
+    }
+
+    /// Drops the oldest rows and events beyond `limit` of each, returning how many were dropped.
+    fn keep_newest(&mut self, limit: usize) -> usize {
+        let rows = self.rows.len().saturating_sub(limit);
+        let events = self.events.len().saturating_sub(limit);
+        self.rows.drain(..rows);
+        self.events.drain(..events);
+        rows + events
+    }
+}
+
+/// A sample or state event in the JSON outputs, told apart by the "type" field.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record<'a> {
+    Sample(&'a DataRow),
+    StateEvent(&'a StateEvent),
+}
+
+impl Record<'_> {
+    pub fn time(&self) -> DateTime<FixedOffset> {
+        match self {
+            Record::Sample(row) => row.time(),
+            Record::StateEvent(event) => event.time,
+        }
+    }
+}
+
+/// A destination for gathered data.
+pub trait Sink: Send {
+    /// Receives the logged stations when the sink is opened and after every reload.
+    fn stations(&mut self, _entries: &[HostEntry]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write(&mut self, batch: &Batch) -> Result<(), Error>;
+}
+
+/// What a sink does with data it failed to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorPolicy {
+    /// Keep the data, up to `buffer` rows, and write it together with the next batch.
+    #[default]
+    Retry,
+    /// Discard the data.
+    Drop,
+    /// Stop using the sink until the next reload.
+    Disable,
+}
+
+/// A sink from a [[sink]] table of the config file, selected by its `kind`.
+/// Without any [[sink]] table the data goes to the [csv] files and, if configured, the [sqlite] database.
+/// Example:
+/// ```toml
+/// [[sink]]
+/// kind = "csv"
+/// directory = "./data"
+///
+/// [[sink]]
+/// kind = "jsonl"
+/// directory = "./data"
+/// compression = "gzip"
+///
+/// [[sink]]
+/// kind = "tcp"
+/// address = "collector.example.org:5170"
+/// on_error = "retry"
+/// buffer = 5000
+///
+/// [[sink]]
+/// kind = "http"
+/// url = "http://influx.example.org:8086/api/v2/write?org=ops&bucket=stations"
+/// token = "..."
+///
+/// [[sink]]
+/// kind = "mqtt"
+/// host = "broker.example.org"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SinkConfig {
+    /// Name used in messages, defaults to the kind. Must be unique.
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub on_error: ErrorPolicy,
+    /// Gather cycles collected before they are written together.
+    #[serde(default = "default_batch")]
+    pub batch: usize,
+    /// Rows (and state events) kept for a retry while the sink fails. The oldest are dropped beyond this.
+    #[serde(default = "default_buffer")]
+    pub buffer: usize,
+    /// Gather cycles waiting for a busy sink. Further cycles are dropped for this sink until it catches up.
+    #[serde(default = "default_queue")]
+    pub queue: usize,
+    #[serde(flatten)]
+    pub kind: SinkKind,
+}
+
+fn default_batch() -> usize {
+    1
+}
+
+fn default_buffer() -> usize {
+    10000
+}
+
+fn default_queue() -> usize {
+    100
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SinkKind {
+    Csv(CsvConfig),
+    Jsonl(JsonlConfig),
+    Sqlite(SqliteConfig),
+    Stdout(StdoutConfig),
+    Tcp(TcpConfig),
+    Http(HttpConfig),
+    Mqtt(MqttConfig),
+}
+
+impl SinkKind {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SinkKind::Csv(_) => "csv",
+            SinkKind::Jsonl(_) => "jsonl",
+            SinkKind::Sqlite(_) => "sqlite",
+            SinkKind::Stdout(_) => "stdout",
+            SinkKind::Tcp(_) => "tcp",
+            SinkKind::Http(_) => "http",
+            SinkKind::Mqtt(_) => "mqtt",
+        }
+    }
+
+    pub fn open(&self) -> Result<Box<dyn Sink>, Error> {
+        Ok(match self {
+            SinkKind::Csv(config) => Box::new(CsvLog::new(config.clone())),
+            SinkKind::Jsonl(config) => Box::new(JsonlLog::new(config.clone())),
+            SinkKind::Sqlite(config) => Box::new(SqliteStore::open(config)?),
+            SinkKind::Stdout(config) => Box::new(StdoutSink::new(config.clone())),
+            SinkKind::Tcp(config) => Box::new(TcpSink::new(config.clone())),
+            SinkKind::Http(config) => Box::new(HttpSink::open(config.clone())?),
+            SinkKind::Mqtt(config) => Box::new(MqttSink::open(config.clone())?),
+        })
+    }
+}
+
+impl SinkConfig {
+    /// A sink with the default buffering and error policy.
+    pub fn new(kind: SinkKind) -> Self {
+        Self { name: None, on_error: ErrorPolicy::default(), batch: default_batch(), buffer: default_buffer(), queue: default_queue(), kind }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(self.kind.kind())
+    }
+}
+
+enum Message {
+    Stations(Vec<HostEntry>),
+    Batch(Batch),
+    /// The config was reloaded and the sink kept, which turns a disabled sink back on.
+    Reload,
+    /// Answered once the messages before it are handled.
+    #[cfg(test)]
+    Sync(mpsc::Sender<()>),
+}
+
+/// Sends every batch to all configured sinks. Each sink runs on a thread of its own with its own queue,
+/// so a slow or failing sink holds up neither data collection nor the other sinks.
+pub struct FanOut {
+    workers: Vec<Worker>,
+    stations: Vec<HostEntry>,
+}
+
+struct Worker {
+    config: SinkConfig,
+    sender: Option<SyncSender<Message>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FanOut {
+    pub fn spawn(configs: &[SinkConfig]) -> Self {
+        Self { workers: configs.iter().cloned().map(Worker::spawn).collect(), stations: vec![] }
+    }
+
+    /// Keeps the sinks whose config is unchanged and replaces the others, waiting until the replaced ones have written their data.
+    /// Kept sinks that were disabled by their error policy are used again.
+    pub fn reconfigure(&mut self, configs: &[SinkConfig]) {
+        let mut previous: Vec<Option<Worker>> = self.workers.drain(..).map(Some).collect();
+        for config in configs {
+            match previous.iter_mut().find(|slot| slot.as_ref().is_some_and(|worker| &worker.config == config)) {
+                Some(slot) => {
+                    if let Some(worker) = slot.take() {
+                        worker.send(Message::Reload);
+                        self.workers.push(worker);
+                    }
+                },
+                None => {
+                    let worker = Worker::spawn(config.clone());
+                    worker.send(Message::Stations(self.stations.clone()));
+                    self.workers.push(worker);
+                },
+            }
+        }
+        for mut worker in previous.into_iter().flatten() {
+            worker.close();
+        }
+    }
+
+    pub fn stations(&mut self, entries: &[HostEntry]) {
+        self.stations = entries.to_vec();
+        for worker in &self.workers {
+            worker.send(Message::Stations(self.stations.clone()));
+        }
+    }
+
+    pub fn write(&self, batch: &Batch) {
+        if batch.is_empty() {
+            return;
+        }
+        for worker in &self.workers {
+            worker.send(Message::Batch(batch.clone()));
+        }
+    }
+
+    /// Waits until every sink has handled the queued data.
+    #[cfg(test)]
+    fn sync(&self) {
+        for worker in &self.workers {
+            let (sender, receiver) = mpsc::channel();
+            worker.send(Message::Sync(sender));
+            let _ = receiver.recv();
+        }
+    }
+
+    /// Waits until every sink has written the queued data (or given up on it).
+    pub fn shutdown(mut self) {
+        self.close();
+    }
+
+    fn close(&mut self) {
+        for worker in &mut self.workers {
+            worker.sender.take();
+        }
+        for mut worker in self.workers.drain(..) {
+            worker.close();
+        }
+    }
+}
+
+impl Drop for FanOut {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Worker {
+    fn spawn(config: SinkConfig) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(config.queue.max(1));
+        let delivery = Delivery::new(config.clone());
+        let thread = thread::spawn(move || delivery.run(receiver));
+        Self { config, sender: Some(sender), thread: Some(thread) }
+    }
+
+    fn send(&self, message: Message) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        match sender.try_send(message) {
+            Ok(()) => {},
+            Err(TrySendError::Full(_)) => eprintln!("Sink {} is falling behind, dropping this cycle for it.", self.config.name()),
+            Err(TrySendError::Disconnected(_)) => eprintln!("Sink {} has stopped, dropping this cycle for it.", self.config.name()),
+        }
+    }
+
+    fn close(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// State of a sink thread: the open sink and the data not yet written to it.
+struct Delivery {
+    config: SinkConfig,
+    sink: Option<Box<dyn Sink>>,
+    stations: Vec<HostEntry>,
+    stations_sent: bool,
+    pending: Batch,
+    cycles: usize,
+    failing: bool,
+    disabled: bool,
+}
+
+impl Delivery {
+    fn new(config: SinkConfig) -> Self {
+        Self { config, sink: None, stations: vec![], stations_sent: false, pending: Batch::default(), cycles: 0, failing: false, disabled: false }
+    }
+
+    fn run(mut self, receiver: Receiver<Message>) {
+        for message in receiver {
+            match message {
+                Message::Stations(entries) => {
+                    self.stations = entries;
+                    self.stations_sent = false;
+                },
+                Message::Batch(batch) => {
+                    self.pending.append(batch);
+                    self.cycles += 1;
+                    if self.cycles >= self.config.batch {
+                        self.deliver();
+                    }
+                },
+                Message::Reload => {
+                    if self.disabled {
+                        eprintln!("Sink {} is enabled again after the reload.", self.config.name());
+                        self.disabled = false;
+                        self.failing = false;
+                        self.pending = Batch::default();
+                        self.cycles = 0;
+                    }
+                },
+                #[cfg(test)]
+                Message::Sync(sender) => {
+                    let _ = sender.send(());
+                },
+            }
+        }
+        if !self.pending.is_empty() {
+            self.deliver();
+        }
+    }
+
+    /// Writes the pending data, opening the sink first if needed, and applies the error policy on failure.
+    /// A failed sink is closed and reopened for the next attempt.
+    fn deliver(&mut self) {
+        self.cycles = 0;
+        if self.disabled {
+            self.pending = Batch::default();
+            return;
+        }
+        match self.try_deliver() {
+            Ok(()) => {
+                if self.failing {
+                    eprintln!("Sink {} is writing again.", self.config.name());
+                    self.failing = false;
+                }
+                self.pending = Batch::default();
+            },
+            Err(error) => {
+                self.sink = None;
+                self.failing = true;
+                let name = self.config.name();
+                match self.config.on_error {
+                    ErrorPolicy::Retry => {
+                        let dropped = self.pending.keep_newest(self.config.buffer);
+                        eprintln!("Problem writing to sink {name}, keeping {} rows for the next attempt. Error: {error}", self.pending.rows.len());
+                        if dropped > 0 {
+                            eprintln!("Sink {name} buffer is full, dropped the {dropped} oldest records.");
+                        }
+                    },
+                    ErrorPolicy::Drop => {
+                        eprintln!("Problem writing to sink {name}, discarding {} rows. Error: {error}", self.pending.rows.len());
+                        self.pending = Batch::default();
+                    },
+                    ErrorPolicy::Disable => {
+                        eprintln!("Problem writing to sink {name}, disabling it until the next reload. Error: {error}");
+                        self.pending = Batch::default();
+                        self.disabled = true;
+                    },
+                }
+            },
+        }
+    }
+
+    fn try_deliver(&mut self) -> Result<(), Error> {
+        let sink = match &mut self.sink {
+            Some(sink) => sink,
+            None => {
+                self.stations_sent = false;
+                self.sink.insert(self.config.kind.open()?)
+            },
+        };
+        if !self.stations_sent && !self.stations.is_empty() {
+            sink.stations(&self.stations)?;
+            self.stations_sent = true;
+        }
+        if !self.pending.is_empty() {
+            sink.write(&self.pending)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// An empty directory of its own for each test.
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("xbfisher-sink-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    /// A csv sink writing to `directory`, which can be blocked by a file of the same name.
+    fn csv(name: &str, directory: &Path, on_error: ErrorPolicy) -> SinkConfig {
+        let kind = SinkKind::Csv(CsvConfig { directory: directory.to_path_buf(), ..CsvConfig::default() });
+        SinkConfig { name: Some(name.into()), on_error, ..SinkConfig::new(kind) }
+    }
+
+    fn batch(minute: u32) -> Batch {
+        let time = DateTime::parse_from_rfc3339(&format!("2024-10-27T12:{minute:02}:00+00:00")).unwrap();
+        Batch::new(vec![DataRow::sample(1, time, 12.5)], vec![])
+    }
+
+    /// Data rows written to the file of the day, without the header.
+    fn rows(directory: &Path) -> usize {
+        fs::read_to_string(directory.join("station_list_2024-10-27.csv")).map(|text| text.lines().count() - 1).unwrap_or(0)
+    }
+
+    #[test]
+    fn retry_writes_the_failed_data_with_the_next_batch() {
+        let base = directory("retry");
+        let output = base.join("output");
+        fs::write(&output, "").unwrap();
+        let sinks = FanOut::spawn(&[csv("retry", &output, ErrorPolicy::Retry)]);
+        sinks.write(&batch(0));
+        sinks.write(&batch(1));
+        sinks.sync();
+        fs::remove_file(&output).unwrap();
+        sinks.write(&batch(2));
+        sinks.shutdown();
+        assert_eq!(rows(&output), 3);
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn retry_keeps_at_most_the_buffer() {
+        let base = directory("buffer");
+        let output = base.join("output");
+        fs::write(&output, "").unwrap();
+        let sinks = FanOut::spawn(&[SinkConfig { buffer: 2, ..csv("buffer", &output, ErrorPolicy::Retry) }]);
+        for minute in 0..5 {
+            sinks.write(&batch(minute));
+        }
+        sinks.sync();
+        fs::remove_file(&output).unwrap();
+        sinks.write(&batch(5));
+        sinks.shutdown();
+        // The two newest failed rows and the new one.
+        assert_eq!(rows(&output), 3);
+        let text = fs::read_to_string(output.join("station_list_2024-10-27.csv")).unwrap();
+        assert!(text.contains("12:03:00") && !text.contains("12:02:00"), "{text}");
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn drop_discards_the_failed_data() {
+        let base = directory("drop");
+        let output = base.join("output");
+        fs::write(&output, "").unwrap();
+        let sinks = FanOut::spawn(&[csv("drop", &output, ErrorPolicy::Drop)]);
+        sinks.write(&batch(0));
+        sinks.sync();
+        fs::remove_file(&output).unwrap();
+        sinks.write(&batch(1));
+        sinks.shutdown();
+        assert_eq!(rows(&output), 1);
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn disable_stops_the_sink_until_the_next_reload() {
+        let base = directory("disable");
+        let output = base.join("output");
+        fs::write(&output, "").unwrap();
+        let configs = [csv("disable", &output, ErrorPolicy::Disable)];
+        let mut sinks = FanOut::spawn(&configs);
+        sinks.write(&batch(0));
+        sinks.sync();
+        fs::remove_file(&output).unwrap();
+        sinks.write(&batch(1));
+        sinks.sync();
+        assert_eq!(rows(&output), 0);
+        sinks.reconfigure(&configs);
+        sinks.write(&batch(2));
+        sinks.shutdown();
+        assert_eq!(rows(&output), 1);
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn a_failing_sink_does_not_hold_up_the_others() {
+        let base = directory("isolation");
+        let failing = base.join("failing");
+        let working = base.join("working");
+        fs::write(&failing, "").unwrap();
+        let sinks = FanOut::spawn(&[csv("failing", &failing, ErrorPolicy::Retry), csv("working", &working, ErrorPolicy::Retry)]);
+        for minute in 0..3 {
+            sinks.write(&batch(minute));
+        }
+        sinks.sync();
+        assert_eq!(rows(&working), 3);
+        sinks.shutdown();
+        assert!(failing.is_file());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn reconfigure_replaces_only_changed_sinks() {
+        let base = directory("reconfigure");
+        let first = base.join("first");
+        let second = base.join("second");
+        let mut sinks = FanOut::spawn(&[csv("first", &first, ErrorPolicy::Retry)]);
+        sinks.write(&batch(0));
+        sinks.reconfigure(&[csv("first", &first, ErrorPolicy::Retry), csv("second", &second, ErrorPolicy::Retry)]);
+        sinks.write(&batch(1));
+        sinks.reconfigure(&[csv("second", &second, ErrorPolicy::Retry)]);
+        sinks.write(&batch(2));
+        sinks.shutdown();
+        assert_eq!(rows(&first), 2);
+        assert_eq!(rows(&second), 2);
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Durable queue of request bodies in a directory, one file per body, delivered oldest first.
+/// Bodies are written to a temporary file and renamed, so a crash leaves either the whole body or nothing.
+pub struct Spool {
+    directory: PathBuf,
+    /// Bytes above which the oldest bodies are deleted.
+    max_size: u64,
+    sequence: u32,
+}
+
+impl Spool {
+    /// Opens or creates the spool directory, removing bodies whose write was interrupted.
+    pub fn open(directory: &Path, max_size: u64) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "tmp") {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(Self { directory: directory.to_path_buf(), max_size, sequence: 0 })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Stores a body and syncs it to disk.
+    pub fn push(&mut self, body: &str) -> io::Result<()> {
+        // Milliseconds and a sequence number keep the names unique and sorted by age.
+        let name = format!("{:013}-{:06}", chrono::Utc::now().timestamp_millis(), self.sequence);
+        self.sequence = (self.sequence + 1) % 1_000_000;
+        let temporary = self.directory.join(format!("{name}.tmp"));
+        let mut file = File::create(&temporary)?;
+        file.write_all(body.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, self.directory.join(format!("{name}.spool")))?;
+        self.trim()
+    }
+
+    /// Spooled bodies, oldest first.
+    pub fn files(&self) -> io::Result<Vec<PathBuf>> {
+        self.with_extensions(&["spool"])
+    }
+
+    /// Files of the spool with one of the extensions, oldest first.
+    fn with_extensions(&self, extensions: &[&str]) -> io::Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()).is_some_and(|extension| extensions.contains(&extension)) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Deletes the oldest bodies, waiting or rejected, while the spool is larger than `max_size`, always keeping the newest one.
+    fn trim(&self) -> io::Result<()> {
+        let mut files = vec![];
+        let mut total = 0;
+        for path in self.with_extensions(&["spool", "rejected"])? {
+            let size = fs::metadata(&path)?.len();
+            total += size;
+            files.push((path, size));
+        }
+        for (path, size) in files.iter().take(files.len().saturating_sub(1)) {
+            if total <= self.max_size {
+                break;
+            }
+            eprintln!("Spool {} is full, deleting {} ({size} bytes).", self.directory.display(), path.display());
+            fs::remove_file(path)?;
+            total -= size;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,361 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::stations::health::StateEvent;
+use crate::stations::hosts::HostEntry;
+use crate::stations::station::DataRow;
+use crate::storage::sink::{Batch, Sink};
+use crate::tools::errors::Error;
+
+/// Options of the SQLite database, set in the [sqlite] section of the config file. The database is only written if the section exists.
+/// Example:
+/// ```toml
+/// [sqlite]
+/// path = "./data/xbfisher.sqlite"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SqliteConfig {
+    pub path: PathBuf,
+    /// Write-ahead logging lets other processes query the database while the logger writes.
+    #[serde(default = "default_wal")]
+    pub wal: bool,
+    /// Milliseconds to wait for a lock held by a reader before giving up.
+    #[serde(default = "default_busy_timeout")]
+    pub busy_timeout: u64,
+}
+
+fn default_wal() -> bool {
+    true
+}
+
+fn default_busy_timeout() -> u64 {
+    5000
+}
+
+/// Schema migrations, applied in order. The number of applied migrations is kept in `PRAGMA user_version`,
+/// so new migrations are only ever appended to this list.
+#[cfg(feature = "sqlite")]
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE stations (
+        station_no INTEGER PRIMARY KEY,
+        usr_name TEXT NOT NULL,
+        ip_address TEXT NOT NULL,
+        updated INTEGER NOT NULL
+    );
+    CREATE TABLE samples (
+        id INTEGER PRIMARY KEY,
+        station_no INTEGER NOT NULL,
+        time TEXT NOT NULL,
+        epoch INTEGER NOT NULL,
+        latency_ms REAL,
+        latency_status TEXT NOT NULL,
+        packet_loss_pct REAL,
+        cpu_temperature_c REAL,
+        temperature_status TEXT NOT NULL,
+        error TEXT
+    );
+    CREATE INDEX samples_station_time ON samples (station_no, epoch);
+    CREATE TABLE state_events (
+        id INTEGER PRIMARY KEY,
+        station_no INTEGER NOT NULL,
+        time TEXT NOT NULL,
+        epoch INTEGER NOT NULL,
+        from_state TEXT NOT NULL,
+        to_state TEXT NOT NULL,
+        duration_s INTEGER NOT NULL,
+        reason TEXT NOT NULL
+    );
+    CREATE INDEX state_events_station_time ON state_events (station_no, epoch);",
+    "ALTER TABLE samples ADD COLUMN jitter_ms REAL;
+    ALTER TABLE samples ADD COLUMN ipdv_mean_abs_ms REAL;
+    ALTER TABLE samples ADD COLUMN ipdv_max_ms REAL;
+    ALTER TABLE samples ADD COLUMN reordered INTEGER;
+    ALTER TABLE samples ADD COLUMN loss_bursts INTEGER;
+    ALTER TABLE samples ADD COLUMN max_loss_burst INTEGER;",
+];
+
+/// Writes stations, samples and state events to a SQLite database.
+pub struct SqliteStore {
+    path: PathBuf,
+    #[cfg(feature = "sqlite")]
+    conn: rusqlite::Connection,
+}
+
+impl SqliteStore {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Sink for SqliteStore {
+    fn stations(&mut self, entries: &[HostEntry]) -> Result<(), Error> {
+        self.upsert_stations(entries)
+    }
+
+    fn write(&mut self, batch: &Batch) -> Result<(), Error> {
+        self.write_batch(&batch.rows, &batch.events)
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl SqliteStore {
+    pub fn open(config: &SqliteConfig) -> Result<Self, Error> {
+        Err(Error::StorageError { path: config.path.display().to_string(), message: "xbfisher was built without the sqlite feature".into() })
+    }
+
+    pub fn upsert_stations(&mut self, _entries: &[HostEntry]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    pub fn write_samples(&mut self, _datavec: &[DataRow]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    pub fn write_events(&mut self, _events: &[StateEvent]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    pub fn write_batch(&mut self, _datavec: &[DataRow], _events: &[StateEvent]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    /// Opens or creates the database and brings its schema up to date.
+    pub fn open(config: &SqliteConfig) -> Result<Self, Error> {
+        if let Some(parent) = config.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = rusqlite::Connection::open(&config.path).map_err(|error| storage_error(&config.path, error))?;
+        let mut store = Self { path: config.path.clone(), conn };
+        store.conn.busy_timeout(std::time::Duration::from_millis(config.busy_timeout)).map_err(|error| store.error(error))?;
+        if config.wal {
+            store.conn.pragma_update(None, "journal_mode", "WAL").map_err(|error| store.error(error))?;
+            store.conn.pragma_update(None, "synchronous", "NORMAL").map_err(|error| store.error(error))?;
+        }
+        store.migrate()?;
+        Ok(store)
+    }
+
+    fn error(&self, error: rusqlite::Error) -> Error {
+        storage_error(&self.path, error)
+    }
+
+    fn migrate(&mut self) -> Result<(), Error> {
+        let version: i64 = self.conn.pragma_query_value(None, "user_version", |row| row.get(0)).map_err(|error| self.error(error))?;
+        let version = version.max(0) as usize;
+        if version > MIGRATIONS.len() {
+            return Err(Error::StorageError { path: self.path.display().to_string(), message: format!("database schema version {version} is newer than this xbfisher ({})", MIGRATIONS.len()) });
+        }
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = self.conn.transaction().map_err(|error| storage_error(&self.path, error))?;
+            tx.execute_batch(migration).map_err(|error| storage_error(&self.path, error))?;
+            tx.pragma_update(None, "user_version", (i + 1) as i64).map_err(|error| storage_error(&self.path, error))?;
+            tx.commit().map_err(|error| storage_error(&self.path, error))?;
+        }
+        Ok(())
+    }
+
+    /// Records the user name and address of the stations being logged.
+    pub fn upsert_stations(&mut self, entries: &[HostEntry]) -> Result<(), Error> {
+        let tx = self.conn.transaction().map_err(|error| storage_error(&self.path, error))?;
+        {
+            let mut statement = tx.prepare_cached(
+                "INSERT INTO stations (station_no, usr_name, ip_address, updated) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (station_no) DO UPDATE SET usr_name = excluded.usr_name, ip_address = excluded.ip_address, updated = excluded.updated",
+            ).map_err(|error| storage_error(&self.path, error))?;
+            let now = chrono::Utc::now().timestamp();
+            for entry in entries {
+                statement.execute(rusqlite::params![entry.station_no, entry.usr_name, entry.ip_address, now]).map_err(|error| storage_error(&self.path, error))?;
+            }
+        }
+        tx.commit().map_err(|error| storage_error(&self.path, error))
+    }
+
+    pub fn write_samples(&mut self, datavec: &[DataRow]) -> Result<(), Error> {
+        self.write_batch(datavec, &[])
+    }
+
+    pub fn write_events(&mut self, events: &[StateEvent]) -> Result<(), Error> {
+        self.write_batch(&[], events)
+    }
+
+    /// Writes the samples and state events in one transaction, so a failed write leaves neither behind and can be retried as a whole.
+    pub fn write_batch(&mut self, datavec: &[DataRow], events: &[StateEvent]) -> Result<(), Error> {
+        if datavec.is_empty() && events.is_empty() {
+            return Ok(());
+        }
+        let tx = self.conn.transaction().map_err(|error| storage_error(&self.path, error))?;
+        insert_samples(&tx, datavec).map_err(|error| storage_error(&self.path, error))?;
+        insert_events(&tx, events).map_err(|error| storage_error(&self.path, error))?;
+        tx.commit().map_err(|error| storage_error(&self.path, error))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn insert_samples(tx: &rusqlite::Transaction, datavec: &[DataRow]) -> rusqlite::Result<()> {
+    let mut statement = tx.prepare_cached(
+        "INSERT INTO samples (station_no, time, epoch, latency_ms, latency_status, packet_loss_pct, cpu_temperature_c, temperature_status, error,
+         jitter_ms, ipdv_mean_abs_ms, ipdv_max_ms, reordered, loss_bursts, max_loss_burst)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+    )?;
+    for row in datavec {
+        statement.execute(rusqlite::params![
+            row.station_no(),
+            crate::tools::time::rfc3339(&row.time()),
+            row.time().timestamp(),
+            row.latency(),
+            row.latency_status().to_string(),
+            row.packet_loss(),
+            row.cpu_temperature(),
+            row.temperature_status().to_string(),
+            row.error(),
+            row.jitter(),
+            row.ipdv_mean_abs(),
+            row.ipdv_max(),
+            row.reordered(),
+            row.loss_bursts(),
+            row.max_loss_burst(),
+        ])?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn insert_events(tx: &rusqlite::Transaction, events: &[StateEvent]) -> rusqlite::Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+    let mut statement = tx.prepare_cached(
+        "INSERT INTO state_events (station_no, time, epoch, from_state, to_state, duration_s, reason) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    for event in events {
+        statement.execute(rusqlite::params![
+            event.station_no,
+            crate::tools::time::rfc3339(&event.time),
+            event.time.timestamp(),
+            event.from.to_string(),
+            event.to.to_string(),
+            event.duration.as_secs() as i64,
+            event.reason,
+        ])?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn storage_error(path: &Path, error: rusqlite::Error) -> Error {
+    Error::StorageError { path: path.display().to_string(), message: error.to_string() }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use crate::stations::health::HealthState;
+
+    /// A database path in a directory of its own for each test.
+    fn database(name: &str) -> SqliteConfig {
+        let directory = std::env::temp_dir().join(format!("xbfisher-sqlite-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        SqliteConfig { path: directory.join("xbfisher.sqlite"), wal: default_wal(), busy_timeout: default_busy_timeout() }
+    }
+
+    fn cleanup(config: &SqliteConfig) {
+        std::fs::remove_dir_all(config.path.parent().unwrap()).unwrap();
+    }
+
+    fn row(no: u8) -> DataRow {
+        DataRow::sample(no, DateTime::parse_from_rfc3339("2024-10-27T12:00:00+00:00").unwrap(), 12.5)
+    }
+
+    fn event() -> StateEvent {
+        StateEvent {
+            time: DateTime::parse_from_rfc3339("2024-10-27T12:00:00+00:00").unwrap(),
+            station_no: 1,
+            from: HealthState::Unknown,
+            to: HealthState::Up,
+            duration: std::time::Duration::ZERO,
+            reason: "probe answered within thresholds".into(),
+        }
+    }
+
+    fn count(conn: &rusqlite::Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| row.get(0)).unwrap()
+    }
+
+    fn user_version(conn: &rusqlite::Connection) -> i64 {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn creates_the_schema_of_a_new_database_in_wal_mode() {
+        let config = database("new");
+        let store = SqliteStore::open(&config).unwrap();
+        assert_eq!(user_version(&store.conn), MIGRATIONS.len() as i64);
+        let journal_mode: String = store.conn.pragma_query_value(None, "journal_mode", |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode, "wal");
+        drop(store);
+        let without_wal = SqliteConfig { wal: false, ..database("rollback-journal") };
+        let store = SqliteStore::open(&without_wal).unwrap();
+        let journal_mode: String = store.conn.pragma_query_value(None, "journal_mode", |row| row.get(0)).unwrap();
+        assert_eq!(journal_mode, "delete");
+        drop(store);
+        cleanup(&config);
+        cleanup(&without_wal);
+    }
+
+    #[test]
+    fn migrates_a_database_of_the_first_schema_keeping_its_rows() {
+        let config = database("migrate");
+        std::fs::create_dir_all(config.path.parent().unwrap()).unwrap();
+        let conn = rusqlite::Connection::open(&config.path).unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+        conn.execute("INSERT INTO samples (station_no, time, epoch, latency_ms, latency_status, temperature_status) VALUES (1, 't', 0, 1.5, 'ok', 'ok')", []).unwrap();
+        drop(conn);
+
+        let mut store = SqliteStore::open(&config).unwrap();
+        assert_eq!(user_version(&store.conn), 2);
+        store.write_samples(&[row(2)]).unwrap();
+        let jitter: Vec<Option<f64>> = store.conn.prepare("SELECT jitter_ms FROM samples ORDER BY id").unwrap()
+            .query_map([], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(jitter, [None, None]);
+        drop(store);
+        cleanup(&config);
+    }
+
+    #[test]
+    fn rejects_a_database_of_a_newer_schema() {
+        let config = database("newer");
+        std::fs::create_dir_all(config.path.parent().unwrap()).unwrap();
+        let conn = rusqlite::Connection::open(&config.path).unwrap();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() as i64 + 1).unwrap();
+        drop(conn);
+        match SqliteStore::open(&config) {
+            Err(Error::StorageError { message, .. }) => assert!(message.contains("newer"), "{message}"),
+            Err(error) => panic!("unexpected error {error}"),
+            Ok(_) => panic!("a newer schema was accepted"),
+        }
+        cleanup(&config);
+    }
+
+    #[test]
+    fn a_failed_write_leaves_no_samples_behind() {
+        let config = database("atomic");
+        let mut store = SqliteStore::open(&config).unwrap();
+        store.conn.execute_batch("ALTER TABLE state_events RENAME TO moved").unwrap();
+        let batch = Batch::new(vec![row(1), row(2)], vec![event()]);
+        assert!(Sink::write(&mut store, &batch).is_err());
+        assert_eq!(count(&store.conn, "samples"), 0);
+        store.conn.execute_batch("ALTER TABLE moved RENAME TO state_events").unwrap();
+        Sink::write(&mut store, &batch).unwrap();
+        assert_eq!(count(&store.conn, "samples"), 2);
+        assert_eq!(count(&store.conn, "state_events"), 1);
+        drop(store);
+        cleanup(&config);
+    }
+}
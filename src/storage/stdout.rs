@@ -0,0 +1,42 @@
+use serde::Deserialize;
+
+use crate::storage::sink::{Batch, Sink};
+use crate::tools::errors::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StdoutFormat {
+    /// One line per sample as printed by `xbfisher get`. State events are already printed by the logger.
+    #[default]
+    Text,
+    /// JSON Lines with samples and state events.
+    Json,
+}
+
+/// Options of a "stdout" sink.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StdoutConfig {
+    pub format: StdoutFormat,
+}
+
+/// Prints the gathered data, e.g. to the journal or into a pipe.
+pub struct StdoutSink {
+    options: StdoutConfig,
+}
+
+impl StdoutSink {
+    pub fn new(options: StdoutConfig) -> Self {
+        Self { options }
+    }
+}
+
+impl Sink for StdoutSink {
+    fn write(&mut self, batch: &Batch) -> Result<(), Error> {
+        match self.options.format {
+            StdoutFormat::Text => batch.rows.iter().for_each(|row| println!("{row}")),
+            StdoutFormat::Json => print!("{}", batch.to_json_lines().map_err(|error| Error::SinkError { sink: "stdout".into(), message: error.to_string() })?),
+        }
+        Ok(())
+    }
+}
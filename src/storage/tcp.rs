@@ -0,0 +1,72 @@
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::storage::sink::{Batch, Sink};
+use crate::tools::errors::Error;
+
+/// Options of a "tcp" sink, which streams JSON Lines to a collector (e.g. Vector, Fluent Bit or `nc -lk`).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TcpConfig {
+    /// "host:port" of the collector.
+    pub address: String,
+    /// Seconds to wait for the connection and for each write.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+/// Sends samples and state events as JSON Lines over a TCP connection, which is opened on the first write.
+pub struct TcpSink {
+    options: TcpConfig,
+    stream: Option<TcpStream>,
+}
+
+impl TcpSink {
+    pub fn new(options: TcpConfig) -> Self {
+        Self { options, stream: None }
+    }
+
+    fn error(&self, message: String) -> Error {
+        Error::SinkError { sink: self.options.address.clone(), message }
+    }
+
+    fn connect(&self) -> Result<TcpStream, Error> {
+        let timeout = Duration::from_secs(self.options.timeout);
+        let mut last_error = format!("could not resolve {}", self.options.address);
+        for address in self.options.address.to_socket_addrs().map_err(|error| self.error(error.to_string()))? {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(timeout)).map_err(|error| self.error(error.to_string()))?;
+                    return Ok(stream);
+                },
+                Err(error) => last_error = error.to_string(),
+            }
+        }
+        Err(self.error(last_error))
+    }
+}
+
+impl Sink for TcpSink {
+    fn write(&mut self, batch: &Batch) -> Result<(), Error> {
+        let text = batch.to_json_lines().map_err(|error| self.error(error.to_string()))?;
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => {
+                let stream = self.connect()?;
+                self.stream.insert(stream)
+            },
+        };
+        if let Err(error) = stream.write_all(text.as_bytes()).and_then(|_| stream.flush()) {
+            self.stream = None;
+            return Err(self.error(error.to_string()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::alerting::notifier::{NotifierConfig, RetryPolicy};
+use crate::alerting::rules::AlertRule;
+use crate::analysis::anomaly::AnomalyConfig;
+use crate::stations::groups::{GroupConfig, Selector};
+use crate::stations::health::HealthThresholds;
+use crate::stations::hosts::HostEntry;
+use crate::stations::station::CollectTimeouts;
+use crate::tools::errors::Error;
+use crate::storage::csv::CsvConfig;
+use crate::storage::sink::{SinkConfig, SinkKind};
+use crate::storage::sqlite::SqliteConfig;
+use crate::tools::time::TimeZoneSetting;
+
+pub const DEFAULT_CONFIG_PATH: &str = "./xbfisher.toml";
+
+/// Settings read from the optional "./xbfisher.toml" file. Every key has a default so the file may be empty or missing.
+/// Example:
+/// ```toml
+/// hosts = "./hosts"
+/// interval = 60
+///
+/// [timeouts]
+/// # the latency probe: five echo requests one second apart, not waiting for the replies
+/// probe = 20
+/// ssh = 10
+///
+/// [daemon]
+/// pidfile = "/run/xbfisher.pid"
+///
+/// [time]
+/// zone = "utc"
+///
+/// [csv]
+/// directory = "./data"
+/// rotation = "daily"
+/// compression = "gzip"
+/// retention_days = 90
+///
+/// [sqlite]
+/// path = "./data/xbfisher.sqlite"
+///
+/// [[sink]]
+/// kind = "jsonl"
+/// directory = "./data"
+///
+/// [[group]]
+/// name = "field"
+/// select = "role=field"
+/// interval = 30
+///
+/// [health]
+/// degraded_latency = 200.0
+/// degraded_loss = 20.0
+/// down_after = 2
+///
+/// [anomaly]
+/// enabled = true
+///
+/// [[alert]]
+/// name = "pi-hot"
+/// metric = "cpu_temperature"
+/// condition = ">"
+/// threshold = 75.0
+/// for_samples = 3
+/// notifiers = ["ops-chat"]
+///
+/// [[notifier]]
+/// kind = "webhook"
+/// name = "ops-chat"
+/// url = "https://chat.example.org/hooks/xyz"
+///
+/// [notify]
+/// retries = 3
+/// backoff = 2
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Path of the station list.
+    pub hosts: PathBuf,
+    /// Seconds between the starts of two gather cycles.
+    pub interval: u64,
+    pub timeouts: TimeoutConfig,
+    pub daemon: DaemonConfig,
+    pub time: TimeConfig,
+    pub csv: CsvConfig,
+    /// Also write to a SQLite database if set.
+    pub sqlite: Option<SqliteConfig>,
+    /// Output destinations, see `SinkConfig`. If empty, the [csv] and [sqlite] sections are used.
+    #[serde(rename = "sink")]
+    pub sinks: Vec<SinkConfig>,
+    /// Named sets of stations, see `GroupConfig`.
+    #[serde(rename = "group")]
+    pub groups: Vec<GroupConfig>,
+    pub health: HealthThresholds,
+    /// Alert rules, see `AlertRule`.
+    #[serde(rename = "alert")]
+    pub alerts: Vec<AlertRule>,
+    pub anomaly: AnomalyConfig,
+    /// Alert delivery backends, see `NotifierConfig`.
+    #[serde(rename = "notifier")]
+    pub notifiers: Vec<NotifierConfig>,
+    pub notify: RetryPolicy,
+}
+
+/// Deadlines of the remote collection steps in seconds.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    /// Deadline of the probe train measuring the latency, five echo requests one second apart that take up to 6 seconds.
+    pub probe: u64,
+    /// Deadline of the ssh session reading the temperature, including connection setup.
+    pub ssh: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Written on start and removed on exit if set.
+    pub pidfile: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeConfig {
+    /// "local" (default) or "utc".
+    pub zone: TimeZoneSetting,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            hosts: PathBuf::from("./hosts"),
+            interval: 60,
+            timeouts: TimeoutConfig::default(),
+            daemon: DaemonConfig::default(),
+            time: TimeConfig::default(),
+            csv: CsvConfig::default(),
+            sqlite: None,
+            sinks: vec![],
+            groups: vec![],
+            health: HealthThresholds::default(),
+            alerts: vec![],
+            anomaly: AnomalyConfig::default(),
+            notifiers: vec![],
+            notify: RetryPolicy::default(),
+        }
+    }
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        let timeouts = CollectTimeouts::default();
+        Self { probe: timeouts.probe.as_secs(), ssh: timeouts.ssh.as_secs() }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn collect_timeouts(&self) -> CollectTimeouts {
+        CollectTimeouts { probe: Duration::from_secs(self.probe), ssh: Duration::from_secs(self.ssh) }
+    }
+}
+
+impl Config {
+    /// Reads the config file at `path`, falling back to the defaults if it does not exist.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let config_error = |message: String| Error::ConfigError { path: path.display().to_string(), message };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => return Err(config_error(error.to_string())),
+        };
+        let config: Self = toml::from_str(&text).map_err(|error| config_error(error.to_string()))?;
+        for (i, rule) in config.alerts.iter().enumerate() {
+            if config.alerts[..i].iter().any(|other| other.name == rule.name) {
+                return Err(config_error(format!("duplicate alert rule name \"{}\"", rule.name)));
+            }
+        }
+        for (i, notifier) in config.notifiers.iter().enumerate() {
+            if notifier.name() == "log" || config.notifiers[..i].iter().any(|other| other.name() == notifier.name()) {
+                return Err(config_error(format!("duplicate notifier name \"{}\"", notifier.name())));
+            }
+        }
+        for rule in &config.alerts {
+            if let Some(name) = rule.notifiers.iter().find(|name| *name != "log" && !config.notifiers.iter().any(|notifier| notifier.name() == *name)) {
+                return Err(config_error(format!("alert rule \"{}\" uses unknown notifier \"{name}\"", rule.name)));
+            }
+        }
+        if let Some(name) = config.anomaly.notifiers.iter().find(|name| *name != "log" && !config.notifiers.iter().any(|notifier| notifier.name() == *name)) {
+            return Err(config_error(format!("[anomaly] uses unknown notifier \"{name}\"")));
+        }
+        if !(config.anomaly.alpha > 0.0 && config.anomaly.alpha <= 1.0) || config.anomaly.z_score <= 0.0 || config.anomaly.trend_days == 0 {
+            return Err(config_error("[anomaly] needs an alpha from 0 to 1, a positive z_score and trend_days of at least 1".into()));
+        }
+        for (i, sink) in config.sinks.iter().enumerate() {
+            if config.sinks[..i].iter().any(|other| other.name() == sink.name()) {
+                return Err(config_error(format!("duplicate sink name \"{}\", set a distinct name", sink.name())));
+            }
+            if let SinkKind::Http(http) = &sink.kind {
+                if config.sinks[..i].iter().any(|other| matches!(&other.kind, SinkKind::Http(other) if other.spool == http.spool)) {
+                    return Err(config_error(format!("sink \"{}\" shares its spool directory with another http sink", sink.name())));
+                }
+            }
+            if sink.batch == 0 || sink.queue == 0 {
+                return Err(config_error(format!("sink \"{}\" needs a batch and queue of at least 1", sink.name())));
+            }
+        }
+        for (i, group) in config.groups.iter().enumerate() {
+            if config.groups[..i].iter().any(|other| other.name == group.name) {
+                return Err(config_error(format!("duplicate group name \"{}\"", group.name)));
+            }
+            if group.interval == Some(0) {
+                return Err(config_error(format!("interval of group \"{}\" must be at least 1 second", group.name)));
+            }
+        }
+        if config.timeouts.probe == 0 || config.timeouts.ssh == 0 {
+            return Err(config_error("[timeouts] probe and ssh must be at least 1 second".into()));
+        }
+        if config.interval == 0 {
+            return Err(config_error("interval must be at least 1 second".into()));
+        }
+        for warning in config.warnings() {
+            eprintln!("{}: {warning}", path.display());
+        }
+        Ok(config)
+    }
+
+    /// Settings that work, but likely not as intended: intervals shorter than a slow station can take.
+    pub fn warnings(&self) -> Vec<String> {
+        let slowest = self.timeouts.probe + self.timeouts.ssh;
+        let intervals = std::iter::once(("interval".to_string(), self.interval))
+            .chain(self.groups.iter().filter_map(|group| group.interval.map(|interval| (format!("interval of group \"{}\"", group.name), interval))));
+        intervals.filter(|(_, interval)| *interval < slowest)
+            .map(|(name, interval)| format!("{name} is {interval} s, but a station that doesn't answer takes up to the probe and ssh timeouts of {slowest} s, delaying the gathers due meanwhile"))
+            .collect()
+    }
+
+    /// Names of the groups the station belongs to.
+    pub fn groups_of(&self, entry: &HostEntry) -> Vec<&str> {
+        self.groups.iter().filter(|group| group.select.matches(entry, &[])).map(|group| group.name.as_str()).collect()
+    }
+
+    /// Whether the selector picks the station, its "group" terms referring to the [[group]] tables.
+    pub fn selects(&self, selector: &Selector, entry: &HostEntry) -> bool {
+        selector.matches(entry, &self.groups_of(entry))
+    }
+
+    /// Seconds between two gathers of the station: the shortest interval of its groups, or `interval` if none sets one.
+    pub fn interval_of(&self, entry: &HostEntry) -> u64 {
+        self.groups.iter().filter(|group| group.select.matches(entry, &[])).filter_map(|group| group.interval).min().unwrap_or(self.interval)
+    }
+
+    /// Options of the .csv files the logged data is read back from: the first csv sink, or the [csv] section.
+    pub fn csv_output(&self) -> CsvConfig {
+        self.sinks().into_iter().find_map(|sink| match sink.kind {
+            SinkKind::Csv(csv) => Some(csv),
+            _ => None,
+        }).unwrap_or_else(|| self.csv.clone())
+    }
+
+    /// The sinks gathered data is written to: the [[sink]] tables, or the [csv] files and [sqlite] database if there are none.
+    pub fn sinks(&self) -> Vec<SinkConfig> {
+        if !self.sinks.is_empty() {
+            return self.sinks.clone();
+        }
+        let mut sinks = vec![SinkConfig::new(SinkKind::Csv(self.csv.clone()))];
+        sinks.extend(self.sqlite.clone().map(|sqlite| SinkConfig::new(SinkKind::Sqlite(sqlite))));
+        sinks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, text: &str) -> Result<Config, Error> {
+        let path = std::env::temp_dir().join(format!("xbfisher-config-{name}-{}.toml", std::process::id()));
+        fs::write(&path, text).unwrap();
+        let config = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn rejects_zero_timeouts() {
+        for text in ["[timeouts]\nprobe = 0\n", "[timeouts]\nssh = 0\n"] {
+            let Err(Error::ConfigError { message, .. }) = load("timeouts", text) else {
+                panic!("{text} was accepted");
+            };
+            assert!(message.contains("[timeouts]"), "{message}");
+        }
+        assert_eq!(load("timeouts", "[timeouts]\nprobe = 5\nssh = 3\n").unwrap().timeouts.collect_timeouts().probe, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn warns_of_intervals_shorter_than_the_timeouts() {
+        let text = "interval = 60\n[[group]]\nname = \"field\"\nselect = \"role=field\"\ninterval = 5\n[[group]]\nname = \"relays\"\nselect = \"role=relay\"\ninterval = 30\n";
+        let warnings = load("warnings", text).unwrap().warnings();
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+        assert!(warnings[0].starts_with("interval of group \"field\" is 5 s") && warnings[0].contains("30 s"), "{}", warnings[0]);
+        assert!(load("warnings", &format!("{text}[timeouts]\nprobe = 3\nssh = 2\n")).unwrap().warnings().is_empty());
+        assert_eq!(load("warnings", "interval = 20\n").unwrap().warnings().len(), 1);
+    }
+}
@@ -0,0 +1,179 @@
+use std::io::Read;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::tools::errors::Error;
+
+/// How often a waiting child process or sleep checks its deadline and the cancel flag.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Shared flag that aborts a running gather cycle.
+/// All clones observe the same flag, and the flag itself can be handed to a signal handler.
+#[derive(Clone, Default, Debug)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+
+    /// Returns the underlying flag, e.g. for `signal_hook::flag::register`.
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.flag)
+    }
+
+    /// Returns an error if the token has been cancelled.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Sleeps for the given duration, waking up early if the token gets cancelled.
+    pub fn sleep(&self, duration: Duration) -> Result<(), Error> {
+        let end = Instant::now() + duration;
+        loop {
+            self.check()?;
+            let now = Instant::now();
+            if now >= end {
+                return Ok(());
+            }
+            thread::sleep(POLL_INTERVAL.min(end - now));
+        }
+    }
+}
+
+/// Runs the command like `Command::output` but kills the child if it runs longer than `timeout` or the token gets cancelled.
+/// operation: &str: names the step in the returned timeout error.
+pub fn output_with_deadline(cmd: &mut Command, operation: &str, timeout: Duration, cancel: &CancelToken) -> Result<Output, Error> {
+    let time_start = Instant::now();
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Drain the pipes on separate threads so a chatty child can't block on a full pipe.
+    let stdout = child.stdout.take().map(drain);
+    let stderr = child.stderr.take().map(drain);
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if cancel.is_cancelled() {
+            kill(&mut child);
+            return Err(Error::Cancelled);
+        }
+        let time_elapsed = time_start.elapsed();
+        if time_elapsed >= timeout {
+            kill(&mut child);
+            return Err(Error::Timeout { operation: operation.into(), duration: time_elapsed });
+        }
+        thread::sleep(POLL_INTERVAL.min(timeout - time_elapsed));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.map(|h| h.join().unwrap_or_default()).unwrap_or_default(),
+        stderr: stderr.map(|h| h.join().unwrap_or_default()).unwrap_or_default(),
+    })
+}
+
+fn drain<R: Read + Send + 'static>(mut pipe: R) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = pipe.read_to_end(&mut buffer);
+        buffer
+    })
+}
+
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_cancel_flag() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(token.check().is_ok());
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(Error::Cancelled)));
+        assert!(token.flag().load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn sleep_wakes_up_on_cancel() {
+        let token = CancelToken::new();
+        let time_start = Instant::now();
+        token.sleep(Duration::from_millis(60)).unwrap();
+        assert!(time_start.elapsed() >= Duration::from_millis(60));
+
+        let canceller = token.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            canceller.cancel();
+        });
+        let time_start = Instant::now();
+        assert!(matches!(token.sleep(Duration::from_secs(30)), Err(Error::Cancelled)));
+        assert!(time_start.elapsed() < Duration::from_secs(5));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn returns_the_output_of_a_command_within_the_deadline() {
+        let output = output_with_deadline(Command::new("sh").args(["-c", "echo out; echo err >&2; exit 3"]), "echo", Duration::from_secs(10), &CancelToken::new()).unwrap();
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    #[test]
+    fn kills_a_command_past_its_deadline() {
+        let time_start = Instant::now();
+        let result = output_with_deadline(Command::new("sleep").arg("30"), "sleep", Duration::from_millis(200), &CancelToken::new());
+        match result {
+            Err(Error::Timeout { operation, duration }) => {
+                assert_eq!(operation, "sleep");
+                assert!(duration >= Duration::from_millis(200));
+            },
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+        assert!(time_start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn kills_a_command_on_cancel() {
+        let token = CancelToken::new();
+        let canceller = token.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            canceller.cancel();
+        });
+        let time_start = Instant::now();
+        let result = output_with_deadline(Command::new("sleep").arg("30"), "sleep", Duration::from_secs(60), &token);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert!(time_start.elapsed() < Duration::from_secs(5));
+        handle.join().unwrap();
+    }
+}
@@ -1,6 +1,10 @@
+use std::time::Duration;
+
 use thiserror::Error;
 
+/// Errors of the library. New variants may be added in minor releases.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     #[error("invalid procotol")]
     InvalidProtocol,
@@ -10,6 +14,71 @@ pub enum Error {
     DecodeV4Error,
     #[error("Decode echo reply error occurred while processing the ICMP echo reply.")]
     DecodeEchoReplyError,
+    #[error("{operation} timed out after {} ms", duration.as_millis())]
+    Timeout {
+        operation: String,
+        duration: Duration,
+    },
+    #[error("cancelled")]
+    Cancelled,
+    #[error("config error in {path}: {message}")]
+    ConfigError {
+        path: String,
+        message: String,
+    },
+    #[error("station {station_no} is not configured in {path}")]
+    UnknownStation {
+        station_no: u8,
+        path: String,
+    },
+    #[error("no station of {path} matches \"{selector}\"")]
+    NoStationSelected {
+        selector: String,
+        path: String,
+    },
+    #[error("station {station_no} has the invalid address \"{address}\"")]
+    InvalidAddress {
+        station_no: u8,
+        address: String,
+    },
+    #[error("could not parse the {what} \"{input}\": {message}")]
+    ParseError {
+        what: String,
+        input: String,
+        message: String,
+    },
+    #[error("ssh to station {station_no} ({address}) failed: {message}")]
+    SshError {
+        station_no: u8,
+        address: String,
+        message: String,
+    },
+    #[error("probe of station {station_no} ({address}) failed: {message}")]
+    ProbeError {
+        station_no: u8,
+        address: String,
+        message: String,
+    },
+    #[error("storage error in {path}: {message}")]
+    StorageError {
+        path: String,
+        message: String,
+    },
+    #[error("sink {sink} failed: {message}")]
+    SinkError {
+        sink: String,
+        message: String,
+    },
+    #[error("notifier {notifier} failed: {message}")]
+    NotifyError {
+        notifier: String,
+        message: String,
+    },
+    #[error("another instance is already running with pid {pid} (pidfile {path})")]
+    AlreadyRunning {
+        pid: u32,
+        path: String,
+    },
     #[error("io error: {error}")]
     IoError {
         #[from]
@@ -0,0 +1,39 @@
+pub mod streaming;
+
+pub use self::streaming::{Ewma, QuantileSketch, RunningStats};
+
+/// Gets the digits amount of digits of value
+pub fn n_decimals(value: f32, digits: usize) -> f32 {
+    let dec = match value {
+        v if v <= 0_f32 => digits,
+        _ => digits.saturating_sub(value.abs().log10() as usize + 1),
+    };
+    let rounded = round(value as f64, dec as i32) as f32;
+    if rounded.is_finite() { rounded } else { value }
+}
+
+/// Calculates the mean of the input vector, NaN if it is empty. `RunningStats` gives the same without a vector.
+#[deprecated(note = "use RunningStats, which returns None without values")]
+pub fn vec_mean(v: &[f32]) -> f32{
+    v.iter().map(|&value| value as f64).collect::<RunningStats>().mean().unwrap_or(f64::NAN) as f32
+}
+
+/// Calculates the standard deviation of the input vector, NaN if it is empty. `RunningStats` gives the same without a vector.
+#[deprecated(note = "use RunningStats, which returns None without values")]
+pub fn vec_mdev(v: &[f32]) -> f32{
+    v.iter().map(|&value| value as f64).collect::<RunningStats>().stddev().unwrap_or(f64::NAN) as f32
+}
+
+/// Rounds value to the given number of decimal places.
+pub fn round(value: f64, decimals: i32) -> f64 {
+    let factor = 10_f64.powi(decimals);
+    (value * factor).round() / factor
+}
+
+/// The p-th percentile (0 to 100) of sorted values, interpolating linearly between the closest ranks.
+pub fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    let last = sorted.len().checked_sub(1)?;
+    let rank = (p.clamp(0.0, 100.0) / 100.0) * last as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64))
+}
@@ -0,0 +1,470 @@
+//! Accumulators that summarize values one at a time, without keeping them, and can be merged.
+//!
+//! ```
+//! use xbfisher::math::{QuantileSketch, RunningStats};
+//!
+//! let mut stats = RunningStats::new();
+//! let mut sketch = QuantileSketch::new();
+//! for latency in [12.0, 15.5, 11.2, 40.1, 13.3] {
+//!     stats.push(latency);
+//!     sketch.push(latency);
+//! }
+//! assert_eq!(stats.max(), Some(40.1));
+//! assert_eq!(sketch.quantile(0.5), Some(13.3));
+//! ```
+
+use std::f64::consts::PI;
+
+/// Count, mean, variance, min and max of a stream of values, by Welford's algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RunningStats {
+    count: u64,
+    mean: f64,
+    /// Sum of the squared differences from the mean.
+    m2: f64,
+    min: f64,
+    max: f64,
+}
+
+impl RunningStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a value. NaN is ignored.
+    pub fn push(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        (self.min, self.max) = match self.count {
+            1 => (value, value),
+            _ => (self.min.min(value), self.max.max(value)),
+        };
+    }
+
+    /// Adds the values summarized by `other`, as if they had been pushed here.
+    pub fn merge(&mut self, other: &Self) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.m2 += other.m2 + delta * delta * (self.count as f64 * other.count as f64 / count as f64);
+        self.mean += delta * other.count as f64 / count as f64;
+        self.count = count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// None without values, like all the other statistics.
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.mean)
+    }
+
+    /// Population variance, the square of ping's mdev.
+    pub fn variance(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.m2 / self.count as f64)
+    }
+
+    /// Sample variance, None with fewer than two values.
+    pub fn sample_variance(&self) -> Option<f64> {
+        (self.count > 1).then(|| self.m2 / (self.count - 1) as f64)
+    }
+
+    /// Population standard deviation.
+    pub fn stddev(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max)
+    }
+}
+
+impl Extend<f64> for RunningStats {
+    fn extend<I: IntoIterator<Item = f64>>(&mut self, values: I) {
+        values.into_iter().for_each(|value| self.push(value));
+    }
+}
+
+impl FromIterator<f64> for RunningStats {
+    fn from_iter<I: IntoIterator<Item = f64>>(values: I) -> Self {
+        let mut stats = Self::new();
+        stats.extend(values);
+        stats
+    }
+}
+
+/// Exponentially weighted moving average and variance. Each value weighs `alpha`, the previous average `1 - alpha`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ewma {
+    alpha: f64,
+    mean: Option<f64>,
+    variance: f64,
+}
+
+impl Ewma {
+    /// An average weighing each new value by `alpha`, clamped to 0.001 to 1.
+    pub fn new(alpha: f64) -> Self {
+        let alpha = if alpha.is_nan() { 1.0 } else { alpha.clamp(0.001, 1.0) };
+        Self { alpha, mean: None, variance: 0.0 }
+    }
+
+    /// An average in which a value has lost half of its weight after `samples` newer values.
+    pub fn with_half_life(samples: f64) -> Self {
+        Self::new(1.0 - 0.5_f64.powf(1.0 / samples.max(f64::MIN_POSITIVE)))
+    }
+
+    /// Adds a value and returns the new average. The first value becomes the average. NaN is ignored.
+    pub fn push(&mut self, value: f64) -> Option<f64> {
+        if value.is_nan() {
+            return self.mean;
+        }
+        let mean = match self.mean {
+            None => value,
+            Some(mean) => {
+                let delta = value - mean;
+                let increment = self.alpha * delta;
+                self.variance = (1.0 - self.alpha) * (self.variance + delta * increment);
+                mean + increment
+            },
+        };
+        self.mean = Some(mean);
+        self.mean
+    }
+
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    /// The average, None before the first value.
+    pub fn mean(&self) -> Option<f64> {
+        self.mean
+    }
+
+    /// The exponentially weighted variance around the average.
+    pub fn variance(&self) -> Option<f64> {
+        self.mean.map(|_| self.variance)
+    }
+
+    pub fn stddev(&self) -> Option<f64> {
+        self.variance().map(f64::sqrt)
+    }
+}
+
+/// Estimates quantiles of a stream of values in bounded memory, as a merging t-digest.
+/// Values are kept as centroids that are small near the tails, so p99 stays accurate; small streams are kept exactly.
+/// Sketches of e.g. several files or stations can be merged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantileSketch {
+    compression: f64,
+    /// Merged centroids as (mean, weight), sorted by mean.
+    centroids: Vec<(f64, f64)>,
+    /// Centroids not yet merged.
+    buffer: Vec<(f64, f64)>,
+    count: u64,
+    min: f64,
+    max: f64,
+}
+
+impl Default for QuantileSketch {
+    fn default() -> Self {
+        Self::with_compression(100.0)
+    }
+}
+
+impl QuantileSketch {
+    /// A sketch with a compression of 100, keeping at most a few hundred centroids.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A sketch keeping about `compression` centroids, at least 20. More centroids give more accurate quantiles.
+    pub fn with_compression(compression: f64) -> Self {
+        let compression = if compression.is_nan() { 100.0 } else { compression.max(20.0) };
+        Self { compression, centroids: vec![], buffer: vec![], count: 0, min: f64::INFINITY, max: f64::NEG_INFINITY }
+    }
+
+    /// Adds a value. NaN is ignored.
+    pub fn push(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.buffer.push((value, 1.0));
+        if self.buffer.len() >= 5 * self.compression as usize {
+            self.compress();
+        }
+    }
+
+    /// Adds the values summarized by `other`.
+    pub fn merge(&mut self, other: &Self) {
+        if other.count == 0 {
+            return;
+        }
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.buffer.extend(other.centroids.iter().chain(&other.buffer));
+        self.compress();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// The q-th quantile (0 to 1), interpolating linearly between the closest ranks like `math::percentile`. None without values.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let centroids = match self.buffer.is_empty() {
+            true => self.centroids.clone(),
+            false => merge_centroids(self.centroids.iter().chain(&self.buffer).copied().collect(), self.compression, self.count as f64),
+        };
+        // Each centroid sits at the mean rank of its values; min and max at the first and last rank.
+        let mut points = Vec::with_capacity(centroids.len() + 2);
+        points.push((0.0, self.min));
+        let mut before = 0.0;
+        for (mean, weight) in centroids {
+            points.push((before + (weight - 1.0) / 2.0, mean));
+            before += weight;
+        }
+        points.push((before - 1.0, self.max));
+        let rank = q.clamp(0.0, 1.0) * (self.count - 1) as f64;
+        let upper = points.iter().position(|point| point.0 >= rank).unwrap_or(points.len() - 1);
+        let (rank_1, value_1) = points[upper];
+        let (rank_0, value_0) = points[upper.saturating_sub(1)];
+        if rank_1 <= rank_0 {
+            return Some(value_1);
+        }
+        Some(value_0 + (value_1 - value_0) * (rank - rank_0) / (rank_1 - rank_0))
+    }
+
+    /// Merges the buffered values into the centroids.
+    fn compress(&mut self) {
+        let mut all = std::mem::take(&mut self.centroids);
+        all.append(&mut self.buffer);
+        self.centroids = merge_centroids(all, self.compression, self.count as f64);
+    }
+}
+
+/// Sorts the centroids and merges neighbours as long as a centroid spans at most one unit of the k1 scale function.
+fn merge_centroids(mut centroids: Vec<(f64, f64)>, compression: f64, total: f64) -> Vec<(f64, f64)> {
+    centroids.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let scale = |q: f64| compression / (2.0 * PI) * (2.0 * q - 1.0).asin();
+    let inverse = |k: f64| ((k * 2.0 * PI / compression).clamp(-PI / 2.0, PI / 2.0).sin() + 1.0) / 2.0;
+    let mut merged: Vec<(f64, f64)> = Vec::with_capacity(centroids.len());
+    let mut before = 0.0;
+    let mut limit = inverse(scale(0.0) + 1.0) * total;
+    for (mean, weight) in centroids {
+        match merged.last_mut() {
+            Some(last) if before + last.1 + weight <= limit => {
+                last.1 += weight;
+                last.0 += (mean - last.0) * weight / last.1;
+            },
+            last => {
+                if let Some(last) = last {
+                    before += last.1;
+                    limit = inverse(scale(before / total) + 1.0) * total;
+                }
+                merged.push((mean, weight));
+            },
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::math;
+
+    /// Deterministic values uniform in [0, 1), from a linear congruential generator.
+    fn uniform(count: usize, seed: u64) -> Vec<f64> {
+        let mut state = seed;
+        (0..count).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        }).collect()
+    }
+
+    /// Standard normal values by the Box-Muller transform.
+    fn normal(count: usize, seed: u64) -> Vec<f64> {
+        uniform(2 * count, seed).chunks(2).map(|pair| (-2.0 * (1.0 - pair[0]).ln()).sqrt() * (2.0 * PI * pair[1]).cos()).collect()
+    }
+
+    /// Exponential values with mean 10, like latencies with a long tail.
+    fn exponential(count: usize, seed: u64) -> Vec<f64> {
+        uniform(count, seed).into_iter().map(|value| -10.0 * (1.0 - value).ln()).collect()
+    }
+
+    fn sorted(values: &[f64]) -> Vec<f64> {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        sorted
+    }
+
+    /// Fraction of the sorted values below `value`, to compare quantiles by rank whatever the distribution.
+    fn rank_of(sorted: &[f64], value: f64) -> f64 {
+        sorted.partition_point(|x| *x < value) as f64 / sorted.len() as f64
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64, what: &str) {
+        let actual = actual.unwrap_or_else(|| panic!("{what}: None"));
+        assert!((actual - expected).abs() <= 1e-9 * expected.abs().max(1.0), "{what}: {actual} != {expected}");
+    }
+
+    #[test]
+    fn running_stats_match_the_naive_computation() {
+        for values in [uniform(1000, 1), normal(1000, 2).into_iter().map(|value| 1e6 + value).collect(), vec![3.5], vec![2.0, 2.0, 2.0]] {
+            let stats: RunningStats = values.iter().copied().collect();
+            let n = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / n;
+            let squares = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>();
+            assert_eq!(stats.count(), values.len() as u64);
+            assert_close(stats.mean(), mean, "mean");
+            assert_close(stats.variance(), squares / n, "variance");
+            assert_close(stats.stddev(), (squares / n).sqrt(), "stddev");
+            assert_eq!(stats.sample_variance().is_some(), values.len() > 1);
+            if values.len() > 1 {
+                assert_close(stats.sample_variance(), squares / (n - 1.0), "sample variance");
+            }
+            assert_eq!(stats.min(), values.iter().copied().reduce(f64::min));
+            assert_eq!(stats.max(), values.iter().copied().reduce(f64::max));
+        }
+    }
+
+    #[test]
+    fn running_stats_merge_like_pushing_everything() {
+        let values = exponential(999, 3);
+        let whole: RunningStats = values.iter().copied().collect();
+        let mut merged = RunningStats::new();
+        for chunk in values.chunks(100) {
+            merged.merge(&chunk.iter().copied().collect());
+        }
+        merged.merge(&RunningStats::new());
+        assert_eq!(merged.count(), whole.count());
+        assert_close(merged.mean(), whole.mean().unwrap(), "mean");
+        assert_close(merged.variance(), whole.variance().unwrap(), "variance");
+        assert_eq!((merged.min(), merged.max()), (whole.min(), whole.max()));
+    }
+
+    #[test]
+    fn running_stats_ignore_nan_and_are_empty_without_values() {
+        let stats: RunningStats = [f64::NAN].into_iter().collect();
+        assert_eq!((stats.count(), stats.mean(), stats.variance(), stats.min(), stats.max()), (0, None, None, None, None));
+        let stats: RunningStats = [1.0, f64::NAN, 3.0].into_iter().collect();
+        assert_eq!((stats.count(), stats.mean()), (2, Some(2.0)));
+    }
+
+    #[test]
+    fn ewma_matches_the_weighted_sums() {
+        let values = normal(200, 4);
+        for alpha in [0.05, 0.3, 0.9] {
+            let mut ewma = Ewma::new(alpha);
+            for (n, value) in values.iter().enumerate() {
+                ewma.push(*value);
+                // The first value weighs (1 - alpha)^n, the i-th of the later ones alpha (1 - alpha)^(n - i).
+                let weights: Vec<f64> = (0..=n).map(|i| match i {
+                    0 => (1.0 - alpha).powi(n as i32),
+                    i => alpha * (1.0 - alpha).powi((n - i) as i32),
+                }).collect();
+                let mean: f64 = weights.iter().zip(&values).map(|(weight, value)| weight * value).sum();
+                let variance: f64 = weights.iter().zip(&values).map(|(weight, value)| weight * (value - mean).powi(2)).sum();
+                assert_close(ewma.mean(), mean, "mean");
+                assert_close(ewma.variance(), variance, "variance");
+            }
+        }
+    }
+
+    #[test]
+    fn ewma_edge_cases() {
+        let mut ewma = Ewma::new(1.0);
+        assert_eq!((ewma.mean(), ewma.variance()), (None, None));
+        assert_eq!(ewma.push(5.0), Some(5.0));
+        assert_eq!(ewma.push(f64::NAN), Some(5.0));
+        assert_eq!(ewma.push(7.0), Some(7.0));
+        assert_eq!(ewma.variance(), Some(0.0));
+        assert_eq!(Ewma::new(0.0).alpha(), 0.001);
+        assert_eq!(Ewma::new(f64::NAN).alpha(), 1.0);
+        let half_life = Ewma::with_half_life(10.0);
+        assert!(((1.0 - half_life.alpha()).powi(10) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn sketch_is_exact_on_small_streams() {
+        for count in [1, 2, 5, 50] {
+            let values = exponential(count, 5);
+            let sorted = sorted(&values);
+            let mut sketch = QuantileSketch::new();
+            values.iter().for_each(|value| sketch.push(*value));
+            for step in 0..=20 {
+                let q = step as f64 / 20.0;
+                assert_close(sketch.quantile(q), math::percentile(&sorted, q * 100.0).unwrap(), &format!("q{q} of {count} values"));
+            }
+        }
+        assert_eq!(QuantileSketch::new().quantile(0.5), None);
+    }
+
+    #[test]
+    fn sketch_is_accurate_on_known_distributions() {
+        for (name, values) in [("uniform", uniform(100_000, 6)), ("normal", normal(100_000, 7)), ("exponential", exponential(100_000, 8))] {
+            let sorted = sorted(&values);
+            let mut sketch = QuantileSketch::new();
+            values.iter().for_each(|value| sketch.push(*value));
+            assert_eq!((sketch.count(), sketch.min(), sketch.max()), (100_000, sorted.first().copied(), sorted.last().copied()));
+            // Errors in rank, smaller towards the tails as the t-digest promises.
+            for (q, tolerance) in [(0.001, 0.0005), (0.01, 0.001), (0.1, 0.002), (0.5, 0.005), (0.9, 0.002), (0.99, 0.001), (0.999, 0.0005)] {
+                let rank = rank_of(&sorted, sketch.quantile(q).unwrap());
+                assert!((rank - q).abs() <= tolerance, "{name} q{q}: estimate at rank {rank}");
+            }
+            assert_eq!(sketch.quantile(0.0), sorted.first().copied());
+            assert_eq!(sketch.quantile(1.0), sorted.last().copied());
+        }
+    }
+
+    #[test]
+    fn merged_sketches_are_as_accurate() {
+        let values = exponential(60_000, 9);
+        let sorted = sorted(&values);
+        let mut merged = QuantileSketch::new();
+        for chunk in values.chunks(7_000) {
+            let mut part = QuantileSketch::new();
+            chunk.iter().for_each(|value| part.push(*value));
+            merged.merge(&part);
+        }
+        merged.merge(&QuantileSketch::new());
+        assert_eq!((merged.count(), merged.min(), merged.max()), (60_000, sorted.first().copied(), sorted.last().copied()));
+        for (q, tolerance) in [(0.01, 0.001), (0.5, 0.005), (0.99, 0.001)] {
+            let rank = rank_of(&sorted, merged.quantile(q).unwrap());
+            assert!((rank - q).abs() <= tolerance, "q{q}: estimate at rank {rank}");
+        }
+    }
+}
@@ -1,3 +1,9 @@
 pub mod math;
 pub mod filecontrol;
-pub mod errors;
\ No newline at end of file
+pub mod errors;
+pub mod deadline;
+pub mod config;
+pub mod output;
+pub mod time;
+#[cfg(test)]
+pub(crate) mod stand_in;
\ No newline at end of file
@@ -0,0 +1,235 @@
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::storage::sink::Batch;
+
+/// How commands print their results, set with the global `--output` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Human readable text.
+    #[default]
+    Text,
+    /// A JSON document, or one JSON object per line for commands that keep printing.
+    Json,
+    /// A header line followed by one record per result.
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown output format \"{text}\", use text, json or csv")),
+        }
+    }
+}
+
+/// Prints the results of a command in the chosen format.
+/// Outside of text output, messages go to stderr so stdout only carries the results.
+pub struct Printer {
+    format: OutputFormat,
+    out: RefCell<Box<dyn Write>>,
+    err: RefCell<Box<dyn Write>>,
+    /// Whether the csv header was printed.
+    csv_header: bool,
+}
+
+impl Printer {
+    pub fn new(format: OutputFormat) -> Self {
+        Self::with_writers(format, io::stdout(), io::stderr())
+    }
+
+    /// A printer writing the results to `out` and, outside of text output, the messages to `err`.
+    pub fn with_writers(format: OutputFormat, out: impl Write + 'static, err: impl Write + 'static) -> Self {
+        Self { format, out: RefCell::new(Box::new(out)), err: RefCell::new(Box::new(err)), csv_header: false }
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    /// Prints a progress or problem message. Messages that can't be written are dropped.
+    pub fn message(&self, text: impl Display) {
+        let mut writer = match self.format {
+            OutputFormat::Text => self.out.borrow_mut(),
+            _ => self.err.borrow_mut(),
+        };
+        let _ = writeln!(writer, "{text}").and_then(|()| writer.flush());
+    }
+
+    /// Prints a line of text output that is no result, like the header of a table. Nothing is printed in the other formats.
+    pub fn heading(&mut self, text: impl Display) -> io::Result<()> {
+        if self.format != OutputFormat::Text {
+            return Ok(());
+        }
+        let mut out = self.out.borrow_mut();
+        writeln!(out, "{text}")?;
+        out.flush()
+    }
+
+    /// Prints the results `write` writes at once, e.g. a document already rendered in the format.
+    pub fn write_with(&mut self, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+        let mut out = self.out.borrow_mut();
+        write(&mut *out)?;
+        out.flush()
+    }
+
+    /// Prints a single result: its Display form, one JSON object on a line of its own, or a csv record.
+    /// The csv header is written before the first record.
+    pub fn record<T: Serialize + Display>(&mut self, value: &T) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text => {
+                let mut out = self.out.borrow_mut();
+                writeln!(out, "{value}")?;
+                out.flush()
+            },
+            OutputFormat::Json => self.json_line(value),
+            OutputFormat::Csv => {
+                let mut wtr = csv::WriterBuilder::new().has_headers(!self.csv_header).from_writer(vec![]);
+                wtr.serialize(value).map_err(io::Error::other)?;
+                let line = wtr.into_inner().map_err(|error| io::Error::other(error.to_string()))?;
+                self.csv_header = true;
+                let mut out = self.out.borrow_mut();
+                out.write_all(&line)?;
+                out.flush()
+            },
+        }
+    }
+
+    /// Prints a gather cycle: with JSON output every sample and state change as a line of its own,
+    /// with csv output every sample, with text output only the state changes.
+    pub fn batch(&mut self, batch: &Batch) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Json => batch.records().try_for_each(|record| self.json_line(&record)),
+            OutputFormat::Csv => {
+                batch.events.iter().for_each(|event| self.message(event));
+                batch.rows.iter().try_for_each(|row| self.record(row))
+            },
+            OutputFormat::Text => {
+                batch.events.iter().for_each(|event| self.message(event));
+                Ok(())
+            },
+        }
+    }
+
+    /// Prints a value as one line of JSON, whatever the format.
+    pub fn json_line<T: Serialize>(&self, value: &T) -> io::Result<()> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        let mut out = self.out.borrow_mut();
+        out.write_all(&line)?;
+        out.flush()
+    }
+}
+
+/// A writer whose output a test can read afterwards.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct Captured(std::rc::Rc<RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Captured {
+    /// A printer of the format with the results and messages it prints.
+    pub(crate) fn printer(format: OutputFormat) -> (Printer, Captured, Captured) {
+        let (out, err) = (Captured::default(), Captured::default());
+        (Printer::with_writers(format, out.clone(), err.clone()), out, err)
+    }
+
+    pub(crate) fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Write for Captured {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::DateTime;
+
+    use crate::stations::health::{HealthState, StateEvent};
+    use crate::stations::station::DataRow;
+
+    fn batch() -> Batch {
+        let time = DateTime::parse_from_rfc3339("2024-10-27T12:00:00+00:00").unwrap();
+        let event = StateEvent {
+            time,
+            station_no: 2,
+            from: HealthState::Up,
+            to: HealthState::Down,
+            duration: std::time::Duration::from_secs(60),
+            reason: "no probe answered".into(),
+        };
+        Batch::new(vec![DataRow::sample(1, time, 12.5), DataRow::sample(2, time, 13.5)], vec![event])
+    }
+
+    #[test]
+    fn json_prints_one_object_per_line_and_messages_on_stderr() {
+        let (mut printer, out, err) = Captured::printer(OutputFormat::Json);
+        printer.message("Sweeping 254 addresses.");
+        printer.batch(&batch()).unwrap();
+        printer.record(&DataRow::sample(3, DateTime::parse_from_rfc3339("2024-10-27T12:01:00+00:00").unwrap(), 14.5)).unwrap();
+        assert_eq!(err.text(), "Sweeping 254 addresses.\n");
+        let lines: Vec<serde_json::Value> = out.text().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let types: Vec<&str> = lines.iter().map(|line| line["type"].as_str().unwrap_or("")).collect();
+        assert_eq!(types, ["sample", "sample", "state_event", ""]);
+        assert_eq!(lines[1]["Station No"], 2);
+        assert_eq!(lines[2]["To"], "down");
+        assert_eq!(lines[3]["Latency (ms)"], 14.5);
+    }
+
+    #[test]
+    fn csv_prints_the_header_once_and_messages_on_stderr() {
+        let (mut printer, out, err) = Captured::printer(OutputFormat::Csv);
+        printer.batch(&batch()).unwrap();
+        printer.batch(&Batch::new(batch().rows, vec![])).unwrap();
+        assert!(err.text().contains("Station 2 changed from up to down"), "{}", err.text());
+        let text = out.text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("Time,Epoch,Station No,Latency (ms),Latency Status,"), "{}", lines[0]);
+        assert!(lines[1].starts_with("2024-10-27T12:00:00+00:00,1730030400,1,12.5,ok,"), "{}", lines[1]);
+        assert_eq!(text.matches("Station No").count(), 1);
+    }
+
+    #[test]
+    fn text_prints_results_and_messages_on_stdout() {
+        let (mut printer, out, err) = Captured::printer(OutputFormat::Text);
+        printer.message("Logging cancelled.");
+        printer.batch(&batch()).unwrap();
+        printer.record(&DataRow::sample(3, DateTime::parse_from_rfc3339("2024-10-27T12:01:00+00:00").unwrap(), 14.5)).unwrap();
+        assert_eq!(err.text(), "");
+        let text = out.text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "Logging cancelled.");
+        assert!(lines[1].contains("Station 2 changed from up to down"));
+        assert!(lines[2].starts_with("Time: 2024-10-27T12:01:00+00:00, Station: 3, Latency: 14.5 ms"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn parses_the_format_names() {
+        assert_eq!("json".parse::<OutputFormat>(), Ok(OutputFormat::Json));
+        assert_eq!("csv".parse::<OutputFormat>(), Ok(OutputFormat::Csv));
+        assert_eq!("text".parse::<OutputFormat>(), Ok(OutputFormat::Text));
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+}
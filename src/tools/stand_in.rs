@@ -0,0 +1,266 @@
+//! Local stand-ins for the HTTP, SMTP and MQTT servers the notifiers and sinks talk to, used by the tests.
+//! Each listens on a free port of 127.0.0.1 and answers by a script, handing what it received to the test.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// A request received by the HTTP stand-in.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    /// Path with the query.
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpRequest {
+    /// The value of a header, matching its name case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+/// A running stand-in and what it received so far.
+pub struct StandIn<T> {
+    pub address: SocketAddr,
+    received: Receiver<T>,
+}
+
+impl<T> StandIn<T> {
+    /// What the stand-in received, in order. Since the stand-in hands a request over before answering it,
+    /// everything a client got an answer for is included.
+    pub fn received(&self) -> Vec<T> {
+        self.received.try_iter().collect()
+    }
+
+    /// The next thing received, waiting up to 5 s for it.
+    pub fn wait_next(&self) -> Option<T> {
+        self.received.recv_timeout(Duration::from_secs(5)).ok()
+    }
+}
+
+impl StandIn<HttpRequest> {
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.address)
+    }
+}
+
+/// An address nothing listens on, so connecting to it is refused.
+pub fn refused_address() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+fn spawn<T: Send + 'static>(listener: TcpListener, serve: impl FnOnce(TcpListener, Sender<T>) + Send + 'static) -> StandIn<T> {
+    let address = listener.local_addr().unwrap();
+    let (sender, received) = mpsc::channel();
+    thread::spawn(move || serve(listener, sender));
+    StandIn { address, received }
+}
+
+/// An HTTP server answering the n-th request with the n-th status, and closing once the statuses are used up.
+pub fn http(statuses: Vec<u16>) -> StandIn<HttpRequest> {
+    http_on(TcpListener::bind("127.0.0.1:0").unwrap(), statuses)
+}
+
+/// Like `http`, on a given listener, e.g. one bound to an address that refused connections before.
+pub fn http_on(listener: TcpListener, statuses: Vec<u16>) -> StandIn<HttpRequest> {
+    spawn(listener, move |listener, sender| {
+        for status in statuses {
+            let Ok((stream, _)) = listener.accept() else {
+                return;
+            };
+            let Some(request) = read_request(&stream) else {
+                continue;
+            };
+            let _ = sender.send(request);
+            let mut stream = stream;
+            let _ = write!(stream, "HTTP/1.1 {status} Stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        }
+    })
+}
+
+fn read_request(stream: &TcpStream) -> Option<HttpRequest> {
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = (parts.next()?.to_string(), parts.next()?.to_string());
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    let mut request = HttpRequest { method, target, headers, body: String::new() };
+    let length: usize = request.header("Content-Length").and_then(|length| length.parse().ok()).unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    request.body = String::from_utf8_lossy(&body).into_owned();
+    Some(request)
+}
+
+/// An SMTP server refusing the first `refuse` sessions with a temporary 451 at MAIL FROM and accepting the mails of the later ones.
+/// Hands over the DATA of every accepted mail.
+pub fn smtp(refuse: usize) -> StandIn<String> {
+    spawn(TcpListener::bind("127.0.0.1:0").unwrap(), move |listener, sender| {
+        for session in 0.. {
+            let Ok((stream, _)) = listener.accept() else {
+                return;
+            };
+            let _ = serve_smtp(stream, session < refuse, &sender);
+        }
+    })
+}
+
+fn serve_smtp(stream: TcpStream, refuse: bool, sender: &Sender<String>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    writer.write_all(b"220 stand-in ESMTP\r\n")?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let command = line.trim_end().to_ascii_uppercase();
+        if command.starts_with("EHLO") || command.starts_with("HELO") {
+            writer.write_all(b"250 stand-in\r\n")?;
+        } else if command.starts_with("MAIL FROM") && refuse {
+            writer.write_all(b"451 try again later\r\n")?;
+        } else if command.starts_with("MAIL FROM") || command.starts_with("RCPT TO") || command.starts_with("RSET") || command.starts_with("NOOP") {
+            writer.write_all(b"250 OK\r\n")?;
+        } else if command == "DATA" {
+            writer.write_all(b"354 go ahead\r\n")?;
+            let mut data = String::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line)? == 0 || line == ".\r\n" {
+                    break;
+                }
+                data.push_str(&line);
+            }
+            let _ = sender.send(data);
+            writer.write_all(b"250 queued\r\n")?;
+        } else if command == "QUIT" {
+            writer.write_all(b"221 bye\r\n")?;
+            return Ok(());
+        } else {
+            writer.write_all(b"502 not implemented\r\n")?;
+        }
+    }
+}
+
+/// A message received by the MQTT stand-in, or the Last Will it published for a client that went away without disconnecting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: String,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+/// An MQTT 3.1.1 broker for one client at a time. It acknowledges connections and publications, answers pings
+/// and hands over every publication. The first session is closed by the broker after `close_after` publications, if set.
+pub fn mqtt(close_after: Option<usize>) -> StandIn<MqttMessage> {
+    spawn(TcpListener::bind("127.0.0.1:0").unwrap(), move |listener, sender| {
+        for session in 0.. {
+            let Ok((stream, _)) = listener.accept() else {
+                return;
+            };
+            let limit = if session == 0 { close_after } else { None };
+            let _ = serve_mqtt(stream, limit, &sender);
+        }
+    })
+}
+
+fn serve_mqtt(mut stream: TcpStream, close_after: Option<usize>, sender: &Sender<MqttMessage>) -> std::io::Result<()> {
+    let mut will = None;
+    let mut published = 0;
+    while let Some((header, body)) = read_mqtt_packet(&mut stream)? {
+        match header >> 4 {
+            // CONNECT
+            1 => {
+                will = mqtt_will(&body);
+                stream.write_all(&[0x20, 0x02, 0x00, 0x00])?;
+            },
+            // PUBLISH
+            3 => {
+                let qos = (header >> 1) & 0x03;
+                let (topic, rest) = mqtt_string(&body).ok_or(std::io::ErrorKind::InvalidData)?;
+                let (id, payload) = if qos > 0 { (&rest[..2], &rest[2..]) } else { (&[][..], rest) };
+                let _ = sender.send(MqttMessage { topic, payload: String::from_utf8_lossy(payload).into_owned(), qos, retain: header & 0x01 == 1 });
+                match qos {
+                    1 => stream.write_all(&[0x40, 0x02, id[0], id[1]])?,
+                    2 => stream.write_all(&[0x50, 0x02, id[0], id[1]])?,
+                    _ => {},
+                }
+                published += 1;
+                if close_after.is_some_and(|limit| published >= limit) {
+                    break;
+                }
+            },
+            // PUBREL
+            6 => stream.write_all(&[0x70, 0x02, body[0], body[1]])?,
+            // PINGREQ
+            12 => stream.write_all(&[0xd0, 0x00])?,
+            // DISCONNECT
+            14 => return Ok(()),
+            _ => {},
+        }
+    }
+    if let Some(will) = will {
+        let _ = sender.send(will);
+    }
+    Ok(())
+}
+
+/// Reads the fixed header byte and the rest of the next packet, None once the client closed the connection.
+fn read_mqtt_packet(stream: &mut TcpStream) -> std::io::Result<Option<(u8, Vec<u8>)>> {
+    let mut header = [0u8; 1];
+    if stream.read(&mut header)? == 0 {
+        return Ok(None);
+    }
+    let mut length = 0usize;
+    for shift in (0..28).step_by(7) {
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte)?;
+        length |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    Ok(Some((header[0], body)))
+}
+
+/// A string prefixed with its length, and what follows it.
+fn mqtt_string(bytes: &[u8]) -> Option<(String, &[u8])> {
+    let length = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]) as usize;
+    let text = bytes.get(2..2 + length)?;
+    Some((String::from_utf8_lossy(text).into_owned(), &bytes[2 + length..]))
+}
+
+/// The Last Will of a CONNECT packet.
+fn mqtt_will(body: &[u8]) -> Option<MqttMessage> {
+    let (_protocol, rest) = mqtt_string(body)?;
+    let (flags, rest) = (*rest.get(1)?, rest.get(4..)?);
+    if flags & 0x04 == 0 {
+        return None;
+    }
+    let (_client_id, rest) = mqtt_string(rest)?;
+    let (topic, rest) = mqtt_string(rest)?;
+    let (payload, _) = mqtt_string(rest)?;
+    Some(MqttMessage { topic, payload, qos: (flags >> 3) & 0x03, retain: flags & 0x20 != 0 })
+}
@@ -0,0 +1,170 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, SecondsFormat, TimeDelta, TimeZone, Utc};
+use serde::Deserialize;
+
+/// Time zone timestamps are written in, set with `zone` in the [time] section of the config file.
+/// Timestamps are always taken as absolute instants and converted afterwards, so the offset written next to
+/// a local time tells repeated hours at the end of daylight saving time apart and no hour is skipped or doubled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeZoneSetting {
+    #[default]
+    Local,
+    Utc,
+}
+
+impl TimeZoneSetting {
+    pub fn convert(&self, time: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            TimeZoneSetting::Local => time.with_timezone(&Local).fixed_offset(),
+            TimeZoneSetting::Utc => time.fixed_offset(),
+        }
+    }
+
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        self.convert(Utc::now())
+    }
+
+    /// The instant a wall clock time in this zone stands for, the earlier one if the time is repeated.
+    /// A time skipped at the start of daylight saving time is read with the offset from before the skip,
+    /// so "02:30" on a day the clocks jump from 02:00 to 03:00 stands for 03:30.
+    pub fn from_naive(&self, time: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        match self {
+            TimeZoneSetting::Local => resolve_local(&Local, time),
+            TimeZoneSetting::Utc => Some(Utc.from_utc_datetime(&time).fixed_offset()),
+        }
+    }
+}
+
+/// Resolves a wall clock time of `zone` like `TimeZoneSetting::from_naive`.
+fn resolve_local<Tz: TimeZone>(zone: &Tz, time: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    if let Some(resolved) = zone.from_local_datetime(&time).earliest() {
+        return Some(resolved.fixed_offset());
+    }
+    // In a gap. A day earlier the offset from before the transition still applies.
+    let before = zone.from_local_datetime(&(time - TimeDelta::days(1))).earliest()?.offset().fix();
+    let resolved = time.checked_sub_offset(before)?.and_utc();
+    Some(resolved.with_timezone(zone).fixed_offset())
+}
+
+/// Parses a bound of a time range given on the command line: RFC 3339, or "YYYY-MM-DDTHH:MM[:SS]" and "YYYY-MM-DD" in `zone`.
+/// A bare day stands for its start, or for the start of the next day if `end` is set, so "--to 2024-10-27" includes that day.
+pub fn parse_bound(text: &str, end: bool, zone: TimeZoneSetting) -> Option<DateTime<FixedOffset>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time);
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(text, format) {
+            return zone.from_naive(time);
+        }
+    }
+    let day = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+    let day = if end { day.succ_opt()? } else { day };
+    zone.from_naive(day.and_hms_opt(0, 0, 0)?)
+}
+
+/// Formats a timestamp as RFC 3339 with whole seconds and an explicit offset, e.g. "2024-10-27T02:30:00+01:00".
+pub fn rfc3339(time: &DateTime<FixedOffset>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+pub fn serialize_rfc3339<S: serde::Serializer>(time: &DateTime<FixedOffset>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&rfc3339(time))
+}
+
+pub fn serialize_rfc3339_opt<S: serde::Serializer>(time: &Option<DateTime<FixedOffset>>, serializer: S) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(time) => serializer.serialize_str(&rfc3339(time)),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::LocalResult;
+
+    /// Central European time of 2025: +01:00, and +02:00 from 2025-03-30T01:00Z to 2025-10-26T01:00Z.
+    #[derive(Debug, Clone, Copy)]
+    struct Cet;
+
+    impl Cet {
+        fn summer_start() -> NaiveDateTime {
+            NaiveDate::from_ymd_opt(2025, 3, 30).unwrap().and_hms_opt(1, 0, 0).unwrap()
+        }
+
+        fn summer_end() -> NaiveDateTime {
+            NaiveDate::from_ymd_opt(2025, 10, 26).unwrap().and_hms_opt(1, 0, 0).unwrap()
+        }
+
+        fn offset(hours: i32) -> FixedOffset {
+            FixedOffset::east_opt(hours * 3600).unwrap()
+        }
+    }
+
+    impl TimeZone for Cet {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Cet
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let candidates: Vec<FixedOffset> = [Cet::offset(1), Cet::offset(2)].into_iter()
+                .filter(|offset| self.offset_from_utc_datetime(&(*local - *offset)) == *offset)
+                .collect();
+            match candidates[..] {
+                [] => LocalResult::None,
+                [offset] => LocalResult::Single(offset),
+                [winter, summer] => LocalResult::Ambiguous(summer, winter),
+                _ => unreachable!(),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            match *utc >= Cet::summer_start() && *utc < Cet::summer_end() {
+                true => Cet::offset(2),
+                false => Cet::offset(1),
+            }
+        }
+    }
+
+    fn local(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M").unwrap()
+    }
+
+    #[test]
+    fn reads_a_time_in_the_spring_gap_with_the_offset_from_before() {
+        let resolved = resolve_local(&Cet, local("2025-03-30T02:30")).unwrap();
+        assert_eq!(rfc3339(&resolved), "2025-03-30T03:30:00+02:00");
+        // The first time after the gap is unaffected.
+        assert_eq!(rfc3339(&resolve_local(&Cet, local("2025-03-30T03:00")).unwrap()), "2025-03-30T03:00:00+02:00");
+        assert_eq!(rfc3339(&resolve_local(&Cet, local("2025-03-30T01:59")).unwrap()), "2025-03-30T01:59:00+01:00");
+    }
+
+    #[test]
+    fn takes_the_earlier_instant_of_a_repeated_time() {
+        let resolved = resolve_local(&Cet, local("2025-10-26T02:30")).unwrap();
+        assert_eq!(rfc3339(&resolved), "2025-10-26T02:30:00+02:00");
+        assert_eq!(rfc3339(&resolve_local(&Cet, local("2025-10-26T03:00")).unwrap()), "2025-10-26T03:00:00+01:00");
+    }
+
+    #[test]
+    fn parses_bounds_in_utc() {
+        let zone = TimeZoneSetting::Utc;
+        let bound = |text, end| parse_bound(text, end, zone).map(|time| rfc3339(&time));
+        assert_eq!(bound("2024-10-27T02:30:00+01:00", false).as_deref(), Some("2024-10-27T02:30:00+01:00"));
+        assert_eq!(bound("2024-10-27T02:30", false).as_deref(), Some("2024-10-27T02:30:00+00:00"));
+        assert_eq!(bound("2024-10-27 02:30:15", false).as_deref(), Some("2024-10-27T02:30:15+00:00"));
+        assert_eq!(bound("2024-10-27", false).as_deref(), Some("2024-10-27T00:00:00+00:00"));
+        assert_eq!(bound("2024-10-27", true).as_deref(), Some("2024-10-28T00:00:00+00:00"));
+        assert_eq!(bound("yesterday", false), None);
+    }
+}